@@ -0,0 +1,21 @@
+//! Sets the `nightly` cfg when the compiler is a nightly one, so unstable features such as
+//! `memory::alloc_error_handler` are only used where they exist and stable CI keeps building.
+
+use std::env;
+use std::process::Command;
+
+fn main() {
+    println!("cargo::rustc-check-cfg=cfg(nightly)");
+    println!("cargo::rerun-if-env-changed=RUSTC");
+
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
+    let version = Command::new(rustc)
+        .arg("--version")
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+        .unwrap_or_default();
+
+    if version.contains("-nightly") || version.contains("-dev") {
+        println!("cargo::rustc-cfg=nightly");
+    }
+}
@@ -0,0 +1,71 @@
+#!/usr/bin/env python3
+"""Fills the `.ksyms` section of a kernel built with `--features ksyms`.
+
+Usage: scripts/ksyms.py target/riscv64gc-unknown-none-elf/debug/auton
+
+Reads the function symbols with `nm`, encodes them in the layout documented in
+`src/symbols.rs` and writes them into the image with `objcopy`. Set `NM` and `OBJCOPY`
+to pick the tools, defaults are the LLVM ones shipped with `cargo-binutils`.
+"""
+
+import os
+import struct
+import subprocess
+import sys
+import tempfile
+
+CAPACITY = 256 * 1024  # `KSYMS_CAPACITY` in src/symbols.rs
+HEADER = struct.Struct("<4sI")
+ENTRY = struct.Struct("<QQII")
+
+
+def function_symbols(nm, elf):
+    output = subprocess.run(
+        [nm, "--defined-only", "--print-size", "--numeric-sort", "--demangle", elf],
+        check=True,
+        capture_output=True,
+        text=True,
+    ).stdout
+
+    symbols = {}
+    for line in output.splitlines():
+        parts = line.split(maxsplit=3)
+        if len(parts) != 4 or parts[2] not in ("t", "T"):
+            continue
+        start, size = int(parts[0], 16), int(parts[1], 16)
+        if size > 0:
+            symbols[start] = (size, parts[3])
+    return sorted(symbols.items())
+
+
+def encode(symbols):
+    entries, names = bytearray(), bytearray()
+    for start, (size, name) in symbols:
+        encoded = name.encode()
+        entries += ENTRY.pack(start, size, len(names), len(encoded))
+        names += encoded
+    return HEADER.pack(b"KSYM", len(symbols)) + entries + names
+
+
+def main():
+    if len(sys.argv) != 2:
+        sys.exit(__doc__)
+    elf = sys.argv[1]
+
+    table = encode(function_symbols(os.environ.get("NM", "llvm-nm"), elf))
+    if len(table) > CAPACITY:
+        sys.exit(f"symbol table is {len(table)} bytes, only {CAPACITY} are reserved")
+
+    with tempfile.NamedTemporaryFile() as blob:
+        blob.write(table.ljust(CAPACITY, b"\0"))
+        blob.flush()
+        subprocess.run(
+            [os.environ.get("OBJCOPY", "llvm-objcopy"), "--update-section", f".ksyms={blob.name}", elf],
+            check=True,
+        )
+
+    print(f"{elf}: {len(table)} of {CAPACITY} .ksyms bytes used")
+
+
+if __name__ == "__main__":
+    main()
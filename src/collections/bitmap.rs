@@ -0,0 +1,180 @@
+const WORD_BITS: usize = u64::BITS as usize;
+
+/// A fixed-size set of bits, spread over `WORDS` 64-bit words.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct Bitmap<const WORDS: usize>([u64; WORDS]);
+
+impl<const WORDS: usize> Bitmap<WORDS> {
+    /// number of bits the bitmap holds
+    pub const BITS: usize = WORDS * WORD_BITS;
+
+    #[inline]
+    pub const fn new() -> Self {
+        Self([0; WORDS])
+    }
+
+    /// sets the given bit
+    #[inline]
+    pub const fn set(&mut self, bit: usize) {
+        self.0[bit / WORD_BITS] |= 1 << (bit % WORD_BITS);
+    }
+
+    /// clears the given bit
+    #[inline]
+    pub const fn clear(&mut self, bit: usize) {
+        self.0[bit / WORD_BITS] &= !(1 << (bit % WORD_BITS));
+    }
+
+    /// returns `true` if the given bit is set
+    #[inline]
+    pub const fn is_set(&self, bit: usize) -> bool {
+        self.0[bit / WORD_BITS] & (1 << (bit % WORD_BITS)) != 0
+    }
+
+    /// raw word `index`, bit `i` of it is bit `index * 64 + i` of the bitmap
+    #[inline]
+    pub const fn word(&self, index: usize) -> u64 {
+        self.0[index]
+    }
+
+    /// number of set bits
+    #[inline]
+    pub const fn count_ones(&self) -> usize {
+        let mut count = 0;
+        let mut word = 0;
+        while word < WORDS {
+            count += self.0[word].count_ones() as usize;
+            word += 1;
+        }
+        count
+    }
+
+    /// finds the lowest set bit
+    #[inline]
+    pub const fn find_first_set(&self) -> Option<usize> {
+        self.find_first_set_from(0)
+    }
+
+    /// finds the lowest clear bit
+    #[inline]
+    pub const fn find_first_clear(&self) -> Option<usize> {
+        self.find_first_clear_from(0)
+    }
+
+    /// finds the first set bit greater than or equal to `from`
+    #[inline]
+    pub const fn find_first_set_from(&self, from: usize) -> Option<usize> {
+        let mut word = from / WORD_BITS;
+
+        if word >= WORDS {
+            return None;
+        }
+
+        // create a mask to ignore bits below `from` in the first word
+        let mut candidates = self.0[word] & !((1 << (from % WORD_BITS)) - 1);
+
+        loop {
+            if candidates != 0 {
+                // return the lowest candidate
+                return Some(word * WORD_BITS + candidates.trailing_zeros() as usize);
+            }
+
+            word += 1;
+            if word == WORDS {
+                return None;
+            }
+            candidates = self.0[word];
+        }
+    }
+
+    /// finds the first clear bit greater than or equal to `from`
+    #[inline]
+    pub const fn find_first_clear_from(&self, from: usize) -> Option<usize> {
+        let mut inverted = Self::new();
+        let mut word = 0;
+        while word < WORDS {
+            inverted.0[word] = !self.0[word];
+            word += 1;
+        }
+
+        inverted.find_first_set_from(from)
+    }
+
+    /// finds the highest set bit
+    #[inline]
+    pub const fn find_last_set(&self) -> Option<usize> {
+        let mut word = WORDS;
+
+        while word > 0 {
+            word -= 1;
+            if self.0[word] != 0 {
+                let bit = (u64::BITS - 1 - self.0[word].leading_zeros()) as usize;
+                return Some(word * WORD_BITS + bit);
+            }
+        }
+
+        None
+    }
+}
+
+impl<const WORDS: usize> Default for Bitmap<WORDS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// searches must carry on into the next word and stop after the last one
+const _: () = {
+    let mut bitmap = Bitmap::<2>::new();
+    bitmap.set(3);
+    bitmap.set(70);
+
+    assert!(matches!(bitmap.find_first_set_from(0), Some(3)));
+    assert!(matches!(bitmap.find_first_set_from(4), Some(70)));
+    assert!(matches!(bitmap.find_first_set_from(64), Some(70)));
+    assert!(bitmap.find_first_set_from(71).is_none());
+    assert!(bitmap.find_first_set_from(128).is_none());
+    assert!(matches!(bitmap.find_last_set(), Some(70)));
+
+    bitmap.clear(70);
+    assert!(!bitmap.is_set(70));
+    assert!(bitmap.find_first_set_from(4).is_none());
+    assert!(matches!(bitmap.find_last_set(), Some(3)));
+
+    assert!(matches!(bitmap.find_first_clear_from(3), Some(4)));
+
+    let mut full = Bitmap::<2>::new();
+    let mut bit = 0;
+    while bit < Bitmap::<2>::BITS {
+        full.set(bit);
+        bit += 1;
+    }
+    assert!(full.find_first_clear_from(0).is_none());
+    full.clear(100);
+    assert!(matches!(full.find_first_clear_from(0), Some(100)));
+    assert!(matches!(full.find_first_clear(), Some(100)));
+    assert!(full.count_ones() == Bitmap::<2>::BITS - 1);
+};
+
+// bits on either side of a word boundary belong to different words
+const _: () = {
+    let mut bitmap = Bitmap::<3>::new();
+    assert!(bitmap.find_first_set().is_none());
+    assert!(matches!(bitmap.find_first_clear(), Some(0)));
+
+    bitmap.set(63);
+    bitmap.set(64);
+    bitmap.set(128);
+    assert!(bitmap.count_ones() == 3);
+    assert!(matches!(bitmap.find_first_set(), Some(63)));
+
+    bitmap.clear(63);
+    assert!(bitmap.is_set(64), "Clearing bit 63 touched the next word");
+    assert!(matches!(bitmap.find_first_set(), Some(64)));
+
+    bitmap.clear(64);
+    assert!(bitmap.word(1) == 0 && bitmap.word(2) == 1);
+    assert!(matches!(bitmap.find_first_set(), Some(128)));
+    assert!(bitmap.count_ones() == 1);
+};
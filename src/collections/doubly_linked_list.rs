@@ -86,6 +86,20 @@ impl<T: DoublyLinkable> DoublyLinkedList<T> {
         self.tail.map(|mut node| unsafe { node.as_mut() })
     }
 
+    /// Returns `true` if `node` is linked into this list.
+    ///
+    /// This is an O(n) walk meant for debugging list membership.
+    pub fn contains(&self, node: NonNull<T>) -> bool {
+        self.position(node).is_some()
+    }
+
+    /// Returns the index of `node` counting from the head, or `None` if it is not in this list.
+    ///
+    /// This is an O(n) walk meant for debugging list membership.
+    pub fn position(&self, node: NonNull<T>) -> Option<usize> {
+        self.iter().position(|current| current == node)
+    }
+
     /// Adds an element to the front of the list.
     ///
     /// # Panics
@@ -222,6 +236,11 @@ impl<T: DoublyLinkable> DoublyLinkedList<T> {
     /// Passing a node that belongs to a different list, a detached node, or an
     /// otherwise invalid pointer will lead to memory corruption or undefined behavior.
     pub fn remove(&mut self, mut node: NonNull<T>) -> NonNull<T> {
+        debug_assert!(
+            self.contains(node),
+            "Attempted to remove a node that is not part of this list"
+        );
+
         let node_ref = unsafe { node.as_mut() };
 
         #[cfg(debug_assertions)]
@@ -267,6 +286,39 @@ impl<T: DoublyLinkable> DoublyLinkedList<T> {
         node
     }
 
+    /// Moves every element of `other` to the back of this list in O(1), leaving `other` empty.
+    pub fn append(&mut self, other: &mut DoublyLinkedList<T>) {
+        let Some(mut other_head) = other.head.take() else {
+            return;
+        };
+        let other_tail = other.tail.take();
+
+        match self.tail {
+            Some(mut tail) => {
+                // SAFETY: Both pointers are valid, `&mut` on both lists ensures exclusive access.
+                unsafe {
+                    tail.as_mut().set_next(Some(other_head));
+                    other_head.as_mut().set_prev(Some(tail));
+                }
+            }
+            None => self.head = Some(other_head),
+        }
+        self.tail = other_tail;
+
+        self.len += other.len;
+        other.len = 0;
+    }
+
+    /// Returns an iterator over the raw node pointers, from head to tail.
+    ///
+    /// The list must not be modified while the iterator is alive.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            current: self.head,
+            phantom: PhantomData,
+        }
+    }
+
     /// Returns a `CursorMut` that points to the first element of the list.
     pub fn cursor_mut<'a>(&'a mut self) -> CursorMut<'a, T> {
         CursorMut {
@@ -283,6 +335,24 @@ impl<T: DoublyLinkable> Default for DoublyLinkedList<T> {
     }
 }
 
+/// An iterator over the node pointers of a `DoublyLinkedList`, see `DoublyLinkedList::iter`.
+pub struct Iter<'a, T: DoublyLinkable> {
+    current: Option<NonNull<T>>,
+    phantom: PhantomData<&'a T>,
+}
+
+impl<T: DoublyLinkable> Iterator for Iter<'_, T> {
+    type Item = NonNull<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.current?;
+        // SAFETY: `node` is a valid pointer from the list's internal links,
+        // and the list is borrowed for the iterator's lifetime.
+        self.current = unsafe { node.as_ref().next() };
+        Some(node)
+    }
+}
+
 /// A cursor with mutable access to an `DoublyLinkedList`.
 ///
 /// A `CursorMut` allows for navigation and manipulation of the list.
@@ -457,7 +527,31 @@ impl<'a, T: DoublyLinkable> CursorMut<'a, T> {
     /// Returns a new `DoublyLinkedList` containing all elements after the current one.
     /// The current element becomes the new tail of the original list.
     /// If the cursor is at the tail, an empty list is returned.
+    ///
+    /// Walks the moved tail to keep both `len`s exact, so this is O(n).
+    /// Use `split_after_counted` when the number of moved nodes is already known.
     pub fn split_after(&mut self) -> DoublyLinkedList<T> {
+        let moved_len = self.count_after();
+        self.split_after_counted(moved_len)
+    }
+
+    /// O(1) variant of `split_after` for callers that already know how many nodes follow
+    /// the current element.
+    ///
+    /// Trades the walk for trusting the caller: a wrong `moved_len` silently corrupts
+    /// the `len` of both lists in release builds.
+    ///
+    /// # Panics
+    ///
+    /// Panics in debug builds if `moved_len` does not match the number of nodes
+    /// after the current element.
+    pub fn split_after_counted(&mut self, moved_len: usize) -> DoublyLinkedList<T> {
+        debug_assert_eq!(
+            moved_len,
+            self.count_after(),
+            "split_after_counted() called with a wrong moved length"
+        );
+
         let Some(mut current_ptr) = self.current else {
             return DoublyLinkedList::new();
         };
@@ -478,25 +572,81 @@ impl<'a, T: DoublyLinkable> CursorMut<'a, T> {
             list.tail = Some(current_ptr);
         }
 
-        // Count moved nodes to update lengths correctly.
-        let mut moved_nodes_count = 0;
-        let mut temp_node = Some(new_head_ptr);
+        list.len -= moved_len;
+
+        DoublyLinkedList {
+            head: Some(new_head_ptr),
+            tail: old_tail,
+            len: moved_len,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Counts the nodes after the current element, `0` if the cursor is dangling.
+    fn count_after(&self) -> usize {
+        let mut count = 0;
+        let mut temp_node = self.current().and_then(|node| node.next());
         while let Some(node) = temp_node {
-            moved_nodes_count += 1;
+            count += 1;
             // SAFETY: `node` is valid within this loop.
             temp_node = unsafe { node.as_ref().next() };
         }
+        count
+    }
+
+    /// Splits the list into two before the current element.
+    ///
+    /// Returns a new `DoublyLinkedList` containing all elements before the current one.
+    /// The current element becomes the new head of the original list and the cursor keeps
+    /// pointing at it. If the cursor is at the head, an empty list is returned; if it is
+    /// dangling, the whole list is moved out.
+    ///
+    /// Walks the moved head to keep both `len`s exact, so this is O(n).
+    pub fn split_before(&mut self) -> DoublyLinkedList<T> {
+        // SAFETY: `self.list` is a valid pointer.
+        let list = unsafe { self.list.as_mut() };
 
-        list.len -= moved_nodes_count;
+        let Some(mut current_ptr) = self.current else {
+            return core::mem::take(list);
+        };
+
+        // SAFETY: `current_ptr` is valid.
+        let Some(mut new_tail_ptr) = (unsafe { current_ptr.as_ref().prev() }) else {
+            return DoublyLinkedList::new();
+        };
+
+        let moved_len = self.count_before();
+        let old_head = list.head;
+
+        // SAFETY: Pointers are valid. We are severing the list.
+        unsafe {
+            new_tail_ptr.as_mut().set_next(None);
+            current_ptr.as_mut().set_prev(None);
+            list.head = Some(current_ptr);
+        }
+
+        list.len -= moved_len;
 
         DoublyLinkedList {
-            head: Some(new_head_ptr),
-            tail: old_tail,
-            len: moved_nodes_count,
+            head: old_head,
+            tail: Some(new_tail_ptr),
+            len: moved_len,
             phantom: PhantomData,
         }
     }
 
+    /// Counts the nodes before the current element, `0` if the cursor is dangling.
+    fn count_before(&self) -> usize {
+        let mut count = 0;
+        let mut temp_node = self.current().and_then(|node| node.prev());
+        while let Some(node) = temp_node {
+            count += 1;
+            // SAFETY: `node` is valid within this loop.
+            temp_node = unsafe { node.as_ref().prev() };
+        }
+        count
+    }
+
     /// Moves all elements from another list and inserts them after the current element.
     ///
     /// If the cursor is dangling, the elements are inserted at the end of the list.
@@ -559,6 +709,142 @@ impl<'a, T: DoublyLinkable> CursorMut<'a, T> {
     }
 }
 
+/// Checks `append` for every combination of empty and non-empty lists.
+#[cfg(debug_assertions)]
+pub fn check_append() {
+    use crate::collections::test_support::{TestNode, list_ids};
+
+    let order = list_ids::<5>;
+    let mut nodes = [0, 1, 2, 3, 4].map(TestNode::new);
+    let ptrs = nodes.each_mut().map(NonNull::from);
+
+    let mut list = DoublyLinkedList::new();
+    let mut other = DoublyLinkedList::new();
+
+    // both empty
+    list.append(&mut other);
+    assert!(list.is_empty() && other.is_empty());
+
+    // self empty
+    other.push_back(ptrs[0]);
+    other.push_back(ptrs[1]);
+    list.append(&mut other);
+    assert_eq!(order(&list), [0, 1, usize::MAX, usize::MAX, usize::MAX]);
+    assert_eq!((list.len(), other.len()), (2, 0));
+    assert!(other.head.is_none() && other.tail.is_none());
+
+    // other empty
+    list.append(&mut other);
+    assert_eq!(order(&list), [0, 1, usize::MAX, usize::MAX, usize::MAX]);
+    assert_eq!(list.len(), 2);
+
+    // both non-empty
+    other.push_back(ptrs[2]);
+    other.push_back(ptrs[3]);
+    other.push_back(ptrs[4]);
+    list.append(&mut other);
+    assert_eq!(order(&list), [0, 1, 2, 3, 4], "Appended list out of order");
+    assert_eq!((list.len(), other.len()), (5, 0));
+    assert!(other.is_empty() && other.head.is_none());
+}
+
+/// Checks `split_after_counted` against the counting `split_after`.
+#[cfg(debug_assertions)]
+pub fn check_split_after_counted() {
+    use crate::collections::test_support::{TestNode, list_ids};
+
+    let mut nodes = [0, 1, 2, 3, 4].map(TestNode::new);
+    let mut list = DoublyLinkedList::new();
+    for node in nodes.each_mut() {
+        list.push_back(NonNull::from(node));
+    }
+
+    // a cursor at the tail or dangling has nothing after it
+    let mut cursor = list.cursor_mut();
+    assert_eq!(cursor.count_after(), 4);
+    while cursor.move_next().is_some_and(|node| node.id != 4) {}
+    assert_eq!(cursor.count_after(), 0);
+    assert!(cursor.split_after_counted(0).is_empty());
+    cursor.move_next();
+    assert!(cursor.is_dangling());
+    assert_eq!(cursor.count_after(), 0);
+    assert!(cursor.split_after_counted(0).is_empty());
+    assert_eq!(list_ids::<5>(&list), [0, 1, 2, 3, 4]);
+
+    // split after the second node
+    let mut cursor = list.cursor_mut();
+    cursor.move_next();
+    assert_eq!(cursor.count_after(), 3);
+    let tail = cursor.split_after_counted(3);
+    assert_eq!((list.len(), tail.len()), (2, 3));
+    assert_eq!(list_ids::<5>(&list)[..2], [0, 1]);
+    assert_eq!(list_ids::<5>(&tail)[..3], [2, 3, 4]);
+}
+
+/// Checks `split_before` with the cursor at the head, in the middle and dangling.
+#[cfg(debug_assertions)]
+pub fn check_split_before() {
+    use crate::collections::test_support::{TestNode, list_ids};
+
+    let mut nodes = [0, 1, 2, 3, 4].map(TestNode::new);
+    let mut list = DoublyLinkedList::new();
+    for node in nodes.each_mut() {
+        list.push_back(NonNull::from(node));
+    }
+
+    // at the head nothing moves
+    let mut cursor = list.cursor_mut();
+    assert!(cursor.split_before().is_empty());
+    assert_eq!(cursor.current().map(|node| node.id), Some(0));
+    assert_eq!(list_ids::<5>(&list), [0, 1, 2, 3, 4]);
+
+    // in the middle the cursor stays on the new head
+    let mut cursor = list.cursor_mut();
+    cursor.move_next();
+    cursor.move_next();
+    let front = cursor.split_before();
+    assert_eq!(cursor.current().map(|node| node.id), Some(2));
+    assert_eq!((list.len(), front.len()), (3, 2));
+    assert_eq!(list_ids::<5>(&list)[..3], [2, 3, 4]);
+    assert_eq!(list_ids::<5>(&front)[..2], [0, 1]);
+
+    // dangling moves the whole list out
+    let mut cursor = list.cursor_mut();
+    while cursor.move_next().is_some() {}
+    let rest = cursor.split_before();
+    assert!(list.is_empty() && list.front().is_none() && list.back().is_none());
+    assert_eq!(rest.len(), 3);
+    assert_eq!(list_ids::<5>(&rest)[..3], [2, 3, 4]);
+}
+
+/// Checks `contains` and `position` for linked, unlinked and removed nodes.
+#[cfg(debug_assertions)]
+pub fn check_membership() {
+    use crate::collections::test_support::TestNode;
+
+    let mut nodes = [0, 1, 2, 3].map(TestNode::new);
+    let ptrs = nodes.each_mut().map(NonNull::from);
+    let mut list = DoublyLinkedList::new();
+    for &ptr in &ptrs[..3] {
+        list.push_back(ptr);
+    }
+
+    for (index, &ptr) in ptrs[..3].iter().enumerate() {
+        assert!(list.contains(ptr));
+        assert_eq!(list.position(ptr), Some(index));
+    }
+    assert!(!list.contains(ptrs[3]));
+    assert_eq!(list.position(ptrs[3]), None);
+
+    // removing the middle node shifts the tail down
+    let mut cursor = list.cursor_mut();
+    cursor.move_next();
+    assert_eq!(cursor.remove_current(), Some(ptrs[1]));
+    assert!(!list.contains(ptrs[1]));
+    assert_eq!(list.position(ptrs[1]), None);
+    assert_eq!(list.position(ptrs[2]), Some(1));
+}
+
 /// Asserts that a node's pointers are `None`.
 ///
 /// This is a sanity check to ensure a node isn't already in a list
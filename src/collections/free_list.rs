@@ -0,0 +1,130 @@
+use crate::collections::{SinglyLinkable, SinglyLinkedList};
+use crate::memory::frame::BASE_SIZE;
+use core::ptr::NonNull;
+
+/// A typed pool of free objects backed by an intrusive `SinglyLinkedList`.
+///
+/// Objects are handed out with `acquire` and given back with `recycle`, both O(1).
+/// The pool never owns the memory the objects live in; the user is responsible
+/// for keeping it alive for as long as the pool is in use.
+pub struct FreeList<T: SinglyLinkable> {
+    items: SinglyLinkedList<T>,
+}
+
+impl<T: SinglyLinkable> FreeList<T> {
+    /// Creates a new, empty `FreeList`.
+    pub const fn new() -> Self {
+        Self {
+            items: SinglyLinkedList::new(),
+        }
+    }
+
+    /// Carves a single frame into `T`-sized slots and links all of them into a new pool.
+    ///
+    /// Every slot is initialized with `T::default()`. Any trailing space smaller than
+    /// `size_of::<T>()` is left unused.
+    ///
+    /// # Safety
+    ///
+    /// `frame` must point to the start of `BASE_SIZE` bytes of writable memory, aligned
+    /// for `T` and exclusively owned by the returned pool.
+    pub unsafe fn with_capacity_from_frame(frame: NonNull<u8>) -> Self
+    where
+        T: Default,
+    {
+        let slot_size = size_of::<T>();
+        assert!(
+            slot_size > 0 && slot_size <= BASE_SIZE,
+            "Object does not fit into a frame"
+        );
+        debug_assert!(
+            frame.as_ptr().align_offset(align_of::<T>()) == 0,
+            "Frame is not aligned for the pool object"
+        );
+
+        let slots = BASE_SIZE / slot_size;
+        let mut pool = Self::new();
+
+        // link back to front so `acquire` hands out slots in address order
+        for i in (0..slots).rev() {
+            // SAFETY: `i * slot_size + slot_size <= BASE_SIZE`, so the slot lies within the frame.
+            let slot = unsafe {
+                let slot_ptr = frame.as_ptr().add(i * slot_size).cast::<T>();
+                slot_ptr.write(T::default());
+                NonNull::new_unchecked(slot_ptr)
+            };
+            pool.recycle(slot);
+        }
+
+        pool
+    }
+
+    /// Returns the number of free objects in the pool.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns `true` if the pool has no free objects left.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Takes a free object out of the pool, or `None` if it is exhausted.
+    pub fn acquire(&mut self) -> Option<NonNull<T>> {
+        self.items.pop_front()
+    }
+
+    /// Returns an object to the pool.
+    ///
+    /// # Panics
+    ///
+    /// Panics in debug builds if the object is still linked into a list.
+    pub fn recycle(&mut self, item: NonNull<T>) {
+        self.items.push_front(item);
+    }
+}
+
+impl<T: SinglyLinkable> Default for FreeList<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Checks a pool carved from a frame hands out every slot in address order, then nothing,
+/// and gives recycled slots back last in, first out. Needs the frame allocator.
+#[cfg(debug_assertions)]
+pub fn check_frame_pool() {
+    use crate::collections::test_support::TestNode;
+    use crate::memory::frame::BASE_SIZE_LAYOUT;
+
+    const SLOTS: usize = BASE_SIZE / size_of::<TestNode>();
+
+    let frame = crate::memory::frame_allocator()
+        .alloc(BASE_SIZE_LAYOUT)
+        .expect("Frame allocation failed");
+    // SAFETY: the frame is owned by this check until it is freed below
+    let mut pool = unsafe { FreeList::<TestNode>::with_capacity_from_frame(frame) };
+    assert_eq!(pool.len(), SLOTS);
+
+    let slot = |index: usize| unsafe { frame.cast::<TestNode>().add(index) };
+    for index in 0..SLOTS {
+        assert_eq!(
+            pool.acquire(),
+            Some(slot(index)),
+            "Slot {} out of order",
+            index
+        );
+    }
+    assert!(pool.is_empty() && pool.acquire().is_none());
+
+    for index in [3, 0, SLOTS - 1] {
+        pool.recycle(slot(index));
+    }
+    assert_eq!(pool.len(), 3);
+    for index in [SLOTS - 1, 0, 3] {
+        assert_eq!(pool.acquire(), Some(slot(index)));
+    }
+    assert!(pool.is_empty());
+
+    crate::memory::frame_allocator().dealloc(frame, BASE_SIZE_LAYOUT);
+}
@@ -0,0 +1,332 @@
+use crate::collections::{DoublyLinkable, DoublyLinkedList, SinglyLinkable};
+use alloc::alloc::{alloc, dealloc};
+use core::alloc::Layout;
+use core::hash::{Hash, Hasher};
+use core::ptr::NonNull;
+
+/// Bucket count of the first allocation, always a power of two.
+const INITIAL_BUCKETS: usize = 8;
+
+/// 64-bit FNV-1a, good enough for small integer and string keys.
+pub struct FnvHasher(u64);
+
+impl FnvHasher {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    pub const fn new() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+}
+
+impl Default for FnvHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
+    }
+}
+
+fn hash_of<K: Hash>(key: &K) -> u64 {
+    let mut hasher = FnvHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+struct Entry<K, V> {
+    next: Option<NonNull<Entry<K, V>>>,
+    prev: Option<NonNull<Entry<K, V>>>,
+    hash: u64,
+    key: K,
+    value: V,
+}
+
+unsafe impl<K, V> SinglyLinkable for Entry<K, V> {
+    fn next(&self) -> Option<NonNull<Self>> {
+        self.next
+    }
+
+    fn set_next(&mut self, next: Option<NonNull<Self>>) {
+        self.next = next;
+    }
+}
+
+unsafe impl<K, V> DoublyLinkable for Entry<K, V> {
+    fn prev(&self) -> Option<NonNull<Self>> {
+        self.prev
+    }
+
+    fn set_prev(&mut self, prev: Option<NonNull<Self>>) {
+        self.prev = prev;
+    }
+}
+
+type Bucket<K, V> = DoublyLinkedList<Entry<K, V>>;
+
+/// A separately chained hash map, entries and buckets live on the kernel heap.
+///
+/// Each bucket is an intrusive list of entries. The bucket count is a power of two and
+/// doubles once there are as many entries as buckets; if that allocation fails the map
+/// keeps working with longer chains.
+pub struct HashMap<K: Hash + Eq, V> {
+    buckets: NonNull<Bucket<K, V>>,
+    // power of two, 0 until the first insert
+    bucket_count: usize,
+    len: usize,
+}
+
+impl<K: Hash + Eq, V> HashMap<K, V> {
+    pub const fn new() -> Self {
+        Self {
+            buckets: NonNull::dangling(),
+            bucket_count: 0,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Inserts `value` under `key`, returning the value it replaced.
+    ///
+    /// Fails only if the heap cannot hold the new entry.
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, &'static str> {
+        let hash = hash_of(&key);
+
+        if let Some(mut entry) = self.find(hash, &key) {
+            let entry = unsafe { entry.as_mut() };
+            return Ok(Some(core::mem::replace(&mut entry.value, value)));
+        }
+
+        if self.len >= self.bucket_count {
+            let grown = (self.bucket_count * 2).max(INITIAL_BUCKETS);
+            if self.rehash(grown).is_err() && self.bucket_count == 0 {
+                return Err("Out of memory for the hash map buckets");
+            }
+        }
+
+        let entry = NonNull::new(unsafe { alloc(Layout::new::<Entry<K, V>>()) })
+            .ok_or("Out of memory for a hash map entry")?
+            .cast::<Entry<K, V>>();
+        unsafe {
+            entry.write(Entry {
+                next: None,
+                prev: None,
+                hash,
+                key,
+                value,
+            })
+        };
+
+        self.bucket_mut(hash).push_front(entry);
+        self.len += 1;
+
+        Ok(None)
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.find(hash_of(key), key)
+            .map(|entry| unsafe { &(*entry.as_ptr()).value })
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.find(hash_of(key), key)
+            .map(|entry| unsafe { &mut (*entry.as_ptr()).value })
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Removes `key`, returning its value.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let hash = hash_of(key);
+        let entry = self.find(hash, key)?;
+
+        self.bucket_mut(hash).remove(entry);
+        self.len -= 1;
+
+        let Entry { value, .. } = unsafe { take_entry(entry) };
+        Some(value)
+    }
+
+    /// Removes every entry, keeping the buckets allocated.
+    pub fn clear(&mut self) {
+        for index in 0..self.bucket_count {
+            let bucket = unsafe { &mut *self.buckets.as_ptr().add(index) };
+            while let Some(entry) = bucket.pop_front() {
+                drop(unsafe { take_entry(entry) });
+            }
+        }
+        self.len = 0;
+    }
+
+    fn find(&self, hash: u64, key: &K) -> Option<NonNull<Entry<K, V>>> {
+        if self.bucket_count == 0 {
+            return None;
+        }
+
+        self.bucket(hash).iter().find(|entry| {
+            let entry = unsafe { entry.as_ref() };
+            entry.hash == hash && entry.key == *key
+        })
+    }
+
+    fn bucket(&self, hash: u64) -> &Bucket<K, V> {
+        let index = hash as usize & (self.bucket_count - 1);
+        unsafe { &*self.buckets.as_ptr().add(index) }
+    }
+
+    fn bucket_mut(&mut self, hash: u64) -> &mut Bucket<K, V> {
+        let index = hash as usize & (self.bucket_count - 1);
+        unsafe { &mut *self.buckets.as_ptr().add(index) }
+    }
+
+    /// Moves every entry into a fresh array of `bucket_count` buckets.
+    fn rehash(&mut self, bucket_count: usize) -> Result<(), ()> {
+        debug_assert!(bucket_count.is_power_of_two());
+
+        let layout = Layout::array::<Bucket<K, V>>(bucket_count).map_err(|_| ())?;
+        let buckets = NonNull::new(unsafe { alloc(layout) })
+            .ok_or(())?
+            .cast::<Bucket<K, V>>();
+        for index in 0..bucket_count {
+            unsafe { buckets.add(index).write(DoublyLinkedList::new()) };
+        }
+
+        let old_buckets = core::mem::replace(&mut self.buckets, buckets);
+        let old_count = core::mem::replace(&mut self.bucket_count, bucket_count);
+
+        for index in 0..old_count {
+            let old_bucket = unsafe { &mut *old_buckets.as_ptr().add(index) };
+            while let Some(entry) = old_bucket.pop_front() {
+                let hash = unsafe { entry.as_ref() }.hash;
+                self.bucket_mut(hash).push_front(entry);
+            }
+        }
+
+        if old_count > 0 {
+            unsafe {
+                dealloc(
+                    old_buckets.as_ptr().cast(),
+                    bucket_layout::<K, V>(old_count),
+                )
+            };
+        }
+
+        Ok(())
+    }
+}
+
+impl<K: Hash + Eq, V> Default for HashMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Hash + Eq, V> Drop for HashMap<K, V> {
+    fn drop(&mut self) {
+        self.clear();
+
+        if self.bucket_count > 0 {
+            unsafe {
+                dealloc(
+                    self.buckets.as_ptr().cast(),
+                    bucket_layout::<K, V>(self.bucket_count),
+                )
+            };
+        }
+    }
+}
+
+// entries are only reachable through the map
+unsafe impl<K: Hash + Eq + Send, V: Send> Send for HashMap<K, V> {}
+unsafe impl<K: Hash + Eq + Sync, V: Sync> Sync for HashMap<K, V> {}
+
+fn bucket_layout<K, V>(bucket_count: usize) -> Layout {
+    Layout::array::<Bucket<K, V>>(bucket_count)
+        .expect("Bucket array was allocated with this layout")
+}
+
+/// Moves the entry out of its heap allocation and frees it.
+///
+/// # Safety
+///
+/// `entry` must come from `HashMap::insert`, be unlinked, and not be used afterwards.
+unsafe fn take_entry<K, V>(entry: NonNull<Entry<K, V>>) -> Entry<K, V> {
+    unsafe {
+        let taken = entry.read();
+        dealloc(entry.as_ptr().cast(), Layout::new::<Entry<K, V>>());
+        taken
+    }
+}
+
+/// Key whose hash ignores its value, every instance lands in the same bucket.
+#[derive(PartialEq, Eq)]
+struct CollidingKey(u32);
+
+impl Hash for CollidingKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u8(0);
+    }
+}
+
+/// Checks insert/get/remove, replacement, growth and same-bucket chains. Needs the
+/// kernel heap.
+pub fn check_hash_map() {
+    let mut map = HashMap::new();
+    for key in 0..100u64 {
+        assert_eq!(
+            map.insert(key, key * 2),
+            Ok(None),
+            "Fresh key reported a previous value"
+        );
+    }
+    assert_eq!(map.len(), 100);
+    assert!(map.bucket_count >= 100, "Map did not grow");
+    assert!(
+        (0..100u64).all(|key| map.get(&key) == Some(&(key * 2))),
+        "Lookup after growth failed"
+    );
+
+    assert_eq!(
+        map.insert(7, 0),
+        Ok(Some(14)),
+        "Replacement did not return the old value"
+    );
+    assert_eq!(map.remove(&7), Some(0));
+    assert_eq!(map.remove(&7), None, "Removed key still present");
+    assert_eq!(map.get(&7), None);
+    assert_eq!(map.len(), 99);
+
+    let mut colliding = HashMap::new();
+    for key in 0..4 {
+        colliding
+            .insert(CollidingKey(key), key)
+            .expect("Insert failed");
+    }
+    assert_eq!(colliding.remove(&CollidingKey(1)), Some(1));
+    assert!(
+        [0, 2, 3]
+            .iter()
+            .all(|&key| colliding.get(&CollidingKey(key)) == Some(&key)),
+        "Removing from a chain lost its neighbours"
+    );
+    assert!(!colliding.contains_key(&CollidingKey(1)));
+}
@@ -0,0 +1,99 @@
+use crate::collections::Bitmap;
+
+/// Number of bitmap words an `IdAllocator` handing out `max` ids needs.
+pub const fn id_words(max: usize) -> usize {
+    max.div_ceil(u64::BITS as usize)
+}
+
+/// Hands out the ids `0..MAX`, always the lowest free one, e.g. for pids or descriptors.
+///
+/// `WORDS` must be `id_words(MAX)`, spelled out since array lengths can't depend on
+/// `MAX` directly: `IdAllocator<MAX_PIDS, { id_words(MAX_PIDS) }>`.
+#[derive(Debug, Clone, Copy)]
+pub struct IdAllocator<const MAX: usize, const WORDS: usize> {
+    // set while the id is in use
+    used: Bitmap<WORDS>,
+    len: usize,
+}
+
+impl<const MAX: usize, const WORDS: usize> IdAllocator<MAX, WORDS> {
+    pub const fn new() -> Self {
+        const { assert!(WORDS == id_words(MAX), "WORDS must be id_words(MAX)") };
+
+        Self {
+            used: Bitmap::new(),
+            len: 0,
+        }
+    }
+
+    /// Number of ids currently handed out.
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub const fn is_allocated(&self, id: usize) -> bool {
+        id < MAX && self.used.is_set(id)
+    }
+
+    /// Takes the lowest free id, `None` once all `MAX` are in use.
+    pub const fn alloc(&mut self) -> Option<usize> {
+        match self.used.find_first_clear() {
+            // bits past `MAX` in the last word are never handed out
+            Some(id) if id < MAX => {
+                self.used.set(id);
+                self.len += 1;
+                Some(id)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns `id` for reuse, failing if it is out of range or not allocated.
+    pub const fn free(&mut self, id: usize) -> Result<(), &'static str> {
+        if id >= MAX {
+            return Err("Id out of range");
+        }
+        if !self.used.is_set(id) {
+            return Err("Id freed twice or never allocated");
+        }
+
+        self.used.clear(id);
+        self.len -= 1;
+        Ok(())
+    }
+}
+
+impl<const MAX: usize, const WORDS: usize> Default for IdAllocator<MAX, WORDS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// exhaustion stops at `MAX` even mid-word, freed ids come back lowest first
+const _: () = {
+    let mut ids = IdAllocator::<70, { id_words(70) }>::new();
+
+    let mut expected = 0;
+    while expected < 70 {
+        assert!(matches!(ids.alloc(), Some(id) if id == expected));
+        expected += 1;
+    }
+    assert!(ids.alloc().is_none());
+    assert!(ids.len() == 70);
+
+    assert!(ids.free(65).is_ok());
+    assert!(ids.free(3).is_ok());
+    assert!(matches!(ids.alloc(), Some(3)));
+    assert!(matches!(ids.alloc(), Some(65)));
+    assert!(ids.alloc().is_none());
+
+    assert!(ids.free(10).is_ok());
+    assert!(ids.free(10).is_err());
+    assert!(ids.free(70).is_err());
+    assert!(!ids.is_allocated(10));
+    assert!(ids.len() == 69);
+};
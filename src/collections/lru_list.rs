@@ -0,0 +1,118 @@
+use crate::collections::{DoublyLinkable, DoublyLinkedList, Iter};
+use core::ptr::NonNull;
+
+/// Least-recently-used ordering over intrusive nodes.
+///
+/// The most recently touched node is at the front, `evict` takes from the back.
+/// Like the list it wraps, it does not own the nodes.
+pub struct LruList<T: DoublyLinkable> {
+    list: DoublyLinkedList<T>,
+}
+
+impl<T: DoublyLinkable> LruList<T> {
+    pub const fn new() -> Self {
+        Self {
+            list: DoublyLinkedList::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    /// Adds a detached node as the most recently used one.
+    pub fn insert(&mut self, node: NonNull<T>) {
+        self.list.push_front(node);
+    }
+
+    /// Marks `node` as the most recently used one.
+    ///
+    /// # Panics
+    ///
+    /// Panics in debug builds if `node` is not in this list.
+    pub fn touch(&mut self, node: NonNull<T>) {
+        debug_assert!(
+            self.list.contains(node),
+            "touch() called with a node from another list"
+        );
+
+        // already the most recently used
+        if self.list.front().map(NonNull::from) == Some(node) {
+            return;
+        }
+
+        let node = self.list.remove(node);
+        self.list.push_front(node);
+    }
+
+    /// Removes and returns the least recently used node.
+    pub fn evict(&mut self) -> Option<NonNull<T>> {
+        self.list.pop_back()
+    }
+
+    /// Removes `node`, which must be in this list, without evicting anything else.
+    pub fn remove(&mut self, node: NonNull<T>) -> NonNull<T> {
+        self.list.remove(node)
+    }
+
+    /// Iterates from the most to the least recently used node.
+    pub fn iter(&self) -> Iter<'_, T> {
+        self.list.iter()
+    }
+}
+
+impl<T: DoublyLinkable> Default for LruList<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Checks touching moves a node to the front and eviction follows touch order.
+#[cfg(debug_assertions)]
+pub fn check_lru_list() {
+    use crate::collections::test_support::TestNode;
+
+    let mut nodes = [0, 1, 2, 3].map(TestNode::new);
+    let ptrs = nodes.each_mut().map(NonNull::from);
+
+    let mut lru = LruList::new();
+    for &node in &ptrs {
+        lru.insert(node);
+    }
+    // front to back: 3 2 1 0
+
+    let order = |lru: &LruList<TestNode>| {
+        let mut ids = [usize::MAX; 4];
+        for (slot, node) in ids.iter_mut().zip(lru.iter()) {
+            *slot = unsafe { node.as_ref() }.id;
+        }
+        ids
+    };
+
+    lru.touch(ptrs[1]);
+    assert_eq!(
+        order(&lru),
+        [1, 3, 2, 0],
+        "Touched middle node not moved to the front"
+    );
+
+    // touching the front is a no-op
+    lru.touch(ptrs[1]);
+    assert_eq!(order(&lru), [1, 3, 2, 0]);
+
+    lru.touch(ptrs[0]);
+    assert_eq!(
+        lru.evict(),
+        Some(ptrs[2]),
+        "Evicted a recently touched node"
+    );
+    assert_eq!(lru.evict(), Some(ptrs[3]));
+    assert_eq!(lru.evict(), Some(ptrs[1]));
+    assert_eq!(lru.evict(), Some(ptrs[0]));
+    assert_eq!(lru.evict(), None);
+    assert!(lru.is_empty());
+}
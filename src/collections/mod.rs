@@ -1,5 +1,17 @@
+pub mod bitmap;
 pub mod doubly_linked_list;
+pub mod free_list;
+pub mod hash_map;
+pub mod id_allocator;
+pub mod lru_list;
 pub mod singly_linked_list;
+#[cfg(debug_assertions)]
+pub(crate) mod test_support;
 
-pub use doubly_linked_list::{CursorMut, DoublyLinkable, DoublyLinkedList};
+pub use bitmap::Bitmap;
+pub use doubly_linked_list::{CursorMut, DoublyLinkable, DoublyLinkedList, Iter};
+pub use free_list::FreeList;
+pub use hash_map::HashMap;
+pub use id_allocator::{IdAllocator, id_words};
+pub use lru_list::LruList;
 pub use singly_linked_list::{SinglyLinkable, SinglyLinkedList};
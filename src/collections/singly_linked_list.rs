@@ -42,6 +42,18 @@ impl<T: SinglyLinkable> SinglyLinkedList<T> {
         self.len == 0
     }
 
+    /// Returns `true` if `node` is linked into this list. O(n).
+    pub fn contains(&self, node: NonNull<T>) -> bool {
+        let mut current = self.head;
+        while let Some(current_node) = current {
+            if current_node == node {
+                return true;
+            }
+            current = unsafe { current_node.as_ref().next() };
+        }
+        false
+    }
+
     pub fn front(&self) -> Option<&T> {
         self.head.map(|node| unsafe { node.as_ref() })
     }
@@ -58,6 +70,36 @@ impl<T: SinglyLinkable> SinglyLinkedList<T> {
         self.len += 1;
     }
 
+    /// Splices an already linked run of `count` nodes, `head` through `tail`, onto the front in O(1).
+    ///
+    /// # Panics
+    ///
+    /// Panics in debug builds if `tail` is still linked to something or if the run
+    /// from `head` to `tail` is not exactly `count` nodes long.
+    pub fn prepend_chain(&mut self, head: NonNull<T>, mut tail: NonNull<T>, count: usize) {
+        debug_assert!(
+            unsafe { tail.as_ref().next().is_none() },
+            "Chain tail is already linked"
+        );
+        debug_assert_eq!(
+            {
+                let mut walked = 1;
+                let mut node = head;
+                while node != tail {
+                    node = unsafe { node.as_ref().next().expect("Chain does not reach its tail") };
+                    walked += 1;
+                }
+                walked
+            },
+            count,
+            "Chain length does not match count"
+        );
+
+        unsafe { tail.as_mut().set_next(self.head) };
+        self.head = Some(head);
+        self.len += count;
+    }
+
     pub fn pop_front(&mut self) -> Option<NonNull<T>> {
         self.head.map(|mut old_head| {
             let old_head_ref = unsafe { old_head.as_mut() };
@@ -131,3 +173,30 @@ impl<T: SinglyLinkable> Iterator for IntoIter<T> {
         self.list.pop_front()
     }
 }
+
+/// Checks `prepend_chain` splices a 4-node run in front of the existing nodes, in order.
+#[cfg(debug_assertions)]
+pub fn check_prepend_chain() {
+    use crate::collections::test_support::TestNode;
+
+    let mut nodes = [0, 1, 2, 3, 4, 5].map(TestNode::new);
+    let ptrs = nodes.each_mut().map(NonNull::from);
+
+    let mut list = SinglyLinkedList::new();
+    list.push_front(ptrs[5]);
+    list.push_front(ptrs[4]);
+
+    for (mut node, next) in ptrs[..3].iter().copied().zip(ptrs[1..4].iter().copied()) {
+        unsafe { node.as_mut() }.set_next(Some(next));
+    }
+    list.prepend_chain(ptrs[0], ptrs[3], 4);
+    assert_eq!(list.len(), 6, "Chain length not added");
+
+    assert!(
+        list.drain(list.len())
+            .map(|node| unsafe { node.as_ref() }.id)
+            .eq(0..6),
+        "Prepended chain out of order"
+    );
+    assert!(list.is_empty());
+}
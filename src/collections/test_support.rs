@@ -0,0 +1,79 @@
+//! Fixtures shared by the collections' self-checks, only built in debug builds.
+
+use crate::collections::{DoublyLinkable, DoublyLinkedList, SinglyLinkable};
+use core::ptr::NonNull;
+
+/// Plain list node for the collections' self-checks.
+pub(crate) struct TestNode {
+    pub id: usize,
+    next: Option<NonNull<TestNode>>,
+    prev: Option<NonNull<TestNode>>,
+}
+
+impl TestNode {
+    pub const fn new(id: usize) -> Self {
+        Self {
+            id,
+            next: None,
+            prev: None,
+        }
+    }
+}
+
+impl Default for TestNode {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+unsafe impl SinglyLinkable for TestNode {
+    fn next(&self) -> Option<NonNull<Self>> {
+        self.next
+    }
+
+    fn set_next(&mut self, next: Option<NonNull<Self>>) {
+        self.next = next;
+    }
+}
+
+unsafe impl DoublyLinkable for TestNode {
+    fn prev(&self) -> Option<NonNull<Self>> {
+        self.prev
+    }
+
+    fn set_prev(&mut self, prev: Option<NonNull<Self>>) {
+        self.prev = prev;
+    }
+}
+
+/// Ids of `list` from head to tail, padded with `usize::MAX`.
+///
+/// # Panics
+///
+/// Panics if walking back from the tail does not give the reverse, or the list holds more
+/// than `N` nodes or disagrees with its `len`.
+pub(crate) fn list_ids<const N: usize>(list: &DoublyLinkedList<TestNode>) -> [usize; N] {
+    assert!(list.len() <= N, "List longer than the ids buffer");
+
+    let mut ids = [usize::MAX; N];
+    let mut walked = 0;
+    for (slot, node) in ids.iter_mut().zip(list.iter()) {
+        *slot = unsafe { node.as_ref() }.id;
+        walked += 1;
+    }
+    assert_eq!(walked, list.len(), "next chain disagrees with len");
+
+    let mut backwards = list.back().map(NonNull::from);
+    for &id in ids[..list.len()].iter().rev() {
+        let node = unsafe { backwards.expect("Broken prev chain").as_ref() };
+        assert_eq!(node.id, id, "prev chain disagrees with next chain");
+        backwards = node.prev();
+    }
+    assert!(backwards.is_none(), "Head has a prev link");
+    assert!(
+        list.back().is_none_or(|tail| tail.next().is_none()),
+        "Tail has a next link"
+    );
+
+    ids
+}
@@ -1,9 +1,319 @@
+use crate::memory::hart_cache::MAX_HARTS;
+use core::arch::asm;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
 pub const CACHE_LINE_SIZE: usize = 64;
 
+/// `sstatus.SIE`: global supervisor interrupt enable
+const SSTATUS_SIE: usize = 1 << 1;
+
+/// `sie.SSIE`: supervisor software interrupt enable
+pub const SIE_SSIE: usize = 1 << 1;
+/// `sie.STIE`: supervisor timer interrupt enable
+pub const SIE_STIE: usize = 1 << 5;
+/// `sie.SEIE`: supervisor external interrupt enable
+pub const SIE_SEIE: usize = 1 << 9;
+
+/// Returns the id of the hart executing this code.
+///
+/// `mhartid` is an M-mode CSR and faults in S-mode, so the id is kept in the
+/// thread pointer register (`tp`) instead.
+///
+/// # Boot-time contract
+///
+/// `boot.S` loads `tp` with `mhartid` before dropping to S-mode, and every hart
+/// calls `set_current_hart_id` once on entry to Rust. Nothing else may write `tp`:
+/// the kernel has no thread-local storage and the trap path saves/restores it.
+#[inline]
 pub fn current_hart_id() -> usize {
     let hart_id: usize;
     unsafe {
-        core::arch::asm!("csrr {}, mhartid", out(reg) hart_id);
+        asm!("mv {}, tp", out(reg) hart_id);
     }
     hart_id
 }
+
+/// Records `hart_id` as the current hart's id, see `current_hart_id`.
+///
+/// Must be called exactly once per hart at startup, before anything uses `current_hart_id`.
+#[inline]
+pub fn set_current_hart_id(hart_id: usize) {
+    unsafe {
+        asm!("mv tp, {}", in(reg) hart_id);
+    }
+}
+
+/// Globally enables supervisor interrupts on the current hart.
+#[inline]
+pub fn enable_interrupts() {
+    unsafe {
+        asm!("csrs sstatus, {}", in(reg) SSTATUS_SIE);
+    }
+}
+
+/// Globally disables supervisor interrupts on the current hart.
+#[inline]
+pub fn disable_interrupts() {
+    unsafe {
+        asm!("csrc sstatus, {}", in(reg) SSTATUS_SIE);
+    }
+}
+
+/// Returns `true` if supervisor interrupts are globally enabled on the current hart.
+#[inline]
+pub fn interrupts_enabled() -> bool {
+    let sstatus: usize;
+    unsafe {
+        asm!("csrr {}, sstatus", out(reg) sstatus);
+    }
+    sstatus & SSTATUS_SIE != 0
+}
+
+/// Runs `f` with interrupts disabled, restoring the previous state afterwards.
+#[inline]
+pub fn with_interrupts_disabled<R>(f: impl FnOnce() -> R) -> R {
+    let _irq = IrqGuard::new();
+    f()
+}
+
+/// Disables interrupts on the current hart until dropped, then restores the previous state.
+///
+/// Guards nest: only the outermost one re-enables interrupts.
+pub struct IrqGuard {
+    was_enabled: bool,
+    // must be dropped on the hart that created it
+    _not_send: PhantomData<*const ()>,
+}
+
+impl IrqGuard {
+    #[inline]
+    pub fn new() -> Self {
+        let previous: usize;
+        // read and clear in a single instruction so an interrupt can't slip in between
+        unsafe {
+            asm!("csrrc {}, sstatus, {}", out(reg) previous, in(reg) SSTATUS_SIE);
+        }
+
+        Self {
+            was_enabled: previous & SSTATUS_SIE != 0,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl Default for IrqGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for IrqGuard {
+    #[inline]
+    fn drop(&mut self) {
+        if self.was_enabled {
+            enable_interrupts();
+        }
+    }
+}
+
+/// Enables individual interrupt sources in `sie` (see `SIE_*` constants).
+#[inline]
+pub fn set_sie_bit(mask: usize) {
+    unsafe {
+        asm!("csrs sie, {}", in(reg) mask);
+    }
+}
+
+/// Clears the pending supervisor timer interrupt and re-enables the machine timer
+/// interrupt it is forwarded from, see `machine_trap` in `boot.S`.
+///
+/// Call after moving the current hart's `mtimecmp`: if the new deadline has already
+/// passed, the interrupt is raised again right away.
+#[inline]
+pub fn ack_timer_interrupt() {
+    // SAFETY: the M-mode handler only touches `mip`, `mie`, `mepc` and `mscratch`
+    unsafe {
+        asm!("ecall");
+    }
+}
+
+/// Disables individual interrupt sources in `sie` (see `SIE_*` constants).
+#[inline]
+pub fn clear_sie_bit(mask: usize) {
+    unsafe {
+        asm!("csrc sie, {}", in(reg) mask);
+    }
+}
+
+/// Word painted over unused stack, see `stack_high_watermark`.
+pub const STACK_PAINT: u64 = 0x57AC_57AC_57AC_57AC;
+
+/// Stack left unpainted below the live frames when painting the running boot stack.
+const STACK_PAINT_MARGIN: usize = 1024;
+
+struct StackBounds {
+    bottom: AtomicUsize,
+    top: AtomicUsize,
+}
+
+/// Painted stack of each hart, `0..0` until registered.
+static STACK_BOUNDS: [StackBounds; MAX_HARTS] = [const {
+    StackBounds {
+        bottom: AtomicUsize::new(0),
+        top: AtomicUsize::new(0),
+    }
+}; MAX_HARTS];
+
+/// Fills `bottom..top` with `STACK_PAINT`.
+///
+/// # Safety
+///
+/// The range must be a word-aligned stack nothing is running on yet, or the unused part
+/// below the current hart's `sp`.
+#[inline]
+pub unsafe fn paint_stack(bottom: usize, top: usize) {
+    let mut word = bottom as *mut u64;
+    while (word as usize) < top {
+        unsafe {
+            word.write_volatile(STACK_PAINT);
+            word = word.add(1);
+        }
+    }
+}
+
+/// Records the painted stack `bottom..top` of `hart_id` for `stack_high_watermark`.
+pub fn register_stack(hart_id: usize, bottom: usize, top: usize) {
+    STACK_BOUNDS[hart_id]
+        .bottom
+        .store(bottom, Ordering::Relaxed);
+    STACK_BOUNDS[hart_id].top.store(top, Ordering::Release);
+}
+
+/// Registered stack `(bottom, top)` of `hart_id`, if any.
+pub fn stack_bounds(hart_id: usize) -> Option<(usize, usize)> {
+    let bounds = STACK_BOUNDS.get(hart_id)?;
+    let top = bounds.top.load(Ordering::Acquire);
+    (top != 0).then(|| (bounds.bottom.load(Ordering::Relaxed), top))
+}
+
+/// Paints the part of the boot stack below the caller and registers it for the current hart.
+///
+/// Must be called on the boot hart, as early as possible: anything deeper than the stack
+/// already is at this point is lost to the watermark.
+#[inline(never)]
+pub fn paint_boot_stack() {
+    // defined by the linker script
+    unsafe extern "C" {
+        static _stack_bottom: [u8; 0];
+        static _stack_top: [u8; 0];
+    }
+    let bottom = unsafe { _stack_bottom.as_ptr() as usize };
+    let top = unsafe { _stack_top.as_ptr() as usize };
+
+    let sp: usize;
+    unsafe {
+        asm!("mv {}, sp", out(reg) sp);
+    }
+    assert!(bottom < sp && sp <= top, "Not running on the boot stack");
+
+    // leaves room for `paint_stack`'s own frame
+    let paint_top = (sp - STACK_PAINT_MARGIN).max(bottom) & !(size_of::<u64>() - 1);
+    unsafe { paint_stack(bottom, paint_top) };
+
+    register_stack(current_hart_id(), bottom, top);
+}
+
+/// Bytes of a painted stack that were ever written, given as words from bottom to top.
+///
+/// Stacks grow down, so everything above the lowest overwritten word counts as used.
+pub const fn painted_depth(stack: &[u64]) -> usize {
+    let mut untouched = 0;
+    while untouched < stack.len() && stack[untouched] == STACK_PAINT {
+        untouched += 1;
+    }
+    (stack.len() - untouched) * size_of::<u64>()
+}
+
+/// Peak stack usage of `hart_id` in bytes, `None` if its stack was never painted.
+///
+/// Read while the hart runs, so another hart's value is only a snapshot.
+pub fn stack_high_watermark(hart_id: usize) -> Option<usize> {
+    let (bottom, top) = stack_bounds(hart_id)?;
+
+    let words = (top - bottom) / size_of::<u64>();
+    let stack = unsafe { core::slice::from_raw_parts(bottom as *const u64, words) };
+    Some(painted_depth(stack))
+}
+
+// the scan stops at the lowest overwritten word, paint-colored data above it still counts
+const _: () = {
+    let mut stack = [STACK_PAINT; 32];
+    assert!(painted_depth(&stack) == 0);
+
+    stack[20] = 0;
+    stack[31] = 1;
+    assert!(painted_depth(&stack) == size_of::<[u64; 12]>());
+
+    stack[4] = 0;
+    assert!(painted_depth(&stack) == size_of::<[u64; 28]>());
+
+    assert!(painted_depth(&[0; 8]) == size_of::<[u64; 8]>());
+    assert!(painted_depth(&[]) == 0);
+};
+
+/// Points `sscratch` at the top of the current hart's trap stack.
+///
+/// `alltraps` swaps `sp` with `sscratch` on entry, so this must be set before
+/// the hart can take its first trap.
+#[inline]
+pub fn set_trap_stack(stack_top: usize) {
+    unsafe {
+        asm!("csrw sscratch, {}", in(reg) stack_top);
+    }
+}
+
+/// Orders all prior memory and I/O accesses before all later ones (`fence iorw, iorw`),
+/// e.g. between filling a DMA buffer and ringing the device's doorbell.
+#[inline]
+pub fn fence() {
+    unsafe {
+        asm!("fence iorw, iorw");
+    }
+}
+
+/// Cache-block management operation, see `cache_range`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheOp {
+    /// Write dirty lines back to memory, keeping them cached (`cbo.clean`).
+    Clean,
+    /// Drop cached lines so the next read comes from memory (`cbo.inval`).
+    Invalidate,
+    /// Write back, then drop (`cbo.flush`).
+    Flush,
+}
+
+/// Applies `op` to every cache block overlapping `[start, start + len)`, then fences.
+///
+/// Uses the Zicbom instructions with the `zicbom` feature, which `boot.S` then enables for
+/// S-mode in `menvcfg`. Otherwise the caches are assumed coherent with DMA (as on QEMU virt)
+/// and only the fence is issued.
+pub fn cache_range(op: CacheOp, start: usize, len: usize) {
+    if cfg!(feature = "zicbom") && len > 0 {
+        let mut block = start & !(CACHE_LINE_SIZE - 1);
+
+        while block < start + len {
+            // encoded by hand, assemblers without Zicbom support reject the mnemonics
+            unsafe {
+                match op {
+                    CacheOp::Clean => asm!(".insn i 0x0F, 2, x0, {0}, 1", in(reg) block),
+                    CacheOp::Invalidate => asm!(".insn i 0x0F, 2, x0, {0}, 0", in(reg) block),
+                    CacheOp::Flush => asm!(".insn i 0x0F, 2, x0, {0}, 2", in(reg) block),
+                }
+            }
+            block += CACHE_LINE_SIZE;
+        }
+    }
+
+    fence();
+}
@@ -0,0 +1,237 @@
+//! Panic record kept in RAM across a warm reboot.
+//!
+//! `_panic` encodes the message, the panicking hart and, for fatal traps, the `TrapFrame`
+//! into `PhysicalMemoryMap::crash_log`, which the frame allocator never hands out. The
+//! next boot prints the record and clears it. A panic halts the system and the log is
+//! cleared on every boot, so the region only ever holds one record.
+//!
+//! Record layout, little endian: a `CRASHLOG` magic, `u32` hart id, `u32` flags (bit 0:
+//! trap frame present), `u32` message length and `u32` padding, then the raw `TrapFrame`
+//! (zeroed when absent), then the UTF-8 message. The magic is written last, so a record
+//! cut short by a fault while encoding is never read back.
+
+use crate::cpu::current_hart_id;
+use crate::memory::{self, MemoryRegion};
+use crate::trap::TrapFrame;
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+const MAGIC: [u8; 8] = *b"CRASHLOG";
+const HEADER_SIZE: usize = 24;
+const FRAME_OFFSET: usize = HEADER_SIZE;
+const MESSAGE_OFFSET: usize = FRAME_OFFSET + size_of::<TrapFrame>();
+
+const FLAG_TRAP_FRAME: u32 = 1 << 0;
+
+const _: () = assert!(MESSAGE_OFFSET < memory::pmem_map::CRASH_LOG_SIZE);
+
+/// Frame of the fatal trap being turned into a panic, see `set_trap_frame`.
+static FATAL_TRAP_FRAME: AtomicPtr<TrapFrame> = AtomicPtr::new(core::ptr::null_mut());
+
+/// A record parsed back from the layout described in the module docs.
+#[derive(Debug, Clone, Copy)]
+pub struct CrashRecord<'a> {
+    pub hart_id: usize,
+    pub frame: Option<TrapFrame>,
+    pub message: &'a str,
+}
+
+impl<'a> CrashRecord<'a> {
+    /// `None` if `bytes` does not start with a complete record.
+    pub fn parse(bytes: &'a [u8]) -> Option<Self> {
+        if bytes.get(..MAGIC.len())? != MAGIC {
+            return None;
+        }
+
+        let hart_id = read_u32(bytes, 8)? as usize;
+        let flags = read_u32(bytes, 12)?;
+        let message_len = read_u32(bytes, 16)? as usize;
+
+        let frame_bytes = bytes.get(FRAME_OFFSET..MESSAGE_OFFSET)?;
+        // SAFETY: `TrapFrame` is plain `usize`s, any bit pattern is valid
+        let frame = (flags & FLAG_TRAP_FRAME != 0)
+            .then(|| unsafe { frame_bytes.as_ptr().cast::<TrapFrame>().read_unaligned() });
+
+        let message = bytes.get(MESSAGE_OFFSET..MESSAGE_OFFSET.checked_add(message_len)?)?;
+
+        Some(Self {
+            hart_id,
+            frame,
+            message: core::str::from_utf8(message).ok()?,
+        })
+    }
+}
+
+impl fmt::Display for CrashRecord<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "hart {} panicked: {}", self.hart_id, self.message)?;
+        if let Some(frame) = &self.frame {
+            write!(f, "{}", frame)?;
+        }
+        Ok(())
+    }
+}
+
+/// Encodes a record into `buf`, cutting the message short to fit.
+///
+/// Never allocates. Returns the record length, or `None` if `buf` cannot even hold the
+/// header and trap frame.
+pub fn encode(
+    buf: &mut [u8],
+    hart_id: usize,
+    frame: Option<&TrapFrame>,
+    message: fmt::Arguments,
+) -> Option<usize> {
+    if buf.len() < MESSAGE_OFFSET {
+        return None;
+    }
+
+    // an interrupted encode must not leave a stale magic in front of new contents
+    buf[..MAGIC.len()].fill(0);
+
+    let mut writer = TruncatingWriter::new(&mut buf[MESSAGE_OFFSET..]);
+    writer.write_fmt(message).ok();
+    let message_len = writer.len;
+
+    let frame_bytes = &mut buf[FRAME_OFFSET..MESSAGE_OFFSET];
+    match frame {
+        // SAFETY: the slice is exactly `size_of::<TrapFrame>()` bytes long
+        Some(frame) => unsafe {
+            frame_bytes
+                .as_mut_ptr()
+                .cast::<TrapFrame>()
+                .write_unaligned(*frame)
+        },
+        None => frame_bytes.fill(0),
+    }
+
+    let flags = if frame.is_some() { FLAG_TRAP_FRAME } else { 0 };
+    buf[8..12].copy_from_slice(&(hart_id as u32).to_le_bytes());
+    buf[12..16].copy_from_slice(&flags.to_le_bytes());
+    buf[16..20].copy_from_slice(&(message_len as u32).to_le_bytes());
+    buf[20..24].fill(0);
+    buf[..MAGIC.len()].copy_from_slice(&MAGIC);
+
+    Some(MESSAGE_OFFSET + message_len)
+}
+
+/// Remembers the frame of a fatal trap, so the panic it raises records the registers.
+///
+/// The frame lives on the trap stack, which the panicking hart never unwinds.
+pub fn set_trap_frame(frame: &TrapFrame) {
+    FATAL_TRAP_FRAME.store(core::ptr::from_ref(frame).cast_mut(), Ordering::Release);
+}
+
+/// Writes `info` to the crash log. No-op if the memory map is not set up yet.
+///
+/// Called from `_panic`: takes no locks and does not touch the heap.
+pub fn record_panic(info: &PanicInfo) {
+    let Some(region) = crash_log_region() else {
+        return;
+    };
+
+    let frame = unsafe { FATAL_TRAP_FRAME.load(Ordering::Acquire).as_ref() };
+    // SAFETY: the crash log region is reserved for this module and the panicking hart
+    // is the only one writing it
+    let buf = unsafe { region.start().as_mut_slice(region.size()) };
+    encode(buf, current_hart_id(), frame, format_args!("{}", info));
+}
+
+/// Prints the record left by a panic in the previous boot, if any, and clears it.
+///
+/// Must run after `memory::init` and before anything can panic on another hart.
+pub fn report_previous_crash() {
+    let Some(region) = crash_log_region() else {
+        return;
+    };
+
+    // SAFETY: as in `record_panic`, no other hart is running yet
+    let buf = unsafe { region.start().as_mut_slice(region.size()) };
+    if let Some(record) = CrashRecord::parse(buf) {
+        println!("[WARN] The previous boot crashed, {}", record);
+    }
+
+    buf[..MAGIC.len()].fill(0);
+}
+
+fn crash_log_region() -> Option<MemoryRegion> {
+    memory::PMEM_MAP
+        .get()
+        .map(|map| map.crash_log)
+        .filter(|region| region.size() >= MESSAGE_OFFSET)
+}
+
+/// `fmt::Write` into a fixed buffer, silently dropping what does not fit.
+pub(crate) struct TruncatingWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> TruncatingWriter<'a> {
+    pub(crate) fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    /// What was written so far.
+    pub(crate) fn as_str(&self) -> &str {
+        // only whole characters are ever copied in
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or_default()
+    }
+}
+
+impl Write for TruncatingWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = self.buf.len() - self.len;
+        let mut take = s.len().min(room);
+        // keep the message valid UTF-8
+        while !s.is_char_boundary(take) {
+            take -= 1;
+        }
+
+        self.buf[self.len..self.len + take].copy_from_slice(&s.as_bytes()[..take]);
+        self.len += take;
+        Ok(())
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// Checks a record survives an encode/parse round trip, and that truncation, a missing
+/// frame and a corrupt magic are handled.
+pub fn check_crash_log() {
+    // SAFETY: `TrapFrame` is plain `usize`s, all zeros is valid
+    let mut frame: TrapFrame = unsafe { core::mem::zeroed() };
+    frame.sepc = 0x8020_1234;
+    frame.gprs[10] = 42;
+
+    let mut buf = [0u8; MESSAGE_OFFSET + 64];
+    let len = encode(&mut buf, 3, Some(&frame), format_args!("oops at {}", 7))
+        .expect("Buffer too small for a record");
+    assert_eq!(len, MESSAGE_OFFSET + "oops at 7".len());
+
+    let record = CrashRecord::parse(&buf).expect("Failed to parse an encoded record");
+    assert_eq!(record.hart_id, 3);
+    assert_eq!(record.message, "oops at 7");
+    let decoded = record.frame.expect("Trap frame lost");
+    assert_eq!((decoded.sepc, decoded.gprs[10]), (0x8020_1234, 42));
+
+    // 5 bytes of room: the third 2-byte character must not be split
+    let mut short = [0u8; MESSAGE_OFFSET + 5];
+    encode(&mut short, 0, None, format_args!("ééé")).expect("Buffer too small for a record");
+    let record = CrashRecord::parse(&short).expect("Failed to parse a truncated record");
+    assert_eq!(record.message, "éé", "Truncation split a character");
+    assert!(record.frame.is_none(), "Absent trap frame decoded");
+
+    assert!(encode(&mut [0u8; MESSAGE_OFFSET - 1], 0, None, format_args!("")).is_none());
+
+    buf[0] ^= 0xff;
+    assert!(
+        CrashRecord::parse(&buf).is_none(),
+        "Record with a corrupt magic parsed"
+    );
+}
@@ -0,0 +1,314 @@
+//! Block device abstraction and a write-through block cache on top of it.
+
+use crate::collections::{DoublyLinkable, LruList, SinglyLinkable};
+use alloc::alloc::{alloc, dealloc};
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+/// Random-access storage addressed in fixed-size blocks.
+pub trait BlockDevice {
+    /// Size of a block in bytes.
+    fn block_size(&self) -> usize;
+
+    fn num_blocks(&self) -> u64;
+
+    /// Reads `block` into `buf`, which must be exactly `block_size` bytes long.
+    fn read_block(&mut self, block: u64, buf: &mut [u8]) -> Result<(), &'static str>;
+
+    /// Writes `buf`, exactly `block_size` bytes long, to `block`.
+    fn write_block(&mut self, block: u64, buf: &[u8]) -> Result<(), &'static str>;
+}
+
+/// A cached copy of one block, linked into the cache's LRU list.
+pub struct CachedBlock {
+    next: Option<NonNull<CachedBlock>>,
+    prev: Option<NonNull<CachedBlock>>,
+    block: u64,
+    data: NonNull<u8>,
+}
+
+unsafe impl SinglyLinkable for CachedBlock {
+    fn next(&self) -> Option<NonNull<Self>> {
+        self.next
+    }
+
+    fn set_next(&mut self, next: Option<NonNull<Self>>) {
+        self.next = next;
+    }
+}
+
+unsafe impl DoublyLinkable for CachedBlock {
+    fn prev(&self) -> Option<NonNull<Self>> {
+        self.prev
+    }
+
+    fn set_prev(&mut self, prev: Option<NonNull<Self>>) {
+        self.prev = prev;
+    }
+}
+
+/// Keeps up to `capacity` recently used blocks of a `BlockDevice` in kernel heap memory.
+///
+/// Writes go to the device before the cached copy is updated, so the cache never holds
+/// data the device doesn't. Lookups walk the LRU list, meant for small capacities.
+pub struct BlockCache<D: BlockDevice> {
+    device: D,
+    lru: LruList<CachedBlock>,
+    capacity: usize,
+    data_layout: Layout,
+    hits: usize,
+    misses: usize,
+}
+
+impl<D: BlockDevice> BlockCache<D> {
+    pub fn new(device: D, capacity: usize) -> Result<Self, &'static str> {
+        if capacity == 0 {
+            return Err("Block cache needs a capacity of at least one block");
+        }
+
+        let data_layout = Layout::from_size_align(device.block_size(), size_of::<u64>())
+            .map_err(|_| "Invalid block size")?;
+
+        Ok(Self {
+            device,
+            lru: LruList::new(),
+            capacity,
+            data_layout,
+            hits: 0,
+            misses: 0,
+        })
+    }
+
+    pub fn device(&self) -> &D {
+        &self.device
+    }
+
+    pub fn len(&self) -> usize {
+        self.lru.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lru.is_empty()
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    /// Returns the cached contents of `block`, reading it from the device on a miss.
+    pub fn read(&mut self, block: u64) -> Result<&[u8], &'static str> {
+        let node = match self.lookup(block) {
+            Some(node) => {
+                self.hits += 1;
+                node
+            }
+            None => {
+                self.misses += 1;
+                let node = self.take_node(block)?;
+
+                let buf = unsafe { block_data(node, self.data_layout.size()) };
+                if let Err(e) = self.device.read_block(block, buf) {
+                    self.free_node(node);
+                    return Err(e);
+                }
+                self.lru.insert(node);
+                node
+            }
+        };
+
+        Ok(unsafe { block_data(node, self.data_layout.size()) })
+    }
+
+    /// Writes `buf` through to the device, then caches it as the contents of `block`.
+    ///
+    /// `buf` must be exactly one block long.
+    pub fn write(&mut self, block: u64, buf: &[u8]) -> Result<(), &'static str> {
+        if buf.len() != self.data_layout.size() {
+            return Err("Buffer length does not match the block size");
+        }
+        self.device.write_block(block, buf)?;
+
+        let node = match self.lookup(block) {
+            Some(node) => node,
+            None => {
+                let node = self.take_node(block)?;
+                self.lru.insert(node);
+                node
+            }
+        };
+        unsafe { block_data(node, self.data_layout.size()) }.copy_from_slice(buf);
+
+        Ok(())
+    }
+
+    /// Drops the cached copy of `block`, if any.
+    pub fn invalidate(&mut self, block: u64) {
+        if let Some(node) = self.lookup(block) {
+            self.lru.remove(node);
+            self.free_node(node);
+        }
+    }
+
+    /// Finds `block` and marks it as the most recently used.
+    fn lookup(&mut self, block: u64) -> Option<NonNull<CachedBlock>> {
+        let node = self
+            .lru
+            .iter()
+            .find(|node| unsafe { node.as_ref() }.block == block)?;
+
+        self.lru.touch(node);
+        Some(node)
+    }
+
+    /// A detached node for `block`, recycling the least recently used one when full.
+    fn take_node(&mut self, block: u64) -> Result<NonNull<CachedBlock>, &'static str> {
+        if self.lru.len() >= self.capacity {
+            let mut node = self.lru.evict().expect("Full block cache is empty");
+            unsafe { node.as_mut() }.block = block;
+            return Ok(node);
+        }
+
+        let data = NonNull::new(unsafe { alloc(self.data_layout) })
+            .ok_or("Out of memory for a cached block")?;
+        let Some(node) =
+            NonNull::new(unsafe { alloc(Layout::new::<CachedBlock>()) }.cast::<CachedBlock>())
+        else {
+            unsafe { dealloc(data.as_ptr(), self.data_layout) };
+            return Err("Out of memory for a cached block");
+        };
+
+        unsafe {
+            node.write(CachedBlock {
+                next: None,
+                prev: None,
+                block,
+                data,
+            })
+        };
+        Ok(node)
+    }
+
+    /// Frees a node that is not linked into the LRU list.
+    fn free_node(&mut self, node: NonNull<CachedBlock>) {
+        unsafe {
+            dealloc(node.as_ref().data.as_ptr(), self.data_layout);
+            dealloc(node.as_ptr().cast(), Layout::new::<CachedBlock>());
+        }
+    }
+}
+
+/// The `len` bytes of block data owned by `node`.
+///
+/// # Safety
+///
+/// `node` must be a live node of a cache with `len`-byte blocks, and the returned slice
+/// must not outlive it or alias another borrow of the same data.
+unsafe fn block_data<'a>(node: NonNull<CachedBlock>, len: usize) -> &'a mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut(node.as_ref().data.as_ptr(), len) }
+}
+
+impl<D: BlockDevice> Drop for BlockCache<D> {
+    fn drop(&mut self) {
+        while let Some(node) = self.lru.evict() {
+            self.free_node(node);
+        }
+    }
+}
+
+/// In-memory device counting the reads that reach it, for `check_block_cache`.
+struct MockBlockDevice {
+    blocks: [[u8; MOCK_BLOCK_SIZE]; MOCK_BLOCKS],
+    reads: usize,
+}
+
+const MOCK_BLOCK_SIZE: usize = 16;
+const MOCK_BLOCKS: usize = 4;
+
+impl BlockDevice for MockBlockDevice {
+    fn block_size(&self) -> usize {
+        MOCK_BLOCK_SIZE
+    }
+
+    fn num_blocks(&self) -> u64 {
+        MOCK_BLOCKS as u64
+    }
+
+    fn read_block(&mut self, block: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        let data = self
+            .blocks
+            .get(block as usize)
+            .ok_or("Block out of range")?;
+        buf.copy_from_slice(data);
+        self.reads += 1;
+        Ok(())
+    }
+
+    fn write_block(&mut self, block: u64, buf: &[u8]) -> Result<(), &'static str> {
+        let data = self
+            .blocks
+            .get_mut(block as usize)
+            .ok_or("Block out of range")?;
+        data.copy_from_slice(buf);
+        Ok(())
+    }
+}
+
+/// Checks that hits are served from the cached buffer and misses and evictions go to
+/// the device. Needs the kernel heap.
+pub fn check_block_cache() {
+    let mut device = MockBlockDevice {
+        blocks: [[0; MOCK_BLOCK_SIZE]; MOCK_BLOCKS],
+        reads: 0,
+    };
+    for (i, block) in device.blocks.iter_mut().enumerate() {
+        block.fill(i as u8);
+    }
+
+    let mut cache = BlockCache::new(device, 2).expect("Failed to create the block cache");
+
+    let first = cache.read(1).expect("Miss failed").as_ptr();
+    assert_eq!(cache.device().reads, 1, "Miss did not reach the device");
+
+    let hit = cache.read(1).expect("Hit failed");
+    assert_eq!(hit, &[1; MOCK_BLOCK_SIZE], "Hit returned the wrong data");
+    assert_eq!(hit.as_ptr(), first, "Hit did not return the cached buffer");
+    assert_eq!(cache.device().reads, 1, "Hit reached the device");
+
+    // block 1 is now the least recently used and gets evicted by block 3
+    cache.read(2).expect("Miss failed");
+    cache.read(3).expect("Miss failed");
+    assert_eq!(cache.len(), 2);
+    cache.read(1).expect("Miss failed");
+    assert_eq!(
+        cache.device().reads,
+        4,
+        "Evicted block was served from the cache"
+    );
+
+    cache
+        .write(3, &[7; MOCK_BLOCK_SIZE])
+        .expect("Write through failed");
+    assert_eq!(
+        cache.device().blocks[3],
+        [7; MOCK_BLOCK_SIZE],
+        "Write did not reach the device"
+    );
+    assert_eq!(cache.read(3).expect("Hit failed"), &[7; MOCK_BLOCK_SIZE]);
+
+    assert!(
+        cache.write(2, &[9; MOCK_BLOCK_SIZE - 1]).is_err(),
+        "Short write accepted"
+    );
+    assert_eq!(
+        cache.device().blocks[2],
+        [2; MOCK_BLOCK_SIZE],
+        "Short write reached the device"
+    );
+
+    assert_eq!(cache.hits(), 2);
+    assert_eq!(cache.misses(), 4);
+}
@@ -0,0 +1,49 @@
+pub mod block;
+pub mod timer;
+
+use crate::drivers::{Clint, Uart, VirtioBlk};
+use crate::sync::{
+    IrqSaveGuard, OnceLock, ReentrantSpinlock, ReentrantSpinlockGuard, Spinlock, SpinlockGuard,
+};
+
+/// Base of the FDT's `/chosen` stdout device, used by the panic path until (or unless) a
+/// UART is probed.
+pub static EARLY_UART_ADDRESS: OnceLock<usize> = OnceLock::new();
+
+// lock-free copy of the console for the panic path
+pub static _UART_PANIC_DEVICE: OnceLock<Uart> = OnceLock::new();
+// reentrant so a hart panicking mid-print can still print the panic message
+pub static UART_INSTANCE: OnceLock<ReentrantSpinlock<Uart>> = OnceLock::new();
+
+pub fn uart() -> ReentrantSpinlockGuard<'static, Uart> {
+    UART_INSTANCE
+        .get()
+        .expect("UART driver not initialized")
+        .lock()
+}
+
+/// Like `uart`, with interrupts disabled on this hart until the guard is dropped.
+pub fn uart_irqsave() -> IrqSaveGuard<ReentrantSpinlockGuard<'static, Uart>> {
+    UART_INSTANCE
+        .get()
+        .expect("UART driver not initialized")
+        .lock_irqsave()
+}
+
+pub static CLINT_INSTANCE: OnceLock<Spinlock<Clint>> = OnceLock::new();
+
+pub fn clint() -> SpinlockGuard<'static, Clint> {
+    CLINT_INSTANCE
+        .get()
+        .expect("CLINT driver not initialized")
+        .lock()
+}
+
+pub static VIRTIO_BLK_INSTANCE: OnceLock<Spinlock<VirtioBlk>> = OnceLock::new();
+
+pub fn virtio_blk() -> SpinlockGuard<'static, VirtioBlk> {
+    VIRTIO_BLK_INSTANCE
+        .get()
+        .expect("virtio-blk driver not initialized")
+        .lock()
+}
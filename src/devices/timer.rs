@@ -0,0 +1,258 @@
+use crate::cpu::{ack_timer_interrupt, current_hart_id};
+use crate::devices::clint;
+use crate::sync::Spinlock;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub const TIMER_WHEEL_CAPACITY: usize = 32;
+
+/// Deadline that never fires, used to disarm the timer when nothing is scheduled.
+pub const NO_DEADLINE: u64 = u64::MAX;
+
+#[derive(Debug, Clone, Copy)]
+pub struct TimerEntry {
+    pub deadline: u64,
+    /// `Some(interval)` re-arms the entry `interval` ticks after each deadline.
+    pub interval: Option<u64>,
+    pub callback: fn(),
+}
+
+/// A fixed-capacity set of timer deadlines (in `mtime` ticks), kept sorted so the
+/// nearest deadline is always at the front.
+pub struct TimerWheel {
+    entries: [Option<TimerEntry>; TIMER_WHEEL_CAPACITY],
+    len: usize,
+}
+
+impl TimerWheel {
+    pub const fn new() -> Self {
+        Self {
+            entries: [None; TIMER_WHEEL_CAPACITY],
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Inserts an entry keeping the wheel sorted by deadline.
+    /// Entries with equal deadlines fire in insertion order.
+    pub fn insert(&mut self, entry: TimerEntry) -> Result<(), &'static str> {
+        if self.len == TIMER_WHEEL_CAPACITY {
+            return Err("Timer wheel is full");
+        }
+
+        let idx = self.entries[..self.len]
+            .iter()
+            .flatten()
+            .position(|e| e.deadline > entry.deadline)
+            .unwrap_or(self.len);
+
+        self.entries.copy_within(idx..self.len, idx + 1);
+        self.entries[idx] = Some(entry);
+        self.len += 1;
+
+        Ok(())
+    }
+
+    /// Nearest pending deadline, or `None` if the wheel is empty.
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.entries[0].map(|entry| entry.deadline)
+    }
+
+    /// Removes and returns the nearest entry if it is due at `now`.
+    ///
+    /// Periodic entries are re-armed before being returned.
+    pub fn pop_due(&mut self, now: u64) -> Option<TimerEntry> {
+        let entry = self.entries[0].filter(|entry| entry.deadline <= now)?;
+
+        self.entries.copy_within(1..self.len, 0);
+        self.len -= 1;
+        self.entries[self.len] = None;
+
+        if let Some(interval) = entry.interval {
+            let rearmed = TimerEntry {
+                // skip missed periods instead of firing them back to back
+                deadline: entry
+                    .deadline
+                    .saturating_add(interval)
+                    .max(now.saturating_add(1)),
+                ..entry
+            };
+            // can't fail, a slot was just freed
+            self.insert(rearmed).ok();
+        }
+
+        Some(entry)
+    }
+}
+
+impl Default for TimerWheel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub static TIMER_WHEEL: Spinlock<TimerWheel> = Spinlock::new(TimerWheel::new());
+
+/// Runs `callback` once `mtime` reaches `deadline`.
+pub fn schedule_at(deadline: u64, callback: fn()) -> Result<(), &'static str> {
+    TIMER_WHEEL.lock().insert(TimerEntry {
+        deadline,
+        interval: None,
+        callback,
+    })?;
+    reprogram();
+    Ok(())
+}
+
+/// Runs `callback` every `interval` ticks, starting `interval` ticks from now.
+pub fn schedule_periodic(interval: u64, callback: fn()) -> Result<(), &'static str> {
+    assert!(interval > 0, "Periodic timer interval must be non-zero");
+
+    let now = clint().mtime();
+    TIMER_WHEEL.lock().insert(TimerEntry {
+        deadline: now.saturating_add(interval),
+        interval: Some(interval),
+        callback,
+    })?;
+    reprogram();
+    Ok(())
+}
+
+/// Fires every callback due at `now` and programs `mtimecmp` for the nearest remaining deadline.
+///
+/// Called from the timer interrupt handler.
+pub fn tick(now: u64) {
+    loop {
+        // don't hold the lock while running callbacks, they may schedule new timers
+        let Some(entry) = TIMER_WHEEL.lock().pop_due(now) else {
+            break;
+        };
+        (entry.callback)();
+    }
+
+    reprogram();
+}
+
+/// Programs the current hart's `mtimecmp` for the nearest deadline,
+/// or disarms the timer if the wheel is empty.
+fn reprogram() {
+    let deadline = TIMER_WHEEL.lock().next_deadline().unwrap_or(NO_DEADLINE);
+    clint().schedule_timer_interrupt(current_hart_id(), deadline);
+    // the interrupt reaches S-mode through M-mode, which holds it until acked
+    ack_timer_interrupt();
+}
+
+/// Checks a `TimerWheel` against a mock clock: deadline order, equal deadlines in insertion
+/// order, `pop_due` leaving entries that are not due, periodic re-arming (also next to
+/// `u64::MAX`) and a full wheel.
+pub fn check_timer_wheel() {
+    // tells entries apart by the callback they carry
+    static FIRED: AtomicUsize = AtomicUsize::new(0);
+    fn first() {
+        FIRED.store(1, Ordering::Relaxed);
+    }
+    fn second() {
+        FIRED.store(2, Ordering::Relaxed);
+    }
+    fn third() {
+        FIRED.store(3, Ordering::Relaxed);
+    }
+
+    let fired = |entry: Option<TimerEntry>| {
+        let entry = entry.expect("Due entry not popped");
+        (entry.callback)();
+        (entry.deadline, FIRED.load(Ordering::Relaxed))
+    };
+    let once = |deadline, callback| TimerEntry {
+        deadline,
+        interval: None,
+        callback,
+    };
+
+    let mut wheel = TimerWheel::new();
+    for entry in [
+        once(30, first),
+        once(10, second),
+        once(20, third),
+        once(20, first),
+    ] {
+        wheel.insert(entry).expect("Wheel full below capacity");
+    }
+    assert_eq!(wheel.next_deadline(), Some(10));
+
+    assert!(
+        wheel.pop_due(5).is_none(),
+        "Entry popped before its deadline"
+    );
+    assert_eq!(fired(wheel.pop_due(20)), (10, 2));
+    assert_eq!(
+        fired(wheel.pop_due(20)),
+        (20, 3),
+        "Equal deadlines out of order"
+    );
+    assert_eq!(
+        fired(wheel.pop_due(20)),
+        (20, 1),
+        "Equal deadlines out of order"
+    );
+    assert!(
+        wheel.pop_due(20).is_none(),
+        "Entry popped before its deadline"
+    );
+    assert_eq!(wheel.len(), 1);
+
+    let periodic = |deadline| TimerEntry {
+        deadline,
+        interval: Some(10),
+        callback: second,
+    };
+    wheel
+        .insert(periodic(40))
+        .expect("Wheel full below capacity");
+    assert_eq!(fired(wheel.pop_due(40)), (30, 1));
+    assert_eq!(fired(wheel.pop_due(40)), (40, 2));
+    assert_eq!(
+        wheel.next_deadline(),
+        Some(50),
+        "Periodic entry not re-armed"
+    );
+    assert_eq!(wheel.len(), 1);
+
+    // missed periods are skipped, not fired back to back
+    assert_eq!(fired(wheel.pop_due(95)), (50, 2));
+    assert_eq!(wheel.next_deadline(), Some(96));
+    assert!(wheel.pop_due(95).is_none());
+
+    let mut wheel = TimerWheel::new();
+    wheel
+        .insert(periodic(u64::MAX - 1))
+        .expect("Wheel full below capacity");
+    assert_eq!(fired(wheel.pop_due(u64::MAX)), (u64::MAX - 1, 2));
+    assert_eq!(
+        wheel.next_deadline(),
+        Some(u64::MAX),
+        "Re-arm did not saturate"
+    );
+
+    let mut wheel = TimerWheel::new();
+    for deadline in 0..TIMER_WHEEL_CAPACITY as u64 {
+        wheel
+            .insert(periodic(deadline))
+            .expect("Wheel full below capacity");
+    }
+    assert!(
+        wheel.insert(once(0, first)).is_err(),
+        "Full wheel took an entry"
+    );
+    assert_eq!(wheel.len(), TIMER_WHEEL_CAPACITY);
+    // a full wheel still re-arms into the slot the popped entry left
+    assert_eq!(fired(wheel.pop_due(0)), (0, 2));
+    assert_eq!(wheel.len(), TIMER_WHEEL_CAPACITY);
+    assert_eq!(wheel.next_deadline(), Some(1));
+}
@@ -1,6 +1,12 @@
 use super::{Device, Driver};
 use crate::devices::CLINT_INSTANCE;
+use crate::devices::timer::{self, NO_DEADLINE};
+use crate::memory::frame::BASE_SIZE;
+use crate::memory::frame_allocator;
+use crate::memory::hart_cache::MAX_HARTS;
 use crate::sync::Spinlock;
+use crate::trap::{JIFFY_US, default_timer_handler};
+use core::alloc::Layout;
 use core::ptr::{read_volatile, write_volatile};
 
 pub const MTIMECMP_OFFSET: usize = 0x4000;
@@ -9,18 +15,54 @@ pub const MTIME_OFFSET: usize = 0xBFF8;
 pub const MSIP_HART_STRIDE: usize = 4;
 pub const MTIMECMP_HART_STRIDE: usize = 8;
 
+// MSIP is 32-bit, MTIMECMP and MTIME are 64-bit
+const _: () = assert!(MSIP_HART_STRIDE == size_of::<u32>());
+const _: () = assert!(MTIMECMP_HART_STRIDE == size_of::<u64>());
+const _: () = assert!(Clint::new(0x200_0000).register_address(Clint::msip_offset(1)) == 0x200_0004);
+const _: () =
+    assert!(Clint::new(0x200_0000).register_address(Clint::mtimecmp_offset(2)) == 0x200_4010);
+
+/// `mtime` ticks per second on QEMU `virt` (`/cpus/timebase-frequency`).
+pub const TIMEBASE_FREQUENCY: u64 = 10_000_000;
+
+/// Converts microseconds to `mtime` ticks.
+pub const fn us_to_ticks(us: u64) -> u64 {
+    us * (TIMEBASE_FREQUENCY / 1_000_000)
+}
+
 pub struct Clint {
     base_address: usize,
 }
 
 impl Clint {
-    pub fn new(base_address: usize) -> Self {
+    pub const fn new(base_address: usize) -> Self {
         Self { base_address }
     }
 
+    pub const fn register_address(&self, offset: usize) -> usize {
+        self.base_address + offset
+    }
+
+    const fn msip_offset(hart_id: usize) -> usize {
+        MSIP_HART_STRIDE * hart_id
+    }
+
+    const fn mtimecmp_offset(hart_id: usize) -> usize {
+        MTIMECMP_OFFSET + MTIMECMP_HART_STRIDE * hart_id
+    }
+
+    /// Reads the register at `offset`, `T` gives its width.
+    fn read_register<T: Copy>(&self, offset: usize) -> T {
+        unsafe { read_volatile(self.register_address(offset) as *const T) }
+    }
+
+    /// Writes the register at `offset`, `T` gives its width.
+    fn write_register<T: Copy>(&self, offset: usize, value: T) {
+        unsafe { write_volatile(self.register_address(offset) as *mut T, value) }
+    }
+
     pub fn mtime(&self) -> u64 {
-        let mtime_ptr = (self.base_address + MTIME_OFFSET) as *const u64; // MTIME is 64-bit
-        unsafe { read_volatile(mtime_ptr) }
+        self.read_register(MTIME_OFFSET)
     }
 
     pub fn trigger_software_interrupt(&self, hart_id: usize) {
@@ -32,18 +74,26 @@ impl Clint {
     }
 
     pub fn schedule_timer_interrupt(&self, hart_id: usize, time: u64) {
-        let mtimecmp_ptr =
-            (self.base_address + MTIMECMP_OFFSET + MTIMECMP_HART_STRIDE * hart_id) as *mut u64; // MTIMECMP is 64-bit
-        unsafe {
-            write_volatile(mtimecmp_ptr, time);
-        }
+        self.write_register(Self::mtimecmp_offset(hart_id), time);
+    }
+
+    /// Fires the hart's timer interrupt `us` microseconds from now.
+    pub fn schedule_after_us(&self, hart_id: usize, us: u64) {
+        let deadline = self.mtime().saturating_add(us_to_ticks(us));
+        self.schedule_timer_interrupt(hart_id, deadline);
+    }
+
+    pub fn mtimecmp(&self, hart_id: usize) -> u64 {
+        self.read_register(Self::mtimecmp_offset(hart_id))
+    }
+
+    /// Ticks left until the hart's pending timer interrupt fires, `0` if it is already due.
+    pub fn read_time_remaining(&self, hart_id: usize) -> u64 {
+        self.mtimecmp(hart_id).saturating_sub(self.mtime())
     }
 
     fn write_msip(&self, hart_id: usize, value: u32) {
-        let msip_ptr = (self.base_address + MSIP_HART_STRIDE * hart_id) as *mut u32; // MSIP is 32-bit
-        unsafe {
-            write_volatile(msip_ptr, value);
-        }
+        self.write_register(Self::msip_offset(hart_id), value);
     }
 }
 
@@ -59,6 +109,9 @@ impl Driver for ClintDriver {
 
         CLINT_INSTANCE.get_or_init(|| Spinlock::new(device));
 
+        timer::schedule_periodic(us_to_ticks(JIFFY_US), default_timer_handler)
+            .expect("Failed to schedule the jiffy timer");
+
         let driver_type = self.compatibility()[0];
         println!(
             "[ OK ] CLINT ({}): successfully initialized at {:#x}",
@@ -70,14 +123,70 @@ impl Driver for ClintDriver {
         &["riscv,clint0"]
     }
 
+    fn shutdown(&self) {
+        let Some(clint) = CLINT_INSTANCE.get() else {
+            return;
+        };
+        let clint = clint.lock();
+
+        for hart_id in 0..MAX_HARTS {
+            clint.schedule_timer_interrupt(hart_id, NO_DEADLINE);
+            clint.clear_software_interrupt(hart_id);
+        }
+    }
+
     fn probe(&self, node: &fdt::node::FdtNode) -> Option<Self::Device> {
         if !self.is_compatible(node) {
             return None;
         }
 
-        let base_addr = node.reg()?.next()?.starting_address;
-        let clint = Clint::new(base_addr as usize);
+        let base_addr = super::mmio_base(node)?;
+        let clint = Clint::new(base_addr);
 
         Some(clint)
     }
 }
+
+/// Checks every register is accessed at its offset and width, over RAM standing in for
+/// the CLINT. Needs the frame allocator.
+pub fn check_clint_registers() {
+    let layout = Layout::from_size_align(MTIME_OFFSET + size_of::<u64>(), BASE_SIZE)
+        .expect("Invalid mock CLINT layout");
+    let mock = frame_allocator()
+        .alloc_zeroed(layout)
+        .expect("Failed to allocate the mock CLINT");
+    let clint = Clint::new(mock.as_ptr() as usize);
+    let bytes = unsafe { core::slice::from_raw_parts_mut(mock.as_ptr(), layout.size()) };
+
+    // only the bytes of the addressed register may change
+    let written = |bytes: &[u8], offset: usize, width: usize| {
+        bytes
+            .iter()
+            .enumerate()
+            .all(|(i, &byte)| (byte != 0) == (offset..offset + width).contains(&i))
+    };
+
+    clint.write_msip(3, u32::MAX);
+    assert!(
+        written(bytes, 3 * MSIP_HART_STRIDE, size_of::<u32>()),
+        "MSIP of hart 3 misplaced or resized"
+    );
+    clint.clear_software_interrupt(3);
+
+    clint.schedule_timer_interrupt(2, u64::MAX);
+    assert!(
+        written(
+            bytes,
+            MTIMECMP_OFFSET + 2 * MTIMECMP_HART_STRIDE,
+            size_of::<u64>()
+        ),
+        "MTIMECMP of hart 2 misplaced or resized"
+    );
+    assert_eq!(clint.mtimecmp(2), u64::MAX);
+    clint.schedule_timer_interrupt(2, 0);
+
+    bytes[MTIME_OFFSET..].copy_from_slice(&0x1122_3344_5566_7788u64.to_le_bytes());
+    assert_eq!(clint.mtime(), 0x1122_3344_5566_7788, "MTIME read narrowed");
+
+    frame_allocator().dealloc(mock, layout);
+}
@@ -1,9 +1,12 @@
 pub mod clint;
 pub mod uart;
+pub mod virtio_blk;
 
 pub use clint::{Clint, ClintDriver};
 pub use uart::{Uart, UartDriver};
+pub use virtio_blk::{VirtioBlk, VirtioBlkDriver};
 
+use core::sync::atomic::{AtomicUsize, Ordering};
 use fdt::node::FdtNode;
 
 pub trait Driver {
@@ -15,6 +18,11 @@ pub trait Driver {
 
     fn probe(&self, node: &FdtNode) -> Option<Self::Device>;
 
+    /// Quiesces the global device before a reboot or reconfiguration.
+    ///
+    /// Must be a no-op if `init_global` never ran.
+    fn shutdown(&self) {}
+
     fn is_compatible(&self, node: &FdtNode) -> bool {
         let compatibility_list = match node.compatible() {
             Some(list) => list,
@@ -28,6 +36,61 @@ pub trait Driver {
 
 pub trait Device {}
 
+/// `true` if `address` is representable in a `pointer_bits`-wide `usize`.
+pub const fn fits_pointer(address: u64, pointer_bits: u32) -> bool {
+    pointer_bits >= u64::BITS || address >> pointer_bits == 0
+}
+
+// a base above 4 GiB is only reachable with 64-bit pointers
+const _: () = assert!(!fits_pointer(0x1_0000_0000, 32));
+const _: () = assert!(fits_pointer(0xffff_f000, 32));
+const _: () = assert!(fits_pointer(0x1_0000_0000, 64));
+
+/// Base address of the first `reg` entry of `node`, `None` if there is none or it does not
+/// fit in a `usize`, in which case the device must not be initialized.
+pub fn mmio_base(node: &FdtNode) -> Option<usize> {
+    let address = reg_address(node)?;
+
+    if !fits_pointer(address, usize::BITS) {
+        let message = format_args!(
+            "{}: MMIO base {:#x} is out of the address space, skipping",
+            node.name, address
+        );
+        // the UART is probed before there is a console to warn on
+        if crate::printing::console_available() {
+            log_warn!("{}", message);
+        } else {
+            crate::printing::_early_print(format_args!("[WARN] {}\n", message));
+        }
+        return None;
+    }
+
+    Some(address as usize)
+}
+
+/// The full first `reg` address of `node`.
+///
+/// The `fdt` crate hands it out already cast to a pointer. That is lossless with 64-bit
+/// pointers; with narrower ones only the low cell of a 2-cell address survives, so the
+/// high cell is read back from the raw property.
+pub(crate) fn reg_address(node: &FdtNode) -> Option<u64> {
+    let cast = node.reg()?.next()?.starting_address as usize as u64;
+    if fits_pointer(u64::MAX, usize::BITS) {
+        return Some(cast);
+    }
+
+    // with a single address cell the first cell is the address itself
+    let first_cell = node
+        .property("reg")
+        .and_then(|reg| reg.value.first_chunk::<4>())
+        .map(|&cell| u32::from_be_bytes(cell) as u64)?;
+    if first_cell == 0 || first_cell == cast {
+        return Some(cast);
+    }
+
+    Some(first_cell << 32 | cast)
+}
+
 macro_rules! probe_all_drivers {
     ($fdt_node:expr, $($driver:expr),+ $(,)?) => {
         // This code block will be expanded by the macro
@@ -39,9 +102,217 @@ macro_rules! probe_all_drivers {
     };
 }
 
+/// Probes every FDT node against the known drivers.
+///
+/// The UART gets a pass of its own first, other drivers may print from `init_global`
+/// and the FDT node order says nothing about which device comes up first.
 pub fn probe_and_init_devices(fdt: &fdt::Fdt) {
-    // TODO: make sure UART always initialized first
+    // lets the panic path reach the console should probing it fail
+    if let Some(address) = fdt.chosen().stdout().as_ref().and_then(mmio_base) {
+        crate::devices::EARLY_UART_ADDRESS.set(address).ok();
+    }
+
+    probe_in_passes(
+        fdt,
+        &[
+            &|node: &FdtNode| probe_all_drivers!(node, &UartDriver),
+            &|node: &FdtNode| probe_all_drivers!(node, &ClintDriver),
+        ],
+    );
+
+    if !crate::printing::console_available() {
+        // `println!` would panic without a console, try the `/chosen` stdout UART instead
+        crate::printing::_early_print(format_args!(
+            "[WARN] No compatible UART found in the FDT, console output is unavailable\n"
+        ));
+    }
+}
+
+/// Runs every pass over all FDT nodes, a pass only starts once the previous one is done.
+fn probe_in_passes(fdt: &fdt::Fdt, passes: &[&dyn Fn(&FdtNode)]) {
+    for pass in passes {
+        for node in fdt.all_nodes() {
+            pass(&node);
+        }
+    }
+}
+
+/// Probes drivers that need the memory subsystem (e.g. DMA), must run after `memory::init`.
+pub fn probe_and_init_late_devices(fdt: &fdt::Fdt) {
     for node in fdt.all_nodes() {
-        probe_all_drivers!(&node, &UartDriver, &ClintDriver);
+        probe_all_drivers!(&node, &VirtioBlkDriver);
+    }
+}
+
+macro_rules! shutdown_all_drivers {
+    ($($driver:expr),+ $(,)?) => {
+        $(
+            $driver.shutdown();
+        )+
+    };
+}
+
+/// Shuts down every driver, in reverse probe order so the console goes last.
+pub fn shutdown_all() {
+    shutdown_all_drivers!(VirtioBlkDriver, ClintDriver, UartDriver);
+}
+
+/// Driver matching a made up `compatible`, recording when its device was initialized and
+/// how often it was shut down.
+struct MockDriver<'a> {
+    compatible: &'static [&'static str],
+    sequence: &'a AtomicUsize,
+    initialized_at: AtomicUsize,
+    shutdowns: AtomicUsize,
+}
+
+struct MockDevice;
+
+impl Device for MockDevice {}
+
+impl<'a> MockDriver<'a> {
+    fn new(compatible: &'static [&'static str], sequence: &'a AtomicUsize) -> Self {
+        Self {
+            compatible,
+            sequence,
+            initialized_at: AtomicUsize::new(0),
+            shutdowns: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Driver for MockDriver<'_> {
+    type Device = MockDevice;
+
+    fn init_global(&self, _device: MockDevice) {
+        let at = self.sequence.fetch_add(1, Ordering::Relaxed) + 1;
+        self.initialized_at.store(at, Ordering::Relaxed);
+    }
+
+    fn compatibility(&self) -> &'static [&'static str] {
+        self.compatible
+    }
+
+    fn probe(&self, node: &FdtNode) -> Option<MockDevice> {
+        self.is_compatible(node).then_some(MockDevice)
+    }
+
+    fn shutdown(&self) {
+        self.shutdowns.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Writes a flattened device tree with one child of the root per `(name, compatible)`,
+/// in order, into `buf`, which must be zeroed. Returns the blob.
+fn build_test_fdt<'a>(buf: &'a mut [u8], nodes: &[(&str, &str)]) -> &'a [u8] {
+    const HEADER_SIZE: usize = 40;
+    // right after the header, a lone terminating entry
+    const MEM_RSVMAP_SIZE: usize = 16;
+    const STRUCT_OFFSET: usize = HEADER_SIZE + MEM_RSVMAP_SIZE;
+    const STRINGS: &[u8] = b"compatible\0";
+
+    const FDT_MAGIC: u32 = 0xd00d_feed;
+    const FDT_BEGIN_NODE: u32 = 1;
+    const FDT_END_NODE: u32 = 2;
+    const FDT_PROP: u32 = 3;
+    const FDT_END: u32 = 9;
+
+    let mut len = STRUCT_OFFSET;
+    let mut put = |buf: &mut [u8], bytes: &[u8], nul: bool| {
+        buf[len..len + bytes.len()].copy_from_slice(bytes);
+        len = (len + bytes.len() + nul as usize).next_multiple_of(4);
+    };
+
+    put(buf, &FDT_BEGIN_NODE.to_be_bytes(), false);
+    put(buf, b"", true);
+    for (name, compatible) in nodes {
+        put(buf, &FDT_BEGIN_NODE.to_be_bytes(), false);
+        put(buf, name.as_bytes(), true);
+        put(buf, &FDT_PROP.to_be_bytes(), false);
+        put(buf, &(compatible.len() as u32 + 1).to_be_bytes(), false);
+        // offset of "compatible" in the strings block
+        put(buf, &0u32.to_be_bytes(), false);
+        put(buf, compatible.as_bytes(), true);
+        put(buf, &FDT_END_NODE.to_be_bytes(), false);
+    }
+    put(buf, &FDT_END_NODE.to_be_bytes(), false);
+    put(buf, &FDT_END.to_be_bytes(), false);
+
+    let strings_offset = len;
+    let total_size = strings_offset + STRINGS.len();
+    buf[strings_offset..total_size].copy_from_slice(STRINGS);
+
+    let header = [
+        FDT_MAGIC,
+        total_size as u32,
+        STRUCT_OFFSET as u32,
+        strings_offset as u32,
+        HEADER_SIZE as u32,
+        // version, last compatible version, boot CPU
+        17,
+        16,
+        0,
+        STRINGS.len() as u32,
+        (strings_offset - STRUCT_OFFSET) as u32,
+    ];
+    for (field, value) in buf.chunks_exact_mut(4).zip(header) {
+        field.copy_from_slice(&value.to_be_bytes());
+    }
+
+    &buf[..total_size]
+}
+
+/// Checks the console is initialized before other drivers even when its node comes last
+/// in the FDT, so they can print from `init_global`.
+pub fn check_probe_order() {
+    let mut buf = [0u8; 512];
+    let blob = build_test_fdt(
+        &mut buf,
+        &[
+            ("clint@2000000", "test,clint"),
+            ("serial@10000000", "test,uart"),
+        ],
+    );
+    let fdt = fdt::Fdt::new(blob).expect("Failed to parse the test FDT");
+
+    let sequence = AtomicUsize::new(0);
+    let uart = MockDriver::new(&["test,uart"], &sequence);
+    let clint = MockDriver::new(&["test,clint"], &sequence);
+    probe_in_passes(
+        &fdt,
+        &[
+            &|node: &FdtNode| probe_all_drivers!(node, &uart),
+            &|node: &FdtNode| probe_all_drivers!(node, &clint),
+        ],
+    );
+
+    assert_eq!(
+        (
+            uart.initialized_at.load(Ordering::Relaxed),
+            clint.initialized_at.load(Ordering::Relaxed)
+        ),
+        (1, 2),
+        "Console not initialized first"
+    );
+}
+
+/// Checks the driver shutdown sequence reaches every driver exactly once.
+pub fn check_shutdown_all() {
+    let sequence = AtomicUsize::new(0);
+    let drivers = [
+        MockDriver::new(&["test,disk"], &sequence),
+        MockDriver::new(&["test,clint"], &sequence),
+        MockDriver::new(&["test,uart"], &sequence),
+    ];
+
+    shutdown_all_drivers!(drivers[0], drivers[1], drivers[2]);
+
+    for driver in &drivers {
+        assert_eq!(
+            driver.shutdowns.load(Ordering::Relaxed),
+            1,
+            "{} shut down the wrong number of times",
+            driver.compatible[0]
+        );
     }
 }
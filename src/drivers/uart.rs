@@ -1,25 +1,69 @@
 use super::{Device, Driver};
-use crate::{devices::_UART_PANIC_ADDRESS, println};
-use crate::{devices::UART_INSTANCE, sync::Spinlock};
+use crate::{devices::_UART_PANIC_DEVICE, println};
+use crate::{devices::UART_INSTANCE, sync::ReentrantSpinlock};
 
+use core::cell::Cell;
 use core::fmt;
 use core::ptr::{read_volatile, write_volatile};
 use embedded_io::{Error, ErrorKind, ErrorType, Write};
 use fdt::node::FdtNode;
 
+const THR_OFFSET: usize = 0;
+// divisor latch, in place of THR/IER while LCR.DLAB is set
+const DLL_OFFSET: usize = 0;
+const DLM_OFFSET: usize = 1;
+// IIR on read, FCR on write
+const IIR_OFFSET: usize = 2;
+const FCR_OFFSET: usize = 2;
+const LCR_OFFSET: usize = 3;
 const LSR_OFFSET: usize = 5;
 const LSR_TX_EMPTY: u8 = 1 << 5;
+const LSR_TX_IDLE: u8 = 1 << 6;
+
+/// divisor latch access bit
+const LCR_DLAB: u8 = 1 << 7;
+/// 8 data bits, no parity, 1 stop bit
+const LCR_8N1: u8 = 0b0000_0011;
+
+/// Baud rate programmed when the FDT node gives the input clock.
+pub const DEFAULT_BAUD_RATE: u32 = 115_200;
+
+/// enable both FIFOs and clear them
+const FCR_ENABLE_FIFOS: u8 = 0b0000_0111;
+/// both bits read back as set once the FIFOs are enabled
+const IIR_FIFOS_ENABLED: u8 = 0b1100_0000;
+/// ns16550a TX FIFO size
+const TX_FIFO_DEPTH: usize = 16;
+
+// ns16550a defaults, used when the FDT node doesn't say otherwise (e.g. QEMU virt)
+const DEFAULT_REG_SHIFT: u32 = 0;
+const DEFAULT_REG_IO_WIDTH: u8 = 1;
+
+// register offsets must scale with `reg-shift`, e.g. LSR of a 32-bit spaced UART
+const _: () =
+    assert!(Uart::with_layout(0x1000_0000, 2, 4).register_address(LSR_OFFSET) == 0x1000_0014);
+
+// the classic 1.8432 MHz crystal divides evenly, faster clocks round down
+const _: () = assert!(baud_divisor(115_200, 1_843_200) == 1);
+const _: () = assert!(baud_divisor(115_200, 18_432_000) == 10);
+const _: () = assert!(baud_divisor(115_200, 50_000_000) == 27);
+const _: () = assert!(baud_divisor(9_600, 1_843_200) == 12);
 
 pub struct UartDriver;
 
 impl Driver for UartDriver {
     type Device = Uart;
 
-    fn init_global(&self, device: Self::Device) {
+    fn init_global(&self, mut device: Self::Device) {
         let addr = device.base_address;
+        // without the input clock the firmware's setting is kept
+        if let Some(clock_hz) = device.clock_hz {
+            device.set_baud_rate(DEFAULT_BAUD_RATE, clock_hz);
+        }
+        device.enable_fifo();
 
-        _UART_PANIC_ADDRESS.get_or_init(|| addr);
-        UART_INSTANCE.get_or_init(|| Spinlock::new(device));
+        _UART_PANIC_DEVICE.get_or_init(|| device);
+        UART_INSTANCE.get_or_init(|| ReentrantSpinlock::new(device));
 
         let driver_type = self.compatibility()[0];
         println!(
@@ -32,44 +76,191 @@ impl Driver for UartDriver {
         &["ns16550a", "riscv,ns16550a"]
     }
 
+    fn shutdown(&self) {
+        if let Some(uart) = UART_INSTANCE.get() {
+            uart.lock().flush_blocking();
+        }
+    }
+
     fn probe(&self, node: &FdtNode) -> Option<Self::Device> {
         if !self.is_compatible(node) {
             return None;
         }
 
-        let base_addr = node.reg()?.next()?.starting_address;
-        let uart = Uart::new(base_addr as usize);
+        let base_addr = super::mmio_base(node)?;
+
+        let reg_shift = node
+            .property("reg-shift")
+            .and_then(|prop| prop.as_usize())
+            .map_or(DEFAULT_REG_SHIFT, |shift| shift as u32);
+        let reg_io_width = node
+            .property("reg-io-width")
+            .and_then(|prop| prop.as_usize())
+            .map_or(DEFAULT_REG_IO_WIDTH, |width| width as u8);
+
+        let clock_hz = node
+            .property("clock-frequency")
+            .and_then(|prop| prop.as_usize())
+            .and_then(|clock| u32::try_from(clock).ok());
 
-        Some(uart)
+        // no console to complain on yet, an unsupported UART is simply not probed
+        if !matches!(reg_io_width, 1 | 4) {
+            return None;
+        }
+
+        let uart = Uart::with_layout(base_addr, reg_shift, reg_io_width);
+        Some(match clock_hz {
+            Some(clock_hz) => uart.with_clock(clock_hz),
+            None => uart,
+        })
     }
 }
 
+#[derive(Debug, Clone, Copy)]
 pub struct Uart {
     pub base_address: usize,
+    /// Registers are spaced `1 << reg_shift` bytes apart (FDT `reg-shift`).
+    reg_shift: u32,
+    /// Access width of each register in bytes, 1 or 4 (FDT `reg-io-width`).
+    reg_io_width: u8,
+    /// Bytes that may be written after a single "THR empty" check, 1 until the FIFO is enabled.
+    tx_burst: usize,
+    /// Input clock in Hz (FDT `clock-frequency`), `None` if unknown.
+    clock_hz: Option<u32>,
 }
 
 impl Device for Uart {}
 
 impl Uart {
-    pub fn new(base_address: usize) -> Self {
-        Self { base_address }
+    /// A byte-wide UART with packed registers, as on QEMU virt.
+    pub const fn new(base_address: usize) -> Self {
+        Self::with_layout(base_address, DEFAULT_REG_SHIFT, DEFAULT_REG_IO_WIDTH)
+    }
+
+    pub const fn with_layout(base_address: usize, reg_shift: u32, reg_io_width: u8) -> Self {
+        Self {
+            base_address,
+            reg_shift,
+            reg_io_width,
+            tx_burst: 1,
+            clock_hz: None,
+        }
+    }
+
+    pub const fn with_clock(mut self, clock_hz: u32) -> Self {
+        self.clock_hz = Some(clock_hz);
+        self
+    }
+
+    /// Address of the register at ns16550a register `offset`.
+    pub const fn register_address(&self, offset: usize) -> usize {
+        self.base_address + (offset << self.reg_shift)
+    }
+
+    /// Programs the divisor latch for `baud` given a `clock_hz` input clock, and sets the line
+    /// to 8N1. Must run before the device is published.
+    pub fn set_baud_rate(&mut self, baud: u32, clock_hz: u32) {
+        let [low, high] = baud_divisor(baud, clock_hz).to_le_bytes();
+
+        self.write_register(LCR_OFFSET, LCR_DLAB);
+        self.write_register(DLL_OFFSET, low);
+        self.write_register(DLM_OFFSET, high);
+        // clearing DLAB switches offsets 0 and 1 back to THR and IER
+        self.write_register(LCR_OFFSET, LCR_8N1);
+    }
+
+    /// Enables the TX/RX FIFOs so `write` can push bursts of up to `TX_FIFO_DEPTH` bytes.
+    ///
+    /// A plain 8250 without FIFOs keeps writing byte by byte. Must run before the device is
+    /// published: the console lock only hands out `&Uart`.
+    pub fn enable_fifo(&mut self) {
+        self.tx_burst = enable_fifo(self);
+    }
+
+    /// Writes `bytes`, polling LSR once per burst instead of once per byte.
+    pub fn send_bytes_blocking(&self, bytes: &[u8]) {
+        send_bytes_blocking(self, self.tx_burst, bytes);
+    }
+
+    pub fn send_byte_blocking(&self, byte: u8) {
+        // wait untill transmit holding register is empty (5th bit of LSR is set)
+        while self.read_register(LSR_OFFSET) & LSR_TX_EMPTY == 0 {}
+
+        self.write_register(THR_OFFSET, byte);
+    }
+
+    /// Blocks until the transmitter has shifted out every queued byte.
+    pub fn flush_blocking(&self) {
+        while self.read_register(LSR_OFFSET) & LSR_TX_IDLE == 0 {}
+    }
+}
+
+/// Register access of an ns16550a: MMIO for a real `Uart`, a mock in the checks.
+trait Registers {
+    fn read_register(&self, offset: usize) -> u8;
+
+    fn write_register(&self, offset: usize, value: u8);
+}
+
+impl Registers for Uart {
+    fn read_register(&self, offset: usize) -> u8 {
+        let address = self.register_address(offset);
+        unsafe {
+            match self.reg_io_width {
+                4 => read_volatile(address as *const u32) as u8,
+                _ => read_volatile(address as *const u8),
+            }
+        }
     }
 
-    pub fn send_byte_blocking(&mut self, byte: u8) {
-        let base_ptr = self.base_address as *mut u8;
+    fn write_register(&self, offset: usize, value: u8) {
+        let address = self.register_address(offset);
         unsafe {
-            // wait untill transmit holding register is empty (5th bit of LSR is set)
-            loop {
-                let lsr = read_volatile(base_ptr.add(LSR_OFFSET));
-                if (lsr & LSR_TX_EMPTY) != 0 {
-                    break;
-                }
+            match self.reg_io_width {
+                4 => write_volatile(address as *mut u32, value as u32),
+                _ => write_volatile(address as *mut u8, value),
             }
-            write_volatile(base_ptr, byte);
         }
     }
 }
 
+/// Enables the FIFOs of `regs`, returns how many bytes fit behind one "THR empty" check.
+fn enable_fifo(regs: &impl Registers) -> usize {
+    regs.write_register(FCR_OFFSET, FCR_ENABLE_FIFOS);
+
+    if regs.read_register(IIR_OFFSET) & IIR_FIFOS_ENABLED == IIR_FIFOS_ENABLED {
+        TX_FIFO_DEPTH
+    } else {
+        1
+    }
+}
+
+fn send_bytes_blocking(regs: &impl Registers, tx_burst: usize, bytes: &[u8]) {
+    for burst in bytes.chunks(tx_burst) {
+        // with FIFOs enabled THR empty means the whole TX FIFO is empty
+        while regs.read_register(LSR_OFFSET) & LSR_TX_EMPTY == 0 {}
+
+        for &byte in burst {
+            regs.write_register(THR_OFFSET, byte);
+        }
+    }
+}
+
+/// Divisor latch value for `baud` at a `clock_hz` input clock: `clock_hz / (16 * baud)`,
+/// clamped to what the 16-bit latch can hold.
+pub const fn baud_divisor(baud: u32, clock_hz: u32) -> u16 {
+    assert!(baud > 0, "Baud rate must be non-zero");
+
+    let divisor = clock_hz as u64 / (16 * baud as u64);
+    if divisor == 0 {
+        1
+    } else if divisor > u16::MAX as u64 {
+        u16::MAX
+    } else {
+        divisor as u16
+    }
+}
+
 #[derive(Debug)]
 pub struct UartError;
 
@@ -79,27 +270,70 @@ impl Error for UartError {
     }
 }
 
-impl ErrorType for Uart {
+impl ErrorType for &Uart {
     type Error = UartError;
 }
 
-// HAL Write trait, similar to io::Write
-impl Write for Uart {
+// HAL Write trait, similar to io::Write. On `&Uart` like `&File`: writes only touch MMIO,
+// and the console lock never hands out `&mut Uart`.
+impl Write for &Uart {
     fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
-        for &byte in buf {
-            self.send_byte_blocking(byte);
-        }
+        self.send_bytes_blocking(buf);
         Ok(buf.len())
     }
 
     fn flush(&mut self) -> Result<(), Self::Error> {
-        // no-op, QEMU virt UART does not require flushing
+        self.flush_blocking();
         Ok(())
     }
 }
 
-impl fmt::Write for Uart {
+impl fmt::Write for &Uart {
     fn write_str(&mut self, s: &str) -> fmt::Result {
         self.write_all(s.as_bytes()).map_err(|_| fmt::Error)
     }
 }
+
+/// Checks a 64-byte write polls LSR once per 16-byte burst on a UART with FIFOs, and once
+/// per byte on one without, against mocked registers counting the accesses.
+pub fn check_burst_write() {
+    struct MockRegisters {
+        /// What IIR reads back after FCR is written.
+        iir: u8,
+        lsr_reads: Cell<usize>,
+        thr_writes: Cell<usize>,
+    }
+
+    impl Registers for MockRegisters {
+        fn read_register(&self, offset: usize) -> u8 {
+            match offset {
+                IIR_OFFSET => self.iir,
+                LSR_OFFSET => {
+                    self.lsr_reads.set(self.lsr_reads.get() + 1);
+                    LSR_TX_EMPTY
+                }
+                _ => 0,
+            }
+        }
+
+        fn write_register(&self, offset: usize, _value: u8) {
+            if offset == THR_OFFSET {
+                self.thr_writes.set(self.thr_writes.get() + 1);
+            }
+        }
+    }
+
+    let message = [b'x'; 64];
+    for (iir, lsr_reads) in [(IIR_FIFOS_ENABLED, 64 / TX_FIFO_DEPTH), (0, 64)] {
+        let regs = MockRegisters {
+            iir,
+            lsr_reads: Cell::new(0),
+            thr_writes: Cell::new(0),
+        };
+        let tx_burst = enable_fifo(&regs);
+        send_bytes_blocking(&regs, tx_burst, &message);
+
+        assert_eq!(regs.lsr_reads.get(), lsr_reads, "Wrong number of LSR polls");
+        assert_eq!(regs.thr_writes.get(), message.len(), "Bytes lost");
+    }
+}
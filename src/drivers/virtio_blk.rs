@@ -0,0 +1,455 @@
+//! Minimal virtio-blk over the virtio-mmio transport (version 2, "modern").
+//!
+//! A single virtqueue with one request in flight at a time, completion is polled.
+//! Requests go through a bounce buffer in the queue frame, one sector each.
+//! QEMU only exposes modern devices with `-global virtio-mmio.force-legacy=false`.
+
+use super::{Device, Driver};
+use crate::cpu;
+use crate::devices::block::BlockDevice;
+use crate::devices::{CLINT_INSTANCE, VIRTIO_BLK_INSTANCE};
+use crate::drivers::clint::us_to_ticks;
+use crate::memory::frame::BASE_SIZE;
+use crate::memory::{dma_alloc_coherent, dma_free};
+use crate::sync::Spinlock;
+use core::ptr::{NonNull, read_volatile, write_volatile};
+use core::sync::atomic::{Ordering, fence};
+use fdt::node::FdtNode;
+
+pub const SECTOR_SIZE: usize = 512;
+
+const MAGIC_VALUE: u32 = 0x7472_6976; // "virt"
+const MODERN_VERSION: u32 = 2;
+const BLOCK_DEVICE_ID: u32 = 2;
+
+// virtio-mmio registers
+const REG_MAGIC: usize = 0x000;
+const REG_VERSION: usize = 0x004;
+const REG_DEVICE_ID: usize = 0x008;
+const REG_DEVICE_FEATURES: usize = 0x010;
+const REG_DEVICE_FEATURES_SEL: usize = 0x014;
+const REG_DRIVER_FEATURES: usize = 0x020;
+const REG_DRIVER_FEATURES_SEL: usize = 0x024;
+const REG_QUEUE_SEL: usize = 0x030;
+const REG_QUEUE_NUM_MAX: usize = 0x034;
+const REG_QUEUE_NUM: usize = 0x038;
+const REG_QUEUE_READY: usize = 0x044;
+const REG_QUEUE_NOTIFY: usize = 0x050;
+const REG_INTERRUPT_STATUS: usize = 0x060;
+const REG_INTERRUPT_ACK: usize = 0x064;
+const REG_STATUS: usize = 0x070;
+const REG_QUEUE_DESC_LOW: usize = 0x080;
+const REG_QUEUE_DESC_HIGH: usize = 0x084;
+const REG_QUEUE_DRIVER_LOW: usize = 0x090;
+const REG_QUEUE_DRIVER_HIGH: usize = 0x094;
+const REG_QUEUE_DEVICE_LOW: usize = 0x0a0;
+const REG_QUEUE_DEVICE_HIGH: usize = 0x0a4;
+const REG_CONFIG_CAPACITY: usize = 0x100;
+
+// device status bits
+const STATUS_ACKNOWLEDGE: u32 = 1;
+const STATUS_DRIVER: u32 = 2;
+const STATUS_DRIVER_OK: u32 = 4;
+const STATUS_FEATURES_OK: u32 = 8;
+const STATUS_FAILED: u32 = 128;
+
+/// `VIRTIO_F_VERSION_1`, bit 0 of the second feature word
+const FEATURE_VERSION_1: u32 = 1;
+
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+
+const REQUEST_TYPE_IN: u32 = 0;
+const REQUEST_TYPE_OUT: u32 = 1;
+const REQUEST_STATUS_OK: u8 = 0;
+
+const QUEUE_SIZE: u16 = 8;
+
+/// How long `submit` polls for a completion before giving up on the device.
+const REQUEST_TIMEOUT_US: u64 = 1_000_000;
+
+// everything the device touches lives in a single DMA frame
+const DESC_TABLE_OFFSET: usize = 0;
+const AVAIL_RING_OFFSET: usize = DESC_TABLE_OFFSET + size_of::<Descriptor>() * QUEUE_SIZE as usize;
+const USED_RING_OFFSET: usize =
+    (AVAIL_RING_OFFSET + 6 + 2 * QUEUE_SIZE as usize).next_multiple_of(4);
+const HEADER_OFFSET: usize = (USED_RING_OFFSET + 6 + 8 * QUEUE_SIZE as usize).next_multiple_of(16);
+const STATUS_OFFSET: usize = HEADER_OFFSET + size_of::<RequestHeader>();
+const DATA_OFFSET: usize = (STATUS_OFFSET + 1).next_multiple_of(SECTOR_SIZE);
+
+const _: () = assert!(DATA_OFFSET + SECTOR_SIZE <= BASE_SIZE);
+
+/// Virtqueue descriptor, as laid out in the descriptor table.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Descriptor {
+    pub addr: u64,
+    pub len: u32,
+    pub flags: u16,
+    pub next: u16,
+}
+
+#[repr(C)]
+struct RequestHeader {
+    request_type: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+/// Descriptors `0..3` of a block request: header (device reads), data (device writes
+/// for reads, reads for writes) and status (device writes), chained in that order.
+pub const fn request_chain(
+    header: u64,
+    data: u64,
+    data_len: u32,
+    device_writes_data: bool,
+    status: u64,
+) -> [Descriptor; 3] {
+    let data_flags = if device_writes_data {
+        DESC_F_WRITE | DESC_F_NEXT
+    } else {
+        DESC_F_NEXT
+    };
+
+    [
+        Descriptor {
+            addr: header,
+            len: size_of::<RequestHeader>() as u32,
+            flags: DESC_F_NEXT,
+            next: 1,
+        },
+        Descriptor {
+            addr: data,
+            len: data_len,
+            flags: data_flags,
+            next: 2,
+        },
+        Descriptor {
+            addr: status,
+            len: 1,
+            flags: DESC_F_WRITE,
+            next: 0,
+        },
+    ]
+}
+
+// the chain must end at the status descriptor and never loop back
+const _: () = {
+    let chain = request_chain(0x1000, 0x2000, SECTOR_SIZE as u32, true, 0x3000);
+    assert!(chain[0].flags & DESC_F_WRITE == 0 && chain[0].next == 1);
+    assert!(chain[1].flags & DESC_F_WRITE != 0 && chain[1].next == 2);
+    assert!(chain[2].flags & DESC_F_NEXT == 0 && chain[2].len == 1);
+
+    // a write only differs in who may write the data descriptor
+    let chain = request_chain(0x1000, 0x2000, SECTOR_SIZE as u32, false, 0x3000);
+    assert!(chain[1].flags == DESC_F_NEXT && chain[1].next == 2);
+    assert!(chain[2].flags & DESC_F_WRITE != 0);
+};
+
+pub struct VirtioBlk {
+    base_address: usize,
+    /// DMA frame holding the queue, request header, status and bounce buffer
+    queue: Option<NonNull<u8>>,
+    avail_idx: u16,
+    last_used_idx: u16,
+    /// in `SECTOR_SIZE` units
+    capacity: u64,
+}
+
+impl Device for VirtioBlk {}
+
+impl VirtioBlk {
+    pub fn new(base_address: usize) -> Self {
+        Self {
+            base_address,
+            queue: None,
+            avail_idx: 0,
+            last_used_idx: 0,
+            capacity: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    fn read_register(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.base_address + offset) as *const u32) }
+    }
+
+    fn write_register(&mut self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.base_address + offset) as *mut u32, value) }
+    }
+
+    fn write_register_pair(&mut self, low: usize, high: usize, value: u64) {
+        self.write_register(low, value as u32);
+        self.write_register(high, (value >> 32) as u32);
+    }
+
+    /// Runs the virtio initialization sequence and sets up queue 0.
+    ///
+    /// On failure the device is marked `FAILED` and left without a queue.
+    pub fn init(&mut self) -> Result<(), &'static str> {
+        let result = self.negotiate();
+        if result.is_err() {
+            self.write_register(REG_STATUS, STATUS_FAILED);
+        }
+        result
+    }
+
+    fn negotiate(&mut self) -> Result<(), &'static str> {
+        // reset
+        self.write_register(REG_STATUS, 0);
+
+        let mut status = STATUS_ACKNOWLEDGE | STATUS_DRIVER;
+        self.write_register(REG_STATUS, status);
+
+        self.write_register(REG_DEVICE_FEATURES_SEL, 1);
+        if self.read_register(REG_DEVICE_FEATURES) & FEATURE_VERSION_1 == 0 {
+            return Err("Device does not offer VIRTIO_F_VERSION_1");
+        }
+
+        // accept nothing but VERSION_1
+        self.write_register(REG_DRIVER_FEATURES_SEL, 0);
+        self.write_register(REG_DRIVER_FEATURES, 0);
+        self.write_register(REG_DRIVER_FEATURES_SEL, 1);
+        self.write_register(REG_DRIVER_FEATURES, FEATURE_VERSION_1);
+
+        status |= STATUS_FEATURES_OK;
+        self.write_register(REG_STATUS, status);
+        if self.read_register(REG_STATUS) & STATUS_FEATURES_OK == 0 {
+            return Err("Device rejected the negotiated features");
+        }
+
+        self.write_register(REG_QUEUE_SEL, 0);
+        if self.read_register(REG_QUEUE_READY) != 0 {
+            return Err("Queue 0 already in use");
+        }
+        if self.read_register(REG_QUEUE_NUM_MAX) < QUEUE_SIZE as u32 {
+            return Err("Queue 0 is too small");
+        }
+
+        let queue = dma_alloc_coherent(BASE_SIZE).ok_or("Failed to allocate the virtqueue")?;
+        unsafe { queue.as_ptr().write_bytes(0, BASE_SIZE) };
+        let queue_addr = queue.as_ptr() as u64;
+
+        self.write_register(REG_QUEUE_NUM, QUEUE_SIZE as u32);
+        self.write_register_pair(
+            REG_QUEUE_DESC_LOW,
+            REG_QUEUE_DESC_HIGH,
+            queue_addr + DESC_TABLE_OFFSET as u64,
+        );
+        self.write_register_pair(
+            REG_QUEUE_DRIVER_LOW,
+            REG_QUEUE_DRIVER_HIGH,
+            queue_addr + AVAIL_RING_OFFSET as u64,
+        );
+        self.write_register_pair(
+            REG_QUEUE_DEVICE_LOW,
+            REG_QUEUE_DEVICE_HIGH,
+            queue_addr + USED_RING_OFFSET as u64,
+        );
+        self.write_register(REG_QUEUE_READY, 1);
+        self.queue = Some(queue);
+
+        self.capacity = self.read_register(REG_CONFIG_CAPACITY) as u64
+            | (self.read_register(REG_CONFIG_CAPACITY + 4) as u64) << 32;
+
+        status |= STATUS_DRIVER_OK;
+        self.write_register(REG_STATUS, status);
+
+        Ok(())
+    }
+
+    /// Resets the device, it stops touching the queue memory.
+    pub fn reset(&mut self) {
+        self.write_register(REG_STATUS, 0);
+    }
+
+    /// Bounce buffer the data descriptor points at, `None` before `init`.
+    fn bounce_buffer(&self) -> Option<*mut u8> {
+        self.queue
+            .map(|queue| unsafe { queue.as_ptr().add(DATA_OFFSET) })
+    }
+
+    /// Submits a request for `sector` on the bounce buffer, polling until the device
+    /// completes it.
+    ///
+    /// A device that doesn't answer within `REQUEST_TIMEOUT_US` is reset and loses its
+    /// queue, every later request fails.
+    fn submit(&mut self, request_type: u32, sector: u64) -> Result<(), &'static str> {
+        let queue = self.queue.ok_or("virtio-blk queue not set up")?;
+        if sector >= self.capacity {
+            return Err("Sector out of range");
+        }
+
+        let base = queue.as_ptr();
+        let base_addr = base as u64;
+
+        unsafe {
+            write_volatile(
+                base.add(HEADER_OFFSET).cast::<RequestHeader>(),
+                RequestHeader {
+                    request_type,
+                    reserved: 0,
+                    sector,
+                },
+            );
+            write_volatile(base.add(STATUS_OFFSET), 0xff);
+
+            let chain = request_chain(
+                base_addr + HEADER_OFFSET as u64,
+                base_addr + DATA_OFFSET as u64,
+                SECTOR_SIZE as u32,
+                request_type == REQUEST_TYPE_IN,
+                base_addr + STATUS_OFFSET as u64,
+            );
+            let table = base.add(DESC_TABLE_OFFSET).cast::<Descriptor>();
+            for (i, descriptor) in chain.iter().enumerate() {
+                write_volatile(table.add(i), *descriptor);
+            }
+
+            // avail ring: flags u16, idx u16, ring[QUEUE_SIZE] u16
+            let avail = base.add(AVAIL_RING_OFFSET).cast::<u16>();
+            write_volatile(avail.add(2 + (self.avail_idx % QUEUE_SIZE) as usize), 0);
+
+            // descriptors must be visible before the index that publishes them
+            fence(Ordering::Release);
+            self.avail_idx = self.avail_idx.wrapping_add(1);
+            write_volatile(avail.add(1), self.avail_idx);
+        }
+
+        crate::cpu::fence();
+        self.write_register(REG_QUEUE_NOTIFY, 0);
+
+        // used ring: flags u16, idx u16, ...
+        let used_idx = unsafe { base.add(USED_RING_OFFSET).cast::<u16>().add(1) };
+        let deadline = mtime().map(|now| now.saturating_add(us_to_ticks(REQUEST_TIMEOUT_US)));
+        while unsafe { read_volatile(used_idx) } == self.last_used_idx {
+            if deadline.is_some_and(|deadline| mtime().is_some_and(|now| now >= deadline)) {
+                // the request may still complete, stop the device before freeing its memory
+                self.reset();
+                self.queue = None;
+                dma_free(queue, BASE_SIZE);
+                return Err("virtio-blk request timed out");
+            }
+            core::hint::spin_loop();
+        }
+        fence(Ordering::Acquire);
+        self.last_used_idx = self.last_used_idx.wrapping_add(1);
+
+        let interrupt_status = self.read_register(REG_INTERRUPT_STATUS);
+        self.write_register(REG_INTERRUPT_ACK, interrupt_status);
+
+        if unsafe { read_volatile(base.add(STATUS_OFFSET)) } != REQUEST_STATUS_OK {
+            return Err("virtio-blk request failed");
+        }
+
+        Ok(())
+    }
+}
+
+/// Current `mtime`, `None` without a CLINT to time requests out with.
+fn mtime() -> Option<u64> {
+    let clint = CLINT_INSTANCE.get()?;
+    // the timer interrupt handler takes the CLINT lock too
+    Some(cpu::with_interrupts_disabled(|| clint.lock().mtime()))
+}
+
+impl BlockDevice for VirtioBlk {
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn num_blocks(&self) -> u64 {
+        self.capacity
+    }
+
+    fn read_block(&mut self, block: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        if buf.len() != SECTOR_SIZE {
+            return Err("Buffer is not one sector long");
+        }
+
+        self.submit(REQUEST_TYPE_IN, block)?;
+
+        let data = self.bounce_buffer().ok_or("virtio-blk queue not set up")?;
+        buf.copy_from_slice(unsafe { core::slice::from_raw_parts(data, SECTOR_SIZE) });
+
+        Ok(())
+    }
+
+    fn write_block(&mut self, block: u64, buf: &[u8]) -> Result<(), &'static str> {
+        if buf.len() != SECTOR_SIZE {
+            return Err("Buffer is not one sector long");
+        }
+
+        let data = self.bounce_buffer().ok_or("virtio-blk queue not set up")?;
+        unsafe { core::slice::from_raw_parts_mut(data, SECTOR_SIZE) }.copy_from_slice(buf);
+
+        self.submit(REQUEST_TYPE_OUT, block)
+    }
+}
+
+// only reachable through the `VIRTIO_BLK_INSTANCE` spinlock
+unsafe impl Send for VirtioBlk {}
+
+pub struct VirtioBlkDriver;
+
+impl Driver for VirtioBlkDriver {
+    type Device = VirtioBlk;
+
+    fn init_global(&self, mut device: Self::Device) {
+        let addr = device.base_address;
+
+        // before `init`, which hands the device a queue and sets DRIVER_OK
+        if VIRTIO_BLK_INSTANCE.get().is_some() {
+            log_warn!(
+                "virtio-blk at {:#x}: only one block device is supported",
+                addr
+            );
+            return;
+        }
+
+        if let Err(e) = device.init() {
+            log_error!("virtio-blk at {:#x}: {}", addr, e);
+            return;
+        }
+
+        let capacity = device.capacity();
+        // probing runs on the boot hart alone, nothing took the slot since the check
+        if VIRTIO_BLK_INSTANCE.set(Spinlock::new(device)).is_err() {
+            panic!("virtio-blk slot taken during probing");
+        }
+
+        let driver_type = self.compatibility()[0];
+        println!(
+            "[ OK ] virtio-blk ({}): successfully initialized at {:#x} ({} sectors)",
+            driver_type, addr, capacity
+        );
+    }
+
+    fn compatibility(&self) -> &'static [&'static str] {
+        &["virtio,mmio"]
+    }
+
+    fn probe(&self, node: &FdtNode) -> Option<Self::Device> {
+        if !self.is_compatible(node) {
+            return None;
+        }
+
+        let base_addr = super::mmio_base(node)?;
+        let device = VirtioBlk::new(base_addr);
+
+        // every virtio-mmio slot shows up in the FDT, only pick populated block devices
+        (device.read_register(REG_MAGIC) == MAGIC_VALUE
+            && device.read_register(REG_VERSION) == MODERN_VERSION
+            && device.read_register(REG_DEVICE_ID) == BLOCK_DEVICE_ID)
+            .then_some(device)
+    }
+
+    fn shutdown(&self) {
+        if let Some(device) = VIRTIO_BLK_INSTANCE.get() {
+            device.lock().reset();
+        }
+    }
+}
@@ -1,13 +1,23 @@
 #![no_std]
 #![no_main]
+// nightly only, see `memory::alloc_error_handler`
+#![cfg_attr(nightly, feature(alloc_error_handler))]
+
+extern crate alloc;
+
 // Modules
 #[macro_use]
 pub mod printing;
+#[macro_use]
+pub mod log;
 pub mod collections;
 pub mod cpu;
+pub mod crash_log;
 pub mod devices;
 pub mod drivers;
 pub mod memory;
+pub mod smp;
+pub mod symbols;
 pub mod sync;
 pub mod trap;
 
@@ -19,7 +29,10 @@ use core::panic::PanicInfo;
 use core::sync::atomic::AtomicBool;
 use fdt::Fdt;
 
-// boot code
+// boot code, with `ZICBOM` it also enables the cache-block instructions for S-mode
+#[cfg(feature = "zicbom")]
+global_asm!(".equ ZICBOM, 1", include_str!("asm/boot.S"));
+#[cfg(not(feature = "zicbom"))]
 global_asm!(include_str!("asm/boot.S"));
 global_asm!(include_str!("asm/trap.S"));
 
@@ -29,13 +42,16 @@ static IS_PANICKING: AtomicBool = AtomicBool::new(false);
 fn _panic(info: &PanicInfo) -> ! {
     // TODO: interrupt other harts here
     // TODO: disable irqs for this hart
-    // TODO: write a crash log in a file or buffer
 
     if IS_PANICKING.swap(true, core::sync::atomic::Ordering::Relaxed) {
         _panic_print(format_args!("KERNEL PANIC: circular panic detected\n"));
         halt();
     } else {
+        // first, printing may fault again
+        crash_log::record_panic(info);
         _panic_print(format_args!("KERNEL PANIC: {info}\n"));
+        symbols::print_backtrace(_panic_print);
+        memory::dump_state(_panic_print);
     }
 
     halt();
@@ -51,18 +67,133 @@ fn halt() -> ! {
 
 #[unsafe(no_mangle)]
 pub extern "C" fn kmain(hart_id: usize, dtb_ptr: usize) -> ! {
+    cpu::set_current_hart_id(hart_id);
+    cpu::paint_boot_stack();
+
     // Default UART base address, can be overridden by FDT
     let fdt = unsafe { Fdt::from_ptr(dtb_ptr as *const u8).unwrap() };
 
     drivers::probe_and_init_devices(&fdt);
 
     // print_welcome_screen();
-    memory::init(fdt.memory());
+    let allocator_config = memory::AllocatorConfig::from_bootargs(fdt.chosen().bootargs());
+    memory::init(&fdt, dtb_ptr.into(), allocator_config);
+    crash_log::report_previous_crash();
+    drivers::probe_and_init_late_devices(&fdt);
+    smp::init_trap_stack();
+
+    if cfg!(debug_assertions) {
+        trap::check_breakpoint_return();
+        trap::check_jiffies();
+        trap::check_cause_decoding();
+        trap::check_exception_report();
+        collections::hash_map::check_hash_map();
+        sync::spinlock::check_get_mut();
+        sync::ordered_spinlock::check_lock_order();
+        #[cfg(debug_assertions)]
+        sync::once_lock::check_take_reset();
+        sync::irq_save::check_lock_irqsave();
+        symbols::check_symbol_lookup();
+        crash_log::check_crash_log();
+        log::check_level_filter();
+        memory::check_dealloc_routing();
+        memory::check_oversize_alloc();
+        memory::check_size_routing();
+        memory::check_class_for_ptr();
+        memory::check_validate_heap();
+        memory::check_health_report();
+        memory::check_realloc();
+        memory::check_alloc_zeroed();
+        memory::check_cache_aligned();
+        memory::slub::check_reclaim_hysteresis();
+        memory::slub::check_reclaim_step();
+        memory::slub::check_double_free();
+        memory::slub::check_flush_hart_cache();
+        memory::dma::check_dma_pool();
+        memory::dma::check_dma_ownership();
+        #[cfg(feature = "alloc-trace")]
+        memory::trace::check_trace_sink();
+        memory::slub::check_poison();
+        memory::pmem_map::check_memory_region();
+        memory::pmem_map::check_reserved_regions();
+        memory::pmem_map().check_classify();
+        memory::frame_allocator().check_freeze();
+        memory::frame_allocator().check_defragment();
+        drivers::check_probe_order();
+        drivers::check_shutdown_all();
+        drivers::clint::check_clint_registers();
+        drivers::uart::check_burst_write();
+        memory::frame::check_frame_order();
+        memory::frame::check_frame_format();
+        memory::frame::check_slab_cache();
+        memory::free_lists::check_counts();
+        memory::free_lists::check_misordered_block();
+        memory::frame_allocator::check_span_blocks();
+        memory::frame_allocator::check_valid_allocation();
+        memory::watch::check_watch();
+        devices::block::check_block_cache();
+        devices::timer::check_timer_wheel();
+        // test fixtures and buffer-backed memory maps only exist in debug builds
+        #[cfg(debug_assertions)]
+        {
+            collections::doubly_linked_list::check_append();
+            collections::doubly_linked_list::check_split_after_counted();
+            collections::doubly_linked_list::check_split_before();
+            collections::doubly_linked_list::check_membership();
+            collections::free_list::check_frame_pool();
+            collections::singly_linked_list::check_prepend_chain();
+            collections::lru_list::check_lru_list();
+            memory::hart_cache::check_lazy_init();
+            memory::hart_cache::check_try_push();
+            memory::hart_cache::check_adaptive_target();
+            memory::pmem_map::check_ram_banks();
+            memory::pmem_map::check_kernel_overlap();
+            memory::pmem_map::check_frame_round_trip();
+            memory::pmem_map::check_buddy_address();
+            memory::memtest::check_memtest();
+            memory::frame_allocator::check_full_coalescing();
+            memory::frame_allocator::check_refill_batch();
+            memory::frame_allocator::check_touch_hart_cache();
+            memory::frame_allocator::check_drain_all_caches();
+            memory::frame_allocator::check_flush_hart_cache();
+            memory::frame_allocator::check_size_too_large();
+            memory::frame_allocator::check_zero_free_frames();
+            memory::frame_allocator::check_largest_available_order();
+            memory::frame_allocator::check_reserve_range();
+        }
+    }
+
+    smp::boot_secondary_harts(&fdt, hart_id);
 
-    panic!("Test panic on hart {}", hart_id);
+    hart_main(hart_id);
 }
 
+/// Entry point of secondary harts released by `smp::boot_secondary_harts`.
+#[unsafe(no_mangle)]
+pub extern "C" fn kmain_secondary(hart_id: usize) -> ! {
+    cpu::set_current_hart_id(hart_id);
+
+    // the wakeup interrupt stays pending until acknowledged
+    devices::clint().clear_software_interrupt(hart_id);
+
+    memory::FRAME_ALLOCATOR.wait();
+    smp::init_trap_stack();
+
+    hart_main(hart_id);
+}
+
+/// Common per-hart loop once the hart is fully set up.
+fn hart_main(hart_id: usize) -> ! {
+    log_info!("hart {} online", hart_id);
+    halt();
+}
+
+/// Prints the boot banner, skipped on boards without a console.
 pub fn print_welcome_screen() {
+    if !printing::console_available() {
+        return;
+    }
+
     println!(
         r#"
 
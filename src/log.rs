@@ -0,0 +1,115 @@
+use crate::cpu::current_hart_id;
+use crate::printing::_print;
+use core::fmt;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum LogLevel {
+    Error = 0,
+    Warn = 1,
+    Info = 2,
+    Debug = 3,
+    Trace = 4,
+}
+
+impl LogLevel {
+    const fn from_u8(value: u8) -> Self {
+        match value {
+            0 => LogLevel::Error,
+            1 => LogLevel::Warn,
+            2 => LogLevel::Info,
+            3 => LogLevel::Debug,
+            _ => LogLevel::Trace,
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LogLevel::Error => "ERROR",
+            LogLevel::Warn => "WARN",
+            LogLevel::Info => "INFO",
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Trace => "TRACE",
+        };
+        f.pad(name)
+    }
+}
+
+/// Most verbose level that still gets printed.
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+pub fn set_level(level: LogLevel) {
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn level() -> LogLevel {
+    LogLevel::from_u8(LOG_LEVEL.load(Ordering::Relaxed))
+}
+
+#[inline]
+pub fn enabled(level: LogLevel) -> bool {
+    level as u8 <= LOG_LEVEL.load(Ordering::Relaxed)
+}
+
+#[doc(hidden)]
+pub fn _log(level: LogLevel, args: fmt::Arguments) {
+    _print(format_args!(
+        "[{:<5}][hart {}] {}\n",
+        level,
+        current_hart_id(),
+        args
+    ));
+}
+
+/// Logs a message at the given level, skipping formatting entirely when filtered out.
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {
+        if $crate::log::enabled($level) {
+            $crate::log::_log($level, format_args!($($arg)*));
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)*) => ($crate::log!($crate::log::LogLevel::Error, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! log_warn {
+    ($($arg:tt)*) => ($crate::log!($crate::log::LogLevel::Warn, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)*) => ($crate::log!($crate::log::LogLevel::Info, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! log_debug {
+    ($($arg:tt)*) => ($crate::log!($crate::log::LogLevel::Debug, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! log_trace {
+    ($($arg:tt)*) => ($crate::log!($crate::log::LogLevel::Trace, $($arg)*));
+}
+
+/// Checks the filter at `Warn`: more severe levels pass, more verbose ones are dropped.
+/// Restores the previous level.
+pub fn check_level_filter() {
+    let previous = level();
+
+    set_level(LogLevel::Warn);
+    assert_eq!(level(), LogLevel::Warn);
+    assert!(enabled(LogLevel::Error) && enabled(LogLevel::Warn));
+    assert!(!enabled(LogLevel::Info), "Info passed a Warn filter");
+    assert!(!enabled(LogLevel::Trace));
+
+    set_level(previous);
+    assert_eq!(level(), previous, "Log level not restored");
+}
@@ -1,6 +1,29 @@
 use core::fmt;
 use core::ops::{Add, AddAssign, BitXor, Sub};
 
+// the checked variants must fail at the usize boundary instead of wrapping
+const _: () = assert!(PhysicalAddress::new(usize::MAX).checked_add(1).is_none());
+const _: () = assert!(matches!(
+    PhysicalAddress::new(usize::MAX - 1).checked_add(1),
+    Some(PhysicalAddress(usize::MAX))
+));
+const _: () = assert!(PhysicalAddress::new(0).checked_sub(1).is_none());
+const _: () = assert!(matches!(
+    PhysicalAddress::new(1).checked_sub(1),
+    Some(PhysicalAddress(0))
+));
+
+// aligned addresses stay put, unaligned ones move to the neighbouring 4 KiB boundary
+const _: () = assert!(PhysicalAddress::new(0x8000_1000).align_up(4096).0 == 0x8000_1000);
+const _: () = assert!(PhysicalAddress::new(0x8000_1000).align_down(4096).0 == 0x8000_1000);
+const _: () = assert!(PhysicalAddress::new(0x8000_1001).align_up(4096).0 == 0x8000_2000);
+const _: () = assert!(PhysicalAddress::new(0x8000_1fff).align_down(4096).0 == 0x8000_1000);
+const _: () = assert!(PhysicalAddress::new(0x8000_1000).is_aligned_to(4096));
+const _: () = assert!(!PhysicalAddress::new(0x8000_1800).is_aligned_to(4096));
+// the power-of-two assertion lets the page size through but trips on its mask,
+// an easy argument to pass by mistake
+const _: () = assert!(4096usize.is_power_of_two() && !0xfffusize.is_power_of_two());
+
 #[repr(transparent)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct PhysicalAddress(usize);
@@ -22,6 +45,92 @@ impl PhysicalAddress {
         self.0 as *mut T
     }
 
+    /// Views `len` bytes of physical memory starting at this address as a byte slice.
+    ///
+    /// # Safety
+    ///
+    /// The caller guarantees `[self, self + len)` is valid, readable memory that is not
+    /// mutated for `'a`, e.g. the DTB blob or a region owned by the caller.
+    pub unsafe fn as_slice<'a>(&self, len: usize) -> &'a [u8] {
+        unsafe { core::slice::from_raw_parts(self.as_ptr::<u8>(), len) }
+    }
+
+    /// Views `len` bytes of physical memory starting at this address as a mutable byte slice.
+    ///
+    /// # Safety
+    ///
+    /// The caller guarantees `[self, self + len)` is valid, writable memory that nothing
+    /// else accesses for `'a`.
+    pub unsafe fn as_mut_slice<'a>(&self, len: usize) -> &'a mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.as_mut_ptr::<u8>(), len) }
+    }
+
+    /// Rounds the address up to the nearest multiple of `align`.
+    ///
+    /// `align` must be a power of two.
+    pub const fn align_up(self, align: usize) -> Self {
+        debug_assert!(align.is_power_of_two(), "Alignment must be a power of two");
+
+        let result = self
+            .0
+            .checked_add(align - 1)
+            .expect("Overflow when aligning up a PhysicalAddress");
+        Self(result & !(align - 1))
+    }
+
+    /// Rounds the address down to the nearest multiple of `align`.
+    ///
+    /// `align` must be a power of two.
+    pub const fn align_down(self, align: usize) -> Self {
+        debug_assert!(align.is_power_of_two(), "Alignment must be a power of two");
+
+        Self(self.0 & !(align - 1))
+    }
+
+    /// Returns `true` if the address is a multiple of `align`.
+    ///
+    /// `align` must be a power of two.
+    pub const fn is_aligned_to(self, align: usize) -> bool {
+        debug_assert!(align.is_power_of_two(), "Alignment must be a power of two");
+
+        self.0 & (align - 1) == 0
+    }
+
+    /// `self + rhs`, or `None` if it overflows, unlike the `+` operator which panics.
+    pub const fn checked_add(self, rhs: usize) -> Option<Self> {
+        match self.0.checked_add(rhs) {
+            Some(result) => Some(Self(result)),
+            None => None,
+        }
+    }
+
+    /// `self - rhs`, or `None` if it underflows, unlike the `-` operator which panics.
+    pub const fn checked_sub(self, rhs: usize) -> Option<Self> {
+        match self.0.checked_sub(rhs) {
+            Some(result) => Some(Self(result)),
+            None => None,
+        }
+    }
+
+    /// Address of the buddy of the `block_size`-byte block starting here,
+    /// i.e. this address with the block size bit flipped.
+    ///
+    /// `block_size` must be a power of two and the address aligned to it.
+    pub fn buddy(self, block_size: usize) -> Self {
+        debug_assert!(
+            block_size.is_power_of_two(),
+            "Block size must be a power of two"
+        );
+        debug_assert!(
+            self.is_aligned_to(block_size),
+            "Block at {} is not aligned to its size {:#x}",
+            self,
+            block_size
+        );
+
+        self ^ block_size
+    }
+
     pub fn offset_from(&self, other: Self) -> usize {
         self.0
             .checked_sub(other.0)
@@ -0,0 +1,344 @@
+//! Contiguous memory reserved at boot for DMA buffers.
+//!
+//! The pool is a single high-order block taken from the frame allocator before memory
+//! gets fragmented, handed out frame by frame through a bitmap.
+//!
+//! Coherent buffers (`dma_alloc_coherent`) need no maintenance after allocation.
+//! Streaming buffers (`dma_alloc_streaming`) change hands between the CPU and the device
+//! through `dma_sync_for_device`/`dma_sync_for_cpu`, which do the cache maintenance a
+//! non-coherent platform needs (see `cpu::cache_range`).
+
+use crate::collections::Bitmap;
+use crate::cpu::{self, CacheOp};
+use crate::memory::frame::{BASE_SIZE, BASE_SIZE_LAYOUT};
+use crate::memory::{PhysicalAddress, frame_allocator};
+use crate::sync::{OnceLock, Spinlock};
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+/// Pool size used when `mem.dma_pool` is not on the command line.
+pub const DEFAULT_DMA_POOL_SIZE: usize = 1024 * 1024;
+/// Largest pool the bitmap can track, in frames.
+pub const MAX_DMA_POOL_FRAMES: usize = 1024;
+
+const BITMAP_WORDS: usize = MAX_DMA_POOL_FRAMES / u64::BITS as usize;
+
+static DMA_POOL: OnceLock<Spinlock<DmaPool>> = OnceLock::new();
+
+pub struct DmaPool {
+    start: PhysicalAddress,
+    frames: usize,
+    // one bit per frame, set while the frame is handed out
+    used: Bitmap<BITMAP_WORDS>,
+}
+
+impl DmaPool {
+    fn new(start: PhysicalAddress, frames: usize) -> Self {
+        assert!(frames <= MAX_DMA_POOL_FRAMES, "DMA pool too large");
+
+        Self {
+            start,
+            frames,
+            used: Bitmap::new(),
+        }
+    }
+
+    /// `true` if `address` lies inside the pool.
+    pub fn contains(&self, address: PhysicalAddress) -> bool {
+        self.start <= address && address < self.start + self.frames * BASE_SIZE
+    }
+
+    fn is_used(&self, frame: usize) -> bool {
+        self.used.is_set(frame)
+    }
+
+    fn set_used(&mut self, frames: core::ops::Range<usize>, used: bool) {
+        for frame in frames {
+            if used {
+                self.used.set(frame);
+            } else {
+                self.used.clear(frame);
+            }
+        }
+    }
+
+    /// First fit run of `count` free frames.
+    fn alloc(&mut self, count: usize) -> Option<PhysicalAddress> {
+        let mut run_start = 0;
+
+        for frame in 0..self.frames {
+            if self.is_used(frame) {
+                run_start = frame + 1;
+            } else if frame + 1 - run_start == count {
+                self.set_used(run_start..frame + 1, true);
+                return Some(self.start + run_start * BASE_SIZE);
+            }
+        }
+
+        None
+    }
+
+    fn free(&mut self, address: PhysicalAddress, count: usize) {
+        assert!(
+            self.contains(address) && address.is_aligned_to(BASE_SIZE),
+            "{} is not a DMA pool allocation",
+            address
+        );
+
+        let first = address.offset_from(self.start) / BASE_SIZE;
+        assert!(first + count <= self.frames, "DMA free past the pool end");
+
+        debug_assert!(
+            (first..first + count).all(|frame| self.is_used(frame)),
+            "Double free in the DMA pool at {}",
+            address
+        );
+
+        self.set_used(first..first + count, false);
+    }
+}
+
+/// Carves the DMA pool out of the frame allocator, rounded up to a power of two frames.
+///
+/// Must run once, right after the frame allocator is set up.
+pub fn reserve_dma_pool(size: usize) -> Result<(), &'static str> {
+    let frames = size.div_ceil(BASE_SIZE).next_power_of_two();
+    if frames > MAX_DMA_POOL_FRAMES {
+        return Err("DMA pool exceeds MAX_DMA_POOL_FRAMES");
+    }
+
+    let layout = Layout::from_size_align(frames * BASE_SIZE, BASE_SIZE)
+        .map_err(|_| "Invalid DMA pool size")?;
+    let block = frame_allocator()
+        .alloc(layout)
+        .ok_or("Failed to allocate the DMA pool")?;
+
+    let start = PhysicalAddress::from(block.as_ptr() as usize);
+    DMA_POOL
+        .set(Spinlock::new(DmaPool::new(start, frames)))
+        .map_err(|_| "DMA pool already reserved")
+}
+
+/// Allocates `size` bytes of physically contiguous, frame-aligned memory from the DMA pool.
+pub fn dma_alloc(size: usize) -> Option<NonNull<u8>> {
+    let mut pool = DMA_POOL.get()?.lock();
+    let address = pool.alloc(size.div_ceil(BASE_SIZE).max(1))?;
+
+    debug_assert!(
+        pool.contains(address + size.saturating_sub(1)),
+        "DMA allocation escaped the pool"
+    );
+
+    NonNull::new(address.as_mut_ptr::<u8>())
+}
+
+/// Returns memory obtained from `dma_alloc(size)` to the pool.
+pub fn dma_free(ptr: NonNull<u8>, size: usize) {
+    let pool = DMA_POOL.get().expect("DMA pool not reserved");
+    pool.lock().free(
+        PhysicalAddress::from(ptr.as_ptr() as usize),
+        size.div_ceil(BASE_SIZE).max(1),
+    );
+}
+
+/// Allocates a buffer both the CPU and the device may access at any time.
+///
+/// Stale lines are flushed once here. Without a non-cacheable mapping this is only truly
+/// coherent on platforms with coherent DMA, others should prefer streaming buffers.
+pub fn dma_alloc_coherent(size: usize) -> Option<NonNull<u8>> {
+    let ptr = dma_alloc(size)?;
+    cpu::cache_range(CacheOp::Flush, ptr.as_ptr() as usize, size);
+    Some(ptr)
+}
+
+/// Which way data moves through a streaming buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmaDirection {
+    /// The CPU fills the buffer, the device reads it.
+    ToDevice,
+    /// The device fills the buffer, the CPU reads it.
+    FromDevice,
+    Bidirectional,
+}
+
+/// Side currently allowed to touch a streaming buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmaOwner {
+    Cpu,
+    Device,
+}
+
+/// A DMA pool buffer handed back and forth between the CPU and a device.
+#[derive(Debug)]
+pub struct DmaBuffer {
+    ptr: NonNull<u8>,
+    size: usize,
+    direction: DmaDirection,
+    owner: DmaOwner,
+}
+
+impl DmaBuffer {
+    pub fn as_ptr(&self) -> NonNull<u8> {
+        self.ptr
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn direction(&self) -> DmaDirection {
+        self.direction
+    }
+
+    pub fn owner(&self) -> DmaOwner {
+        self.owner
+    }
+
+    /// Moves ownership to `owner`, handing back the current owner if it already has it.
+    fn hand_to(&mut self, owner: DmaOwner) -> Result<(), DmaOwner> {
+        if self.owner == owner {
+            return Err(self.owner);
+        }
+        self.owner = owner;
+        Ok(())
+    }
+}
+
+/// Allocates a streaming buffer, initially owned by the CPU.
+pub fn dma_alloc_streaming(size: usize, direction: DmaDirection) -> Option<DmaBuffer> {
+    Some(DmaBuffer {
+        ptr: dma_alloc(size)?,
+        size,
+        direction,
+        owner: DmaOwner::Cpu,
+    })
+}
+
+/// Hands the buffer to the device: CPU writes are pushed out to memory first.
+pub fn dma_sync_for_device(buffer: &mut DmaBuffer) {
+    buffer
+        .hand_to(DmaOwner::Device)
+        .expect("DMA buffer synced for the device twice");
+
+    let op = match buffer.direction {
+        DmaDirection::ToDevice | DmaDirection::Bidirectional => CacheOp::Clean,
+        // nothing to write back, but dirty lines must not land on top of the device's data
+        DmaDirection::FromDevice => CacheOp::Invalidate,
+    };
+    cpu::cache_range(op, buffer.ptr.as_ptr() as usize, buffer.size);
+}
+
+/// Takes the buffer back from the device: lines cached before the transfer are dropped.
+pub fn dma_sync_for_cpu(buffer: &mut DmaBuffer) {
+    buffer
+        .hand_to(DmaOwner::Cpu)
+        .expect("DMA buffer synced for the CPU without being handed to the device");
+
+    match buffer.direction {
+        DmaDirection::FromDevice | DmaDirection::Bidirectional => {
+            cpu::cache_range(
+                CacheOp::Invalidate,
+                buffer.ptr.as_ptr() as usize,
+                buffer.size,
+            );
+        }
+        // the device only read the buffer, the CPU's view is still valid
+        DmaDirection::ToDevice => cpu::fence(),
+    }
+}
+
+/// Returns a streaming buffer to the pool, the device must be done with it.
+pub fn dma_free_streaming(buffer: DmaBuffer) {
+    assert_eq!(
+        buffer.owner,
+        DmaOwner::Cpu,
+        "DMA buffer freed while owned by the device"
+    );
+    dma_free(buffer.ptr, buffer.size);
+}
+
+/// Checks sub-allocations of a pool stay inside it, never overlap, and that freed frames
+/// are handed out again first fit. Needs the frame allocator.
+pub fn check_dma_pool() {
+    const FRAMES: usize = 8;
+
+    let layout = Layout::from_size_align(FRAMES * BASE_SIZE, BASE_SIZE).expect("Invalid layout");
+    let block = frame_allocator()
+        .alloc(layout)
+        .expect("Failed to allocate the pool");
+    let start = PhysicalAddress::from(block.as_ptr() as usize);
+    let mut pool = DmaPool::new(start, FRAMES);
+
+    let runs = [1, 3, 2, 2].map(|count| (pool.alloc(count).expect("Pool exhausted early"), count));
+    for (index, &(address, count)) in runs.iter().enumerate() {
+        let end = address + (count * BASE_SIZE - 1);
+        assert!(
+            pool.contains(address) && pool.contains(end),
+            "DMA allocation {} escaped the pool",
+            address
+        );
+        assert!(
+            runs[..index]
+                .iter()
+                .all(|&(other, other_count)| other + other_count * BASE_SIZE <= address),
+            "DMA allocation {} overlaps an earlier one",
+            address
+        );
+    }
+    assert_eq!(pool.alloc(1), None, "Full pool handed out a frame");
+
+    // the 3-frame hole is reused before anything past it
+    pool.free(runs[1].0, 3);
+    assert_eq!(pool.alloc(2), Some(runs[1].0));
+    assert_eq!(pool.alloc(2), None, "Allocation ran past the hole");
+    assert_eq!(pool.alloc(1), Some(runs[1].0 + 2 * BASE_SIZE));
+
+    frame_allocator().dealloc(block, layout);
+}
+
+/// Checks streaming buffers go CPU -> device -> CPU in every direction, and that a second
+/// sync to the same side, or a free, is refused while the device owns the buffer. Needs the
+/// frame allocator.
+pub fn check_dma_ownership() {
+    let frame = frame_allocator()
+        .alloc(BASE_SIZE_LAYOUT)
+        .expect("Frame allocation failed");
+
+    for direction in [
+        DmaDirection::ToDevice,
+        DmaDirection::FromDevice,
+        DmaDirection::Bidirectional,
+    ] {
+        let mut buffer = DmaBuffer {
+            ptr: frame,
+            size: BASE_SIZE,
+            direction,
+            owner: DmaOwner::Cpu,
+        };
+        assert_eq!(
+            buffer.hand_to(DmaOwner::Cpu),
+            Err(DmaOwner::Cpu),
+            "Synced for the CPU without a device transfer"
+        );
+
+        dma_sync_for_device(&mut buffer);
+        assert_eq!(buffer.owner(), DmaOwner::Device);
+        assert_eq!(
+            buffer.hand_to(DmaOwner::Device),
+            Err(DmaOwner::Device),
+            "Synced for the device twice"
+        );
+        // `dma_free_streaming` refuses a buffer in this state
+        assert_ne!(
+            buffer.owner(),
+            DmaOwner::Cpu,
+            "Device-owned buffer freeable"
+        );
+
+        dma_sync_for_cpu(&mut buffer);
+        assert_eq!(buffer.owner(), DmaOwner::Cpu);
+        assert_eq!(buffer.direction(), direction);
+    }
+
+    frame_allocator().dealloc(frame, BASE_SIZE_LAYOUT);
+}
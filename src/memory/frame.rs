@@ -1,11 +1,10 @@
+use crate::collections::{DoublyLinkable, SinglyLinkable};
+use crate::memory::SLAB_INFO_LOCK_LEVEL;
 use crate::memory::slub::{SizeClassManager, Slot};
-use crate::sync::Spinlock;
-use crate::{
-    collections::{DoublyLinkable, SinglyLinkable},
-    sync::SpinlockGuard,
-};
+use crate::sync::{OrderedSpinlock, OrderedSpinlockGuard};
 
 use core::alloc::Layout;
+use core::fmt;
 use core::mem::ManuallyDrop;
 use core::ptr::NonNull;
 
@@ -13,11 +12,21 @@ pub const BASE_SIZE: usize = 4096; // 4 KiB
 pub const BASE_SIZE_LAYOUT: Layout =
     unsafe { Layout::from_size_align_unchecked(BASE_SIZE, BASE_SIZE) };
 
+/// First order whose block size no longer fits in `usize`.
+pub const MAX_ORDER: u8 = (usize::BITS - BASE_SIZE.trailing_zeros()) as u8;
+
+/// Order value of a frame tagged by `Frame::set_sweep_mark`, never a real order.
+const SWEEP_MARK: u8 = u8::MAX;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum State {
     Free,
     Allocated,
     Slab,
+    /// Non-head frame of a multi-frame slab, points back to the head `Slab` frame
+    SlabTail,
+    /// Never managed by the allocator: kernel image, allocator metadata, firmware reserved regions
+    Reserved,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -28,6 +37,9 @@ pub struct SlabInfo {
     pub in_use_count: usize,
 }
 
+pub type SlabInfoLock = OrderedSpinlock<SlabInfo, SLAB_INFO_LOCK_LEVEL>;
+pub type SlabInfoGuard<'a> = OrderedSpinlockGuard<'a, SlabInfo, SLAB_INFO_LOCK_LEVEL>;
+
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct BuddyInfo {
@@ -37,8 +49,9 @@ pub struct BuddyInfo {
 
 #[repr(C)]
 pub union FrameData {
-    pub slab: ManuallyDrop<Spinlock<SlabInfo>>,
+    pub slab: ManuallyDrop<SlabInfoLock>,
     pub buddy: ManuallyDrop<BuddyInfo>,
+    pub slab_head: NonNull<Frame>,
 }
 
 pub struct Frame {
@@ -48,6 +61,26 @@ pub struct Frame {
     order: u8,
 }
 
+// Layout invariants `Frame` relies on, checked at compile time.
+//
+// Every variant must fit the union: `FrameData` is sized by its largest member,
+// these catch a variant silently growing past the others' assumptions.
+const _: () = assert!(size_of::<BuddyInfo>() <= size_of::<FrameData>());
+const _: () = assert!(size_of::<SlabInfoLock>() <= size_of::<FrameData>());
+const _: () = assert!(size_of::<NonNull<Frame>>() <= size_of::<FrameData>());
+
+// The slab variant is reinterpreted in place over the buddy links, so the union
+// must be at least as aligned as the spinlock-wrapped `SlabInfo`.
+const _: () = assert!(align_of::<FrameData>() >= align_of::<SlabInfoLock>());
+
+// `PhysicalMemoryMap` places the frame pool at a `BASE_SIZE`-aligned address and indexes it
+// with a `size_of::<Frame>()` stride, so that address must satisfy `Frame`'s alignment.
+const _: () = assert!(BASE_SIZE.is_multiple_of(align_of::<Frame>()));
+
+// The frame pool costs `size_of::<Frame>() / BASE_SIZE` of all RAM (under 1.6% at 64 bytes),
+// growing `Frame` past that should be a deliberate decision.
+const _: () = assert!(size_of::<Frame>() <= 64);
+
 impl Frame {
     pub const fn new() -> Self {
         Frame {
@@ -66,10 +99,28 @@ impl Frame {
         self.order
     }
 
-    pub fn set_order(&mut self, order: u8) {
+    /// Sets the order of the block this frame heads, `orders` being the owning allocator's
+    /// order count.
+    pub fn set_order(&mut self, order: u8, orders: u8) {
+        debug_assert!(
+            order_in_range(order, orders),
+            "Frame order {} is out of range for {} orders",
+            order,
+            orders
+        );
         self.order = order;
     }
 
+    /// Tags a free frame as queued for merging by `FreeLists::coalesce_all`, in place of
+    /// its order, which must be restored with `set_order` afterwards.
+    pub fn set_sweep_mark(&mut self) {
+        self.order = SWEEP_MARK;
+    }
+
+    pub fn has_sweep_mark(&self) -> bool {
+        self.order == SWEEP_MARK
+    }
+
     pub fn state(&self) -> &State {
         &self.state
     }
@@ -82,8 +133,14 @@ impl Frame {
         matches!(self.state, State::Free)
     }
 
+    /// Size in bytes of the block this frame heads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the order is corrupted to the point the size overflows `usize`.
     pub fn size(&self) -> usize {
-        (1 << self.order) * BASE_SIZE
+        block_size(self.order)
+            .unwrap_or_else(|| panic!("Frame order {} overflows the block size", self.order))
     }
 
     pub fn convert_to_slab(
@@ -92,16 +149,31 @@ impl Frame {
         slots_head: Option<NonNull<Slot>>,
     ) {
         self.state = State::Slab;
-        self.data.slab = ManuallyDrop::new(Spinlock::new(SlabInfo {
+        self.data.slab = ManuallyDrop::new(SlabInfoLock::new(SlabInfo {
             cache: cache_ptr,
             next_slot: slots_head,
             in_use_count: 0,
         }));
     }
 
+    /// Marks a non-head frame of a multi-frame slab as belonging to the slab headed by `head`.
+    pub fn convert_to_slab_tail(&mut self, head: NonNull<Frame>) {
+        self.state = State::SlabTail;
+        self.data.slab_head = head;
+    }
+
+    /// Returns the head frame of the multi-frame slab this frame is a tail of, `None` otherwise.
+    pub fn slab_head(&self) -> Option<NonNull<Frame>> {
+        if !matches!(self.state, State::SlabTail) {
+            return None;
+        }
+        // Safety: state is SlabTail, so this union access is valid.
+        Some(unsafe { self.data.slab_head })
+    }
+
     pub fn free_to_buddy(&mut self) {
         debug_assert!(
-            matches!(self.state, State::Slab),
+            matches!(self.state, State::Slab | State::SlabTail),
             "Trying to free_to_buddy() a non-slab frame"
         );
 
@@ -112,7 +184,7 @@ impl Frame {
         });
     }
 
-    pub fn lock_slab_info(&self) -> SpinlockGuard<SlabInfo> {
+    pub fn lock_slab_info(&self) -> SlabInfoGuard<'_> {
         debug_assert!(
             matches!(self.state, State::Slab),
             "Attempted to lock slab info on a non-slab frame"
@@ -121,9 +193,38 @@ impl Frame {
         unsafe { (*self.data.slab).lock() }
     }
 
+    /// Non-blocking `lock_slab_info`, `None` if the slab info is locked.
+    pub fn try_lock_slab_info(&self) -> Option<SlabInfoGuard<'_>> {
+        debug_assert!(
+            matches!(self.state, State::Slab),
+            "Attempted to lock slab info on a non-slab frame"
+        );
+        // Safety: We've asserted the state is Slab, so this union access is valid.
+        unsafe { (*self.data.slab).try_lock() }
+    }
+
+    /// Returns the `SizeClassManager` owning this slab frame, or `None` for non-slab frames.
+    ///
+    /// Does not lock the slab info: `cache` is written once in `convert_to_slab`
+    /// and never changes while the frame stays a slab.
+    pub fn slab_cache(&self) -> Option<NonNull<SizeClassManager>> {
+        if let Some(head) = self.slab_head() {
+            // Safety: tails always point to a live slab head frame
+            return unsafe { head.as_ref() }.slab_cache();
+        }
+
+        if !matches!(self.state, State::Slab) {
+            return None;
+        }
+
+        // Safety: state is Slab, so this union access is valid; `cache` is immutable
+        // for the slab's lifetime, so reading it past the lock doesn't race.
+        Some(unsafe { (*(*self.data.slab).as_ptr()).cache })
+    }
+
     pub fn buddy_info(&self) -> &BuddyInfo {
         debug_assert!(
-            !matches!(self.state, State::Slab),
+            !matches!(self.state, State::Slab | State::SlabTail),
             "Attempted to access buddy info on a slab frame"
         );
         unsafe { &self.data.buddy }
@@ -131,13 +232,58 @@ impl Frame {
 
     pub fn buddy_info_mut(&mut self) -> &mut BuddyInfo {
         debug_assert!(
-            !matches!(self.state, State::Slab),
+            !matches!(self.state, State::Slab | State::SlabTail),
             "Attempted to access buddy info on a slab frame"
         );
         unsafe { &mut self.data.buddy }
     }
 }
 
+impl fmt::Debug for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("Frame");
+        debug
+            .field("state", &self.state)
+            .field("order", &self.order)
+            .field("size", &self.size());
+
+        if matches!(self.state, State::Slab) {
+            // the slab lock may already be held by the caller, never block here
+            // Safety: state is Slab, so this union access is valid.
+            match unsafe { (*self.data.slab).try_lock() } {
+                Some(slab_info) => debug.field("in_use_count", &slab_info.in_use_count),
+                None => debug.field("in_use_count", &format_args!("<locked>")),
+            };
+        }
+
+        debug.finish()
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} frame (order: {}, size: {} KiB)",
+            self.state,
+            self.order,
+            self.size() / 1024
+        )
+    }
+}
+
+/// `true` if `order` is a real order of an allocator with `orders` orders.
+const fn order_in_range(order: u8, orders: u8) -> bool {
+    order < orders && order < MAX_ORDER
+}
+
+/// Size in bytes of an `order` block, `None` if it overflows `usize`.
+fn block_size(order: u8) -> Option<usize> {
+    1usize
+        .checked_shl(order as u32)
+        .and_then(|frames| frames.checked_mul(BASE_SIZE))
+}
+
 impl Default for Frame {
     fn default() -> Self {
         Self::new()
@@ -165,3 +311,73 @@ unsafe impl DoublyLinkable for Frame {
         self.buddy_info_mut().prev = prev;
     }
 }
+
+/// Checks `size` for a real order, that an overflowing one is caught, and that `set_order`
+/// rejects orders beyond the allocator's.
+pub fn check_frame_order() {
+    let mut frame = Frame::new();
+    frame.set_order(3, 4);
+    assert_eq!(frame.size(), 8 * BASE_SIZE);
+
+    assert_eq!(block_size(MAX_ORDER - 1), Some(1 << (usize::BITS - 1)));
+    assert_eq!(block_size(MAX_ORDER), None, "Overflowing order has a size");
+
+    assert!(order_in_range(3, 4));
+    assert!(
+        !order_in_range(4, 4),
+        "Order beyond the allocator's accepted"
+    );
+    assert!(
+        !order_in_range(MAX_ORDER, u8::MAX),
+        "Overflowing order accepted"
+    );
+}
+
+/// Checks that `Debug` and `Display` show the state and order of a free and a slab frame.
+pub fn check_frame_format() {
+    use crate::crash_log::TruncatingWriter;
+    use core::fmt::Write;
+
+    let mut free = Frame::new();
+    free.set_order(2, 4);
+    let mut slab = Frame::new();
+    slab.convert_to_slab(NonNull::dangling(), None);
+
+    let mut buf = [0u8; 128];
+    let mut check = |args: fmt::Arguments, expected: &str| {
+        let mut writer = TruncatingWriter::new(&mut buf);
+        writer.write_fmt(args).unwrap();
+        assert_eq!(writer.as_str(), expected);
+    };
+
+    check(
+        format_args!("{free:?}"),
+        "Frame { state: Free, order: 2, size: 16384 }",
+    );
+    check(
+        format_args!("{free}"),
+        "Free frame (order: 2, size: 16 KiB)",
+    );
+    check(
+        format_args!("{slab:?}"),
+        "Frame { state: Slab, order: 0, size: 4096, in_use_count: 0 }",
+    );
+    check(format_args!("{slab}"), "Slab frame (order: 0, size: 4 KiB)");
+}
+
+/// Checks that `slab_cache` finds the manager from a slab head and its tails only.
+pub fn check_slab_cache() {
+    let cache = NonNull::<SizeClassManager>::dangling();
+
+    let mut head = Frame::new();
+    head.convert_to_slab(cache, None);
+    let mut tail = Frame::new();
+    tail.convert_to_slab_tail(NonNull::from(&mut head));
+    assert_eq!(head.slab_cache(), Some(cache));
+    assert_eq!(tail.slab_cache(), Some(cache), "Tail lost its slab head");
+
+    let mut buddy = Frame::new();
+    assert_eq!(buddy.slab_cache(), None);
+    buddy.set_state(State::Allocated);
+    assert_eq!(buddy.slab_cache(), None, "Buddy frame has a slab cache");
+}
@@ -1,23 +1,116 @@
 use core::alloc::Layout;
-use core::cell::UnsafeCell;
+use core::fmt;
 use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::collections::DoublyLinkedList;
-use crate::cpu::current_hart_id;
-use crate::memory::frame::{BASE_SIZE, Frame, State};
-use crate::memory::free_lists::FreeLists;
-use crate::memory::hart_cache::{MAX_HARTS, Quartering};
-use crate::memory::{HartCache, PhysicalAddress, PhysicalMemoryMap};
-use crate::sync::Spinlock;
+use crate::cpu::{self, current_hart_id};
+use crate::memory::FREE_LISTS_LOCK_LEVEL;
+use crate::memory::frame::{BASE_SIZE, BASE_SIZE_LAYOUT, Frame, State};
+use crate::memory::free_lists::{FreeLists, FreeListsSnapshot};
+use crate::memory::hart_cache::{HartCaches, MAX_HARTS, Quartering};
+use crate::memory::health::{HealthIssue, HealthReport};
+use crate::memory::memtest::MemtestMode;
+use crate::memory::trace::{self, TraceEvent};
+use crate::memory::{
+    HartCache, MemoryRegion, PhysicalAddress, PhysicalMemoryMap, RegionKind, Watch,
+};
+use crate::sync::{OrderedSpinlock, OrderedSpinlockGuard};
 
 const DEFAULT_CACHE_SIZE: usize = 16;
+const MIN_CACHE_SIZE: usize = 4;
+const MAX_CACHE_SIZE: usize = 256;
+
+/// Boot-time knobs for the frame allocator.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AllocatorConfig {
+    /// Zero every free frame once during init (slow but secure), instead of
+    /// handing out whatever was left in RAM by firmware or a previous boot.
+    pub zero_free_frames: bool,
+    /// Frames pulled from the global free lists on a hart cache miss.
+    /// `None` leaves it to the cache strategy, which derives it from the cache target.
+    pub refill_batch: Option<usize>,
+    /// Largest single allocation in bytes, bigger requests fail with `AllocError::SizeTooLarge`.
+    /// `None` allows anything that fits in free memory.
+    pub alloc_limit: Option<usize>,
+    /// Bytes reserved at boot for DMA buffers, `None` uses `DEFAULT_DMA_POOL_SIZE`, `Some(0)` disables the pool.
+    pub dma_pool_size: Option<usize>,
+    /// Print every block placed by the init distribution, then the block count per order.
+    pub log_distribution: bool,
+    /// Free frames to test once at boot, bad ones are never handed out.
+    pub memtest: MemtestMode,
+}
+
+/// Why `FrameAllocator::try_alloc` rejected a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocError {
+    /// Alignment above `BASE_SIZE`, which blocks are not guaranteed to have.
+    UnsupportedAlignment { align: usize },
+    /// The request exceeds the allocation size limit.
+    SizeTooLarge { requested: usize, available: usize },
+}
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AllocError::UnsupportedAlignment { align } => {
+                write!(f, "alignment {:#x} exceeds the frame size", align)
+            }
+            AllocError::SizeTooLarge {
+                requested,
+                available,
+            } => write!(
+                f,
+                "requested {} bytes, at most {} bytes can be allocated",
+                requested, available
+            ),
+        }
+    }
+}
+
+impl AllocatorConfig {
+    /// Builds a config from the kernel command line (`/chosen/bootargs`).
+    ///
+    /// Recognized flags:
+    /// - `mem.zero_frames`: enables `zero_free_frames`
+    /// - `mem.refill_batch=<n>`: sets `refill_batch`, ignored unless `n` is a positive integer
+    /// - `mem.alloc_limit=<bytes>`: sets `alloc_limit`, ignored unless `bytes` is a positive integer
+    /// - `mem.dma_pool=<bytes>`: sets `dma_pool_size`, ignored unless `bytes` is an integer
+    /// - `mem.log_init`: enables `log_distribution`
+    /// - `memtest=off|quick|full`: sets `memtest`, ignored for any other value
+    pub fn from_bootargs(bootargs: Option<&str>) -> Self {
+        let mut config = Self::default();
+
+        for arg in bootargs.unwrap_or_default().split_whitespace() {
+            if arg == "mem.zero_frames" {
+                config.zero_free_frames = true;
+            } else if let Some(value) = arg.strip_prefix("mem.refill_batch=") {
+                config.refill_batch = value.parse().ok().filter(|&batch| batch > 0);
+            } else if let Some(value) = arg.strip_prefix("mem.alloc_limit=") {
+                config.alloc_limit = value.parse().ok().filter(|&size| size > 0);
+            } else if let Some(value) = arg.strip_prefix("mem.dma_pool=") {
+                config.dma_pool_size = value.parse().ok();
+            } else if arg == "mem.log_init" {
+                config.log_distribution = true;
+            } else if let Some(value) = arg.strip_prefix("memtest=") {
+                config.memtest = MemtestMode::parse(value).unwrap_or_default();
+            }
+        }
+
+        config
+    }
+}
 
 pub struct FrameAllocator {
-    free_lists: Spinlock<FreeLists>,
-    hart_caches: [UnsafeCell<HartCache<Frame, Quartering>>; MAX_HARTS], // TODO: make dynamic based on number of harts
+    free_lists: OrderedSpinlock<FreeLists, FREE_LISTS_LOCK_LEVEL>,
+    hart_caches: HartCaches<Frame, Quartering>,
 
     orders: u8,
+    refill_batch: Option<usize>,
+    alloc_limit: Option<usize>,
     memory_map: *const PhysicalMemoryMap,
+    // hart id + 1 of the hart holding the free lists frozen, 0 when thawed
+    frozen_by: AtomicUsize,
 }
 
 impl FrameAllocator {
@@ -29,7 +122,7 @@ impl FrameAllocator {
     /// These regions must be exclusively owned by the allocator and sized correctly.
     ///
     /// A raw pointer is used for performance and FFI-compatibility; no aliasing or concurrent access is allowed.
-    pub unsafe fn init(pmem_map: *const PhysicalMemoryMap) -> Self {
+    pub unsafe fn init(pmem_map: *const PhysicalMemoryMap, config: AllocatorConfig) -> Self {
         let memory_map = unsafe { &*pmem_map };
         // create frame metadata slice in the frame pool region
         let frame_slice = unsafe {
@@ -74,46 +167,156 @@ impl FrameAllocator {
 
         let mut free_lists = FreeLists::new(free_lists);
 
-        let mut current_free_address = memory_map.free_memory.start();
-        let mut frames_left = memory_map.free_memory.size() / BASE_SIZE;
+        // everything outside the free memory spans stays reserved for good
+        frame_slice
+            .iter_mut()
+            .for_each(|frame| frame.set_state(State::Reserved));
+
+        let free_frames = Self::free_spans(memory_map)
+            .map(|(start, end)| {
+                Self::distribute_span(memory_map, frame_slice, &mut free_lists, start, end, config)
+            })
+            .sum::<usize>();
+
+        let reserved_frames = memory_map
+            .free_regions()
+            .map(|region| {
+                region
+                    .frame_indices(memory_map.frame_span.start())
+                    .filter(|&idx| matches!(frame_slice[idx].state(), State::Reserved))
+                    .count()
+            })
+            .sum::<usize>();
+
+        assert_eq!(
+            free_frames + reserved_frames,
+            memory_map.free_size() / BASE_SIZE,
+            "Uninitialized free memory detected"
+        );
+
+        if config.log_distribution {
+            for order in (0..orders).filter(|&order| free_lists.blocks_at_order(order) > 0) {
+                println!(
+                    "[init] order {:>2}: {} blocks",
+                    order,
+                    free_lists.blocks_at_order(order)
+                );
+            }
+        }
+
+        // each hart creates its own cache on first use. Seeding above works on the bare free
+        // lists: there is no `FrameAllocator` to call `hart_cache` on until this returns
+        let hart_caches =
+            HartCaches::new(DEFAULT_CACHE_SIZE).with_target_bounds(MIN_CACHE_SIZE, MAX_CACHE_SIZE);
+
+        FrameAllocator {
+            free_lists: OrderedSpinlock::new(free_lists),
+            hart_caches,
+            orders,
+            refill_batch: config.refill_batch,
+            alloc_limit: config.alloc_limit,
+            memory_map: pmem_map,
+            frozen_by: AtomicUsize::new(0),
+        }
+    }
+
+    /// Spans of free memory between the `reserved` regions, in address order within each
+    /// free region. A span never crosses from one region into another.
+    fn free_spans(
+        memory_map: &PhysicalMemoryMap,
+    ) -> impl Iterator<Item = (PhysicalAddress, PhysicalAddress)> + '_ {
+        memory_map.free_regions().flat_map(move |region| {
+            let mut span_start = region.start();
+
+            // reserved regions are sorted and clipped to a single free region, each one
+            // closes the span before it, an empty region at the end closes the last one
+            memory_map
+                .reserved
+                .iter()
+                .copied()
+                .filter(move |reserved| region.contains(reserved.start()))
+                .chain(core::iter::once(MemoryRegion::new(region.end(), 0)))
+                .filter_map(move |reserved| {
+                    let span =
+                        (span_start < reserved.start()).then_some((span_start, reserved.start()));
+                    span_start = span_start.max(reserved.end());
+                    span
+                })
+        })
+    }
+
+    /// Splits `[start, end)` into the largest naturally aligned blocks, as `(block start, order)`.
+    fn span_blocks(
+        start: PhysicalAddress,
+        end: PhysicalAddress,
+    ) -> impl Iterator<Item = (PhysicalAddress, u8)> {
+        let mut current = start;
+
+        core::iter::from_fn(move || {
+            if current >= end {
+                return None;
+            }
+
+            let frames_left = (end - current) / BASE_SIZE;
+            let alignment_order = current.as_usize().trailing_zeros() - BASE_SIZE.trailing_zeros();
+            let order = frames_left.ilog2().min(alignment_order) as u8;
+
+            let block = (current, order);
+            current += (1 << order) * BASE_SIZE;
+            Some(block)
+        })
+    }
+
+    /// Greedy algorithm to distribute the free memory span `[start, end)` into free lists
+    /// starting from the highest order memory block available.
+    ///
+    /// Every block is naturally aligned to its size, which `PhysicalMemoryMap::buddy_address`
+    /// relies on to find buddies by flipping a single address bit.
+    ///
+    /// Returns the number of frames handed to the free lists.
+    fn distribute_span(
+        memory_map: &PhysicalMemoryMap,
+        frame_slice: &mut [Frame],
+        free_lists: &mut FreeLists,
+        start: PhysicalAddress,
+        end: PhysicalAddress,
+        config: AllocatorConfig,
+    ) -> usize {
+        if config.zero_free_frames {
+            // SAFETY: the span is exclusively owned by the allocator
+            // and nothing has been handed out from it yet
+            unsafe { start.as_mut_slice(end - start) }.fill(0);
+        }
+
+        let span = MemoryRegion::new(start, end - start);
+        let span_frames = span.size() / BASE_SIZE;
+
+        span.frame_indices(memory_map.frame_span.start())
+            .for_each(|idx| frame_slice[idx].set_state(State::Free));
 
-        // greedy algorithm to distribute free memory blocks into free lists
-        // starting from the highest order memory block available
-        while frames_left > 0 {
-            let largest_block_order = frames_left.ilog2();
-            let largest_block_frames = 1 << largest_block_order;
-            let largest_block_bytes = largest_block_frames * BASE_SIZE;
+        let mut distributed_frames = 0;
 
-            let head_frame_idx = (current_free_address - memory_map.ram.start()) / BASE_SIZE;
-            let head_frame = &mut frame_slice[head_frame_idx];
+        for (block_start, order) in Self::span_blocks(start, end) {
+            let head_frame = &mut frame_slice[memory_map.frame_idx_from_address(block_start)];
 
-            head_frame.set_order(largest_block_order as u8);
+            head_frame.set_order(order, free_lists.orders());
+
+            if config.log_distribution {
+                println!("[init] block {} order {}", block_start, order);
+            }
 
             // set the frame with correspondng order as a head of the ordered free list
             free_lists.push_frame(NonNull::from(head_frame));
 
-            frames_left -= largest_block_frames;
-            current_free_address += largest_block_bytes;
+            distributed_frames += 1 << order;
         }
 
-        assert_eq!(frames_left, 0, "Not all frames were initialized");
         assert_eq!(
-            current_free_address,
-            memory_map.free_memory.end(),
+            distributed_frames, span_frames,
             "Uninitialized free memory detected"
         );
 
-        // TODO: check initialization
-        let hart_caches = core::array::from_fn(|_| {
-            UnsafeCell::new(HartCache::new(DEFAULT_CACHE_SIZE, Quartering))
-        });
-
-        FrameAllocator {
-            free_lists: Spinlock::new(free_lists),
-            hart_caches,
-            orders,
-            memory_map: pmem_map,
-        }
+        span_frames
     }
 
     pub fn orders(&self) -> u8 {
@@ -124,10 +327,294 @@ impl FrameAllocator {
         self.free_lists.lock().bitmap_bits()
     }
 
+    /// Snapshot of the global free lists, or `None` if their lock is held.
+    ///
+    /// Never blocks, so it is safe to call from the panic handler.
+    pub fn try_snapshot(&self) -> Option<FreeListsSnapshot> {
+        self.free_lists
+            .try_lock()
+            .map(|free_lists| free_lists.snapshot())
+    }
+
+    /// Number of free blocks of the given order in the global free lists.
+    ///
+    /// Frames parked in hart caches are not taken into account.
+    pub fn blocks_at_order(&self, order: u8) -> usize {
+        self.free_lists.lock().blocks_at_order(order)
+    }
+
+    /// Size in bytes of the largest contiguous block currently available,
+    /// or `0` if the global free lists are empty.
+    ///
+    /// Frames parked in hart caches are not taken into account.
+    pub fn max_alloc_size(&self) -> usize {
+        self.largest_available_order()
+            .map_or(0, |order| (1 << order) * BASE_SIZE)
+    }
+
+    /// Highest order with a free block in the global free lists, `None` if they are empty.
+    pub fn largest_available_order(&self) -> Option<u8> {
+        self.free_lists.lock().find_last_free()
+    }
+
+    /// Returns `true` if `alloc(layout)` would currently succeed instead of panicking,
+    /// so callers can fall back to a smaller request.
+    ///
+    /// Only a snapshot: other harts may take the block before the actual `alloc`.
+    pub fn can_alloc(&self, layout: Layout) -> bool {
+        if layout.align() > BASE_SIZE {
+            return false;
+        }
+
+        if layout.size() == 0 {
+            return true;
+        }
+
+        let order = self.order_from_size(layout.size());
+
+        (order == 0 && !self.hart_cache(current_hart_id()).is_empty())
+            || self
+                .largest_available_order()
+                .is_some_and(|largest| largest >= order)
+    }
+
+    /// Returns every frame parked in `hart_id`'s cache to the global free lists.
+    ///
+    /// For a hart going offline: that hart must not touch the allocator meanwhile.
+    pub fn flush_hart_cache(&self, hart_id: usize) {
+        let Some(cache) = self.hart_caches.get_initialized(hart_id) else {
+            return;
+        };
+
+        cache
+            .drain_all()
+            .for_each(|frame| self.free_to_global(frame));
+
+        debug_assert!(cache.is_empty(), "Hart {} frame cache not drained", hart_id);
+    }
+
+    /// Returns every frame parked in any hart cache to the global free lists,
+    /// e.g. before suspend so the free lists describe all free memory.
+    ///
+    /// Other harts must not touch the allocator meanwhile.
+    pub fn drain_all_caches(&self) {
+        for hart_id in 0..MAX_HARTS {
+            self.flush_hart_cache(hart_id);
+        }
+    }
+
+    /// Drains every hart cache and merges all free buddy pairs left on the free lists,
+    /// to recover large contiguous blocks before a big allocation. Returns the number of
+    /// merges.
+    ///
+    /// Other harts must not touch the allocator meanwhile, see `drain_all_caches`.
+    pub fn defragment(&self) -> usize {
+        self.drain_all_caches();
+        self.lock_free_lists().coalesce_all(self.memory_map())
+    }
+
+    /// Runs `f` with the global free lists frozen, so the snapshots it takes agree with each
+    /// other. Other harts keep allocating from their caches but block on their next refill,
+    /// drain or free to the global lists until `f` returns.
+    ///
+    /// Interrupts stay off meanwhile: the maintenance tick can't run on this hart, and on
+    /// other harts it just waits for the thaw. `f` must not wait for another hart, nor block
+    /// on a lock one may hold while waiting for the thaw, e.g. the slab lists: `try_lock`
+    /// them instead. Freezing again from within `f` only runs the inner closure.
+    pub fn with_frozen<R>(&self, f: impl FnOnce() -> R) -> R {
+        cpu::with_interrupts_disabled(|| {
+            let owner = current_hart_id() + 1;
+            loop {
+                match self
+                    .frozen_by
+                    .compare_exchange(0, owner, Ordering::SeqCst, Ordering::Relaxed)
+                {
+                    Ok(_) => break,
+                    Err(frozen_by) if frozen_by == owner => return f(),
+                    // another hart's freeze
+                    Err(_) => core::hint::spin_loop(),
+                }
+            }
+
+            // a mutation that took the lock before the flag was set finishes first,
+            // later ones see the flag once they hold the lock
+            drop(self.free_lists.lock());
+
+            let result = f();
+            self.frozen_by.store(0, Ordering::Release);
+            result
+        })
+    }
+
+    /// `false` while another hart than `hart_id` holds the free lists frozen.
+    fn admits(&self, hart_id: usize) -> bool {
+        let frozen_by = self.frozen_by.load(Ordering::Acquire);
+        frozen_by == 0 || frozen_by == hart_id + 1
+    }
+
+    /// The free lists lock, for changing them, once they are not frozen by another hart.
+    fn lock_free_lists(&self) -> OrderedSpinlockGuard<'_, FreeLists, FREE_LISTS_LOCK_LEVEL> {
+        let hart_id = current_hart_id();
+        loop {
+            let free_lists = self.free_lists.lock();
+            if self.admits(hart_id) {
+                return free_lists;
+            }
+            drop(free_lists);
+
+            while !self.admits(hart_id) {
+                core::hint::spin_loop();
+            }
+        }
+    }
+
+    /// Checks a freeze shuts out other harts only, nests, and ends with `with_frozen`.
+    pub fn check_freeze(&self) {
+        let hart_id = current_hart_id();
+        let other_hart = (hart_id + 1) % MAX_HARTS;
+        let layout =
+            Layout::from_size_align(4 * BASE_SIZE, BASE_SIZE).expect("Invalid test layout");
+
+        self.with_frozen(|| {
+            assert!(!self.admits(other_hart), "Freeze let another hart in");
+            assert!(self.admits(hart_id), "Freeze shut out its own hart");
+
+            // the freezing hart still goes through the global lists
+            let block = self.alloc(layout).expect("Allocation while frozen failed");
+            self.dealloc(block, layout);
+
+            self.with_frozen(|| assert!(!self.admits(other_hart)));
+            assert!(
+                !self.admits(other_hart),
+                "Nested freeze thawed the allocator"
+            );
+        });
+
+        assert!(self.admits(other_hart), "Allocator still frozen");
+    }
+
+    /// Breaks the largest free block down to order 0 without merging, then checks
+    /// `coalesce_all` rebuilds it and leaves the memory map alone.
+    ///
+    /// Nothing else may touch the allocator meanwhile.
+    pub fn check_defragment(&self) {
+        // the map is immutable once set, splitting and merging blocks must not touch it
+        let map_watch = Watch::new(MemoryRegion::new(
+            PhysicalAddress::from(self.memory_map() as *const PhysicalMemoryMap as usize),
+            size_of::<PhysicalMemoryMap>(),
+        ));
+
+        let mut free_lists = self.free_lists.lock();
+        let Some(order) = free_lists.find_last_free().filter(|&order| order > 0) else {
+            return;
+        };
+        let blocks_before = free_lists.blocks_at_order(order);
+
+        let mut head_ptr = free_lists
+            .pop_frame(order)
+            .expect("Bitmap set for an empty order");
+        let head_addr = self
+            .memory_map()
+            .frame_ref_to_address(unsafe { head_ptr.as_ref() });
+
+        // keep the lower half, hand out the upper one, down to a single frame
+        for half_order in (0..order).rev() {
+            let upper_addr = head_addr + (1 << half_order) * BASE_SIZE;
+            let mut upper_ptr = self.memory_map().address_to_frame_ptr(upper_addr);
+            let upper = unsafe { upper_ptr.as_mut() };
+            upper.set_state(State::Free);
+            upper.set_order(half_order, self.orders);
+            free_lists.push_frame(upper_ptr);
+        }
+        unsafe { head_ptr.as_mut() }.set_order(0, self.orders);
+        free_lists.push_frame(head_ptr);
+
+        assert_eq!(free_lists.blocks_at_order(order), blocks_before - 1);
+
+        let merged = free_lists.coalesce_all(self.memory_map());
+        assert_eq!(
+            merged, order as usize,
+            "coalesce_all did not merge every split level"
+        );
+        assert_eq!(
+            free_lists.blocks_at_order(order),
+            blocks_before,
+            "coalesce_all did not rebuild the order {} block",
+            order
+        );
+
+        drop(free_lists);
+        map_watch.verify();
+    }
+
+    /// Checks the free lists hold exactly the blocks `init` started with, i.e. every
+    /// freed frame was merged back into the largest block its span allows.
+    ///
+    /// Only meaningful when nothing is allocated and every hart cache was drained
+    /// (see `drain_all_caches`), other harts must not touch the allocator meanwhile.
+    pub fn assert_fully_coalesced(&self) {
+        if let Err(issue) = self.check_coalesced() {
+            panic!("{}", issue);
+        }
+    }
+
+    /// Non-panicking `assert_fully_coalesced`, returns the first mismatch found.
+    pub fn check_coalesced(&self) -> Result<(), HealthIssue> {
+        if let Some(hart_id) = (0..MAX_HARTS).find(|&hart_id| {
+            self.hart_caches
+                .get_initialized(hart_id)
+                .is_some_and(|cache| !cache.is_empty())
+        }) {
+            return Err(HealthIssue::HartCacheNotEmpty { hart_id });
+        }
+
+        let mut expected = [0usize; u64::BITS as usize];
+        Self::free_spans(self.memory_map())
+            .flat_map(|(start, end)| Self::span_blocks(start, end))
+            .for_each(|(_, order)| expected[order as usize] += 1);
+
+        let free_lists = self.free_lists.lock();
+
+        for order in 0..self.orders {
+            let found = free_lists.blocks_at_order(order);
+            if found != expected[order as usize] {
+                return Err(HealthIssue::NotCoalesced {
+                    order,
+                    expected: expected[order as usize],
+                    found,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records free list inconsistencies in `report`, plus coalescing mismatches if `idle`
+    /// (nothing allocated, caches drained, see `assert_fully_coalesced`).
+    ///
+    /// Never blocks on the free lists, a held lock is reported instead.
+    pub fn check_health(&self, idle: bool, report: &mut HealthReport) {
+        match self.free_lists.try_lock() {
+            Some(free_lists) => free_lists.check(report),
+            None => {
+                report.push(HealthIssue::LockHeld("free lists"));
+                return;
+            }
+        }
+
+        if idle && let Err(issue) = self.check_coalesced() {
+            report.push(issue);
+        }
+    }
+
+    /// The cache of `hart_id`, created on first access.
+    ///
+    /// Needs no readiness check: a `FrameAllocator` only exists once `init` has seeded the
+    /// free lists, and an uncreated cache is an empty `OnceLock`, never uninitialized memory.
     #[inline]
     #[allow(clippy::mut_from_ref)]
     fn hart_cache(&self, hart_id: usize) -> &mut HartCache<Frame, Quartering> {
-        unsafe { &mut *self.hart_caches[hart_id].get() }
+        self.hart_caches.get(hart_id)
     }
 
     fn memory_map(&self) -> &PhysicalMemoryMap {
@@ -142,29 +629,68 @@ impl FrameAllocator {
         frames.next_power_of_two().ilog2() as u8
     }
 
-    // TODO: cosider result return type with error types later
     pub fn alloc(&self, layout: Layout) -> Option<NonNull<u8>> {
+        self.try_alloc(layout).ok()
+    }
+
+    /// Like `alloc`, with the whole block zeroed, not only the requested bytes.
+    pub fn alloc_zeroed(&self, layout: Layout) -> Option<NonNull<u8>> {
+        let ptr = self.alloc(layout)?;
+        if layout.size() == 0 {
+            return Some(ptr);
+        }
+
+        let block_size = (1 << self.order_from_size(layout.size())) * BASE_SIZE;
+        // SAFETY: the block was just handed out and spans `block_size` bytes
+        unsafe { ptr.write_bytes(0, block_size) };
+
+        Some(ptr)
+    }
+
+    /// Largest request `try_alloc` accepts: the configured limit, capped by free memory.
+    pub fn alloc_size_limit(&self) -> usize {
+        let free_memory_size = self.memory_map().free_size();
+        self.alloc_limit
+            .map_or(free_memory_size, |limit| limit.min(free_memory_size))
+    }
+
+    /// Like `alloc`, but says why a request was rejected.
+    ///
+    /// Running out of memory still panics.
+    pub fn try_alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
         // TODO: decide if I want to allocate aligned-up size in that case
         if layout.align() > BASE_SIZE {
-            return None;
+            return Err(AllocError::UnsupportedAlignment {
+                align: layout.align(),
+            });
         }
 
         let size = layout.size();
 
         if size == 0 {
-            return Some(NonNull::dangling());
+            return Ok(NonNull::dangling());
         }
 
-        assert!(
-            size < self.memory_map().free_memory.size(),
-            "Requested size exceeds available memory"
-        );
+        let available = self.alloc_size_limit();
+        if size > available {
+            return Err(AllocError::SizeTooLarge {
+                requested: size,
+                available,
+            });
+        }
 
         let order = self.order_from_size(size);
 
+        // every path below either hands out the block or panics
+        trace::emit(|| TraceEvent::Alloc {
+            hart_id: current_hart_id(),
+            size,
+            order,
+        });
+
         if order == 0 {
             match self.get_from_cache() {
-                Some(head_frame) => return self.finalize_frame_allocation(head_frame),
+                Some(head_frame) => return Ok(self.finalize_frame_allocation(head_frame)),
                 None =>
                 // TODO: handle oom properly
                 {
@@ -177,7 +703,7 @@ impl FrameAllocator {
         }
 
         match self.prepare_block(order) {
-            Some(head_frame) => self.finalize_frame_allocation(head_frame),
+            Some(head_frame) => Ok(self.finalize_frame_allocation(head_frame)),
             None =>
             // TODO: handle oom properly
             {
@@ -193,12 +719,12 @@ impl FrameAllocator {
         self.get_from_cache()
     }
 
-    fn finalize_frame_allocation(&self, mut frame_ptr: NonNull<Frame>) -> Option<NonNull<u8>> {
+    fn finalize_frame_allocation(&self, mut frame_ptr: NonNull<Frame>) -> NonNull<u8> {
         let frame = unsafe { frame_ptr.as_mut() };
         frame.set_state(State::Allocated);
         let frame_addr = self.memory_map().frame_ref_to_address(frame);
 
-        NonNull::new(frame_addr.as_mut_ptr::<u8>())
+        NonNull::new(frame_addr.as_mut_ptr::<u8>()).expect("Allocated frame at null address")
     }
 
     fn get_from_cache(&self) -> Option<NonNull<Frame>> {
@@ -210,20 +736,129 @@ impl FrameAllocator {
         }
 
         // refill
-        for _ in 0..cache.refill_amount() {
-            if let Some(frame_ptr) = self.prepare_block(0) {
-                cache.push(frame_ptr);
-            } else {
+        cache.record_refill();
+        let batch = self.refill_batch.unwrap_or_else(|| cache.refill_amount());
+
+        let mut refilled = 0;
+        for _ in 0..batch {
+            let Some(frame_ptr) = self.prepare_block(0) else {
                 // global allocator is out of order-0 frames
                 break;
+            };
+
+            // a configured batch may exceed what the cache can hold
+            if let Err(frame_ptr) = cache.try_push(frame_ptr) {
+                self.free_to_global(frame_ptr);
+                break;
             }
+            refilled += 1;
         }
 
+        trace::emit(|| TraceEvent::Refill {
+            hart_id,
+            count: refilled,
+        });
+
         cache.pop()
     }
 
+    /// Keeps an idle hart's cache warm: tops it up to the strategy's low watermark so
+    /// the next allocations after wakeup don't hit the global lock, and returns anything
+    /// above `target_size` so idle harts don't hoard frames.
+    ///
+    /// Meant to be called periodically from the hart's own idle loop.
+    pub fn touch_hart_cache(&self, hart_id: usize) {
+        debug_assert_eq!(
+            hart_id,
+            current_hart_id(),
+            "Hart caches may only be touched by their own hart"
+        );
+
+        let cache = self.hart_cache(hart_id);
+
+        while cache.len() < cache.low_watermark() {
+            match self.prepare_block(0) {
+                Some(frame_ptr) => cache.push(frame_ptr),
+                // global allocator is out of order-0 frames
+                None => break,
+            }
+        }
+
+        while cache.len() > cache.target_size() {
+            let frame_to_free = cache.pop().unwrap();
+            self.free_to_global(frame_to_free);
+        }
+    }
+
+    /// Takes the frames spanning `[start, start + size)` out of the free lists and marks them
+    /// `Allocated`, so they are never handed out. Buddy blocks are split as needed so only the
+    /// exact frames are removed.
+    ///
+    /// Nothing is reserved if any frame in the range is not free (allocated, reserved or parked
+    /// in a hart cache), its address is returned as the error instead.
+    pub fn reserve_range(
+        &self,
+        start: PhysicalAddress,
+        size: usize,
+    ) -> Result<(), PhysicalAddress> {
+        let range_start = start.align_down(BASE_SIZE);
+        let range_end = (start + size).align_up(BASE_SIZE);
+        let memory_map = self.memory_map();
+
+        let mut free_lists = self.lock_free_lists();
+
+        // validate the whole range first so a failure leaves the allocator untouched
+        let mut frame_addr = range_start;
+        while frame_addr < range_end {
+            if !memory_map.is_ram(frame_addr)
+                || free_lists
+                    .find_block_containing(frame_addr, memory_map)
+                    .is_none()
+            {
+                return Err(frame_addr);
+            }
+            frame_addr += BASE_SIZE;
+        }
+
+        let mut frame_addr = range_start;
+        while frame_addr < range_end {
+            let mut block = free_lists
+                .find_block_containing(frame_addr, memory_map)
+                .expect("Frame vanished from the free lists during reservation");
+            free_lists.remove_frame(block);
+
+            // split down to the single frame, returning the other halves to the free lists
+            let mut block_order = unsafe { block.as_ref().order() };
+            while block_order > 0 {
+                block_order -= 1;
+
+                let block_addr = memory_map.frame_ref_to_address(unsafe { block.as_ref() });
+                let half_size = (1 << block_order) * BASE_SIZE;
+                let upper_addr = block_addr + half_size;
+                let mut upper = memory_map.address_to_frame_ptr(upper_addr);
+
+                unsafe {
+                    block.as_mut().set_order(block_order, self.orders);
+                    upper.as_mut().set_order(block_order, self.orders);
+                }
+
+                if frame_addr < upper_addr {
+                    free_lists.push_frame(upper);
+                } else {
+                    free_lists.push_frame(block);
+                    block = upper;
+                }
+            }
+
+            unsafe { block.as_mut().set_state(State::Allocated) };
+            frame_addr += BASE_SIZE;
+        }
+
+        Ok(())
+    }
+
     fn prepare_block(&self, requested_order: u8) -> Option<NonNull<Frame>> {
-        let mut free_lists = self.free_lists.lock();
+        let mut free_lists = self.lock_free_lists();
 
         let found_order = free_lists.find_first_free_from(requested_order)?;
 
@@ -241,8 +876,12 @@ impl FrameAllocator {
             let buddy_frame_ref = unsafe { buddy_frame_ptr.as_mut() };
 
             // downgrade blocks order, i.e `split`
-            unsafe { block_to_split.as_mut().set_order(current_order) };
-            buddy_frame_ref.set_order(current_order);
+            unsafe {
+                block_to_split
+                    .as_mut()
+                    .set_order(current_order, self.orders)
+            };
+            buddy_frame_ref.set_order(current_order, self.orders);
 
             free_lists.push_frame(NonNull::from(buddy_frame_ref));
         }
@@ -250,6 +889,28 @@ impl FrameAllocator {
         Some(block_to_split)
     }
 
+    /// Returns `true` if `ptr` is the start of a live block handed out by `alloc(layout)`:
+    /// inside free memory, aligned to the block size and backed by an allocated head
+    /// frame of the matching order.
+    ///
+    /// Meant for catching wild pointers and double frees, not for hot paths.
+    pub fn is_valid_allocation(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        let address = PhysicalAddress::from(ptr.as_ptr() as usize);
+        let memory_map = self.memory_map();
+
+        if memory_map.classify(address) != RegionKind::FreeMemory {
+            return false;
+        }
+
+        let order = self.order_from_size(layout.size());
+        if !address.is_aligned_to((1 << order) * BASE_SIZE) {
+            return false;
+        }
+
+        let frame = unsafe { memory_map.address_to_frame_ptr(address).as_ref() };
+        matches!(frame.state(), State::Allocated) && frame.order() == order
+    }
+
     pub fn dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
         if layout.size() == 0 {
             return; // ZST dropped
@@ -258,8 +919,9 @@ impl FrameAllocator {
         let current_addr = PhysicalAddress::from(ptr.as_ptr() as usize);
 
         assert!(
-            self.memory_map().ram.contains(current_addr),
-            "Attempted to deallocate a pointer outside managed memory"
+            self.memory_map().is_ram(current_addr),
+            "Attempted to deallocate a pointer outside managed memory: {}",
+            current_addr
         );
 
         let mut current_frame_ptr = self.memory_map().address_to_frame_ptr(current_addr);
@@ -270,11 +932,23 @@ impl FrameAllocator {
             "Double free detected at address {:#x}",
             current_addr.as_usize()
         );
+        debug_assert!(
+            self.is_valid_allocation(ptr, layout),
+            "Invalid dealloc at {}: not the start of a live size={} allocation",
+            current_addr,
+            layout.size()
+        );
 
         current_frame_ref.set_state(State::Free);
 
         let order = current_frame_ref.order();
 
+        trace::emit(|| TraceEvent::Dealloc {
+            hart_id: current_hart_id(),
+            size: layout.size(),
+            order,
+        });
+
         if order > 0 {
             self.free_to_global(current_frame_ptr);
             return;
@@ -288,11 +962,26 @@ impl FrameAllocator {
         }
 
         // trim full cache
-        for _ in 0..cache.drain_amount() {
+        cache.record_drain();
+        let mut drained = cache.drain_amount();
+        for _ in 0..drained {
             let frame_to_free = cache.pop().unwrap();
             self.free_to_global(frame_to_free);
         }
 
+        // a shrink may have left the cache above its new watermark
+        while cache.is_full()
+            && let Some(frame_to_free) = cache.pop()
+        {
+            self.free_to_global(frame_to_free);
+            drained += 1;
+        }
+
+        trace::emit(|| TraceEvent::Drain {
+            hart_id,
+            count: drained,
+        });
+
         cache.push(current_frame_ptr);
     }
 
@@ -302,12 +991,14 @@ impl FrameAllocator {
         let mut current_addr = self.memory_map().frame_ref_to_address(current_frame_ref);
         let mut current_order = current_frame_ref.order();
 
-        let mut free_lists = self.free_lists.lock();
+        let mut free_lists = self.lock_free_lists();
 
         while current_order < self.orders - 1 {
-            // calculate buddy address
-            let buddy_offset = (1 << current_order) * BASE_SIZE;
-            let buddy_addr = current_addr ^ buddy_offset;
+            let Some(buddy_addr) = self.memory_map().buddy_address(current_addr, current_order)
+            else {
+                // buddy would fall outside of free memory, nothing to merge with
+                break;
+            };
 
             let mut buddy_frame_ptr = self.memory_map().address_to_frame_ptr(buddy_addr);
             let buddy_frame_ref = unsafe { buddy_frame_ptr.as_mut() };
@@ -325,7 +1016,7 @@ impl FrameAllocator {
 
                 // increase the order for the new block
                 current_order += 1;
-                current_frame_ref.set_order(current_order);
+                current_frame_ref.set_order(current_order, self.orders);
             } else {
                 // buddy is not free or is a different size, stop
                 break;
@@ -338,3 +1029,456 @@ impl FrameAllocator {
 
 unsafe impl Send for FrameAllocator {}
 unsafe impl Sync for FrameAllocator {}
+
+/// Checks the greedy split of a span into naturally aligned blocks, for an aligned and a
+/// misaligned start.
+pub fn check_span_blocks() {
+    let base = 0x8000_0000;
+    let frame = |idx: usize| PhysicalAddress::from(base + idx * BASE_SIZE);
+
+    // 13 frames from an aligned start: largest first, 8 + 4 + 1
+    assert!(
+        FrameAllocator::span_blocks(frame(0), frame(13)).eq([
+            (frame(0), 3),
+            (frame(8), 2),
+            (frame(12), 0)
+        ]),
+        "Aligned span split into the wrong blocks"
+    );
+
+    // 15 frames from frame 1: alignment caps each order until the start catches up
+    assert!(
+        FrameAllocator::span_blocks(frame(1), frame(16)).eq([
+            (frame(1), 0),
+            (frame(2), 1),
+            (frame(4), 2),
+            (frame(8), 3)
+        ]),
+        "Misaligned span split into the wrong blocks"
+    );
+}
+
+/// Runs `f` on a fresh allocator over a `frames` frame buffer taken from the frame
+/// allocator, with `config`. The buffer is handed back afterwards.
+#[cfg(debug_assertions)]
+pub(crate) fn with_buffer_allocator(
+    frames: usize,
+    config: AllocatorConfig,
+    f: impl FnOnce(&FrameAllocator, &PhysicalMemoryMap),
+) {
+    let layout =
+        Layout::from_size_align(frames * BASE_SIZE, BASE_SIZE).expect("Invalid buffer layout");
+    let block = crate::memory::frame_allocator()
+        .alloc(layout)
+        .expect("Failed to allocate the test buffer");
+    // SAFETY: the block is owned by this check until it is freed below
+    let buffer = unsafe { core::slice::from_raw_parts_mut(block.as_ptr(), layout.size()) };
+    let map = PhysicalMemoryMap::from_buffer(buffer);
+    let allocator = unsafe { FrameAllocator::init(&map as *const PhysicalMemoryMap, config) };
+
+    f(&allocator, &map);
+
+    // the test allocator's metadata lives in the buffer, it is unusable from here on
+    crate::memory::frame_allocator().dealloc(block, layout);
+}
+
+/// Frames on `allocator`'s global free lists, hart caches not included.
+#[cfg(debug_assertions)]
+fn listed_frames(allocator: &FrameAllocator) -> usize {
+    (0..allocator.orders())
+        .map(|order| allocator.blocks_at_order(order) << order)
+        .sum()
+}
+
+/// Allocates every frame of a buffer-backed allocator one by one, frees them all in an
+/// interleaved order and checks the free lists end up as coalesced as right after `init`.
+/// Needs the frame allocator.
+#[cfg(debug_assertions)]
+pub fn check_full_coalescing() {
+    const BUFFER_FRAMES: usize = 64;
+
+    with_buffer_allocator(
+        BUFFER_FRAMES,
+        AllocatorConfig::default(),
+        |allocator, map| {
+            let mut frames = [None; BUFFER_FRAMES];
+            let mut allocated = 0;
+            while allocator.can_alloc(BASE_SIZE_LAYOUT) {
+                frames[allocated] = allocator.alloc(BASE_SIZE_LAYOUT);
+                allocated += 1;
+            }
+            assert_eq!(
+                allocated,
+                map.free_size() / BASE_SIZE,
+                "Not every free frame was handed out"
+            );
+
+            // even frames first, so most buddies are freed apart from each other
+            for idx in (0..allocated).step_by(2).chain((1..allocated).step_by(2)) {
+                let frame = frames[idx].take().expect("Allocated frame missing");
+                allocator.dealloc(frame, BASE_SIZE_LAYOUT);
+            }
+
+            allocator.drain_all_caches();
+            allocator.assert_fully_coalesced();
+        },
+    );
+}
+
+/// Checks a hart cache miss pulls exactly the configured `refill_batch` frames from the
+/// free lists. Needs the frame allocator.
+#[cfg(debug_assertions)]
+pub fn check_refill_batch() {
+    const BATCH: usize = 8;
+
+    let config = AllocatorConfig {
+        refill_batch: Some(BATCH),
+        ..AllocatorConfig::default()
+    };
+    with_buffer_allocator(64, config, |allocator, _| {
+        let before = listed_frames(allocator);
+        let frame = allocator
+            .alloc(BASE_SIZE_LAYOUT)
+            .expect("Frame allocation failed");
+        assert_eq!(
+            before - listed_frames(allocator),
+            BATCH,
+            "Miss refilled the wrong batch"
+        );
+        assert_eq!(
+            allocator.hart_cache(current_hart_id()).len(),
+            BATCH - 1,
+            "Refilled frames did not land in the hart cache"
+        );
+
+        allocator.dealloc(frame, BASE_SIZE_LAYOUT);
+        allocator.drain_all_caches();
+    });
+}
+
+/// Checks `largest_available_order` and `can_alloc` track the largest blocks of a
+/// buffer-backed allocator as they are taken and given back. Needs the frame allocator.
+#[cfg(debug_assertions)]
+pub fn check_largest_available_order() {
+    with_buffer_allocator(64, AllocatorConfig::default(), |allocator, map| {
+        let largest = FrameAllocator::free_spans(map)
+            .flat_map(|(start, end)| FrameAllocator::span_blocks(start, end))
+            .map(|(_, order)| order)
+            .max();
+        assert_eq!(allocator.largest_available_order(), largest);
+
+        let top = largest.expect("Buffer has no free frames");
+        let layout =
+            Layout::from_size_align((1 << top) * BASE_SIZE, BASE_SIZE).expect("Invalid layout");
+        let twice = Layout::from_size_align(2 * layout.size(), BASE_SIZE).expect("Invalid layout");
+        assert!(allocator.can_alloc(layout), "Largest block not allocatable");
+        assert!(
+            !allocator.can_alloc(twice),
+            "Block beyond the largest allocatable"
+        );
+
+        let mut blocks = [None; 4];
+        for block in &mut blocks {
+            if allocator.blocks_at_order(top) == 0 {
+                break;
+            }
+            *block = allocator.alloc(layout);
+        }
+        assert!(
+            allocator.largest_available_order() < Some(top),
+            "Largest order still reported after taking every such block"
+        );
+        assert!(
+            !allocator.can_alloc(layout),
+            "Taken block still allocatable"
+        );
+
+        for block in blocks.into_iter().flatten() {
+            allocator.dealloc(block, layout);
+        }
+        assert_eq!(allocator.largest_available_order(), largest);
+    });
+}
+
+/// Checks `is_valid_allocation` accepts a live block and rejects a pointer into its middle,
+/// a mismatched layout and the block once freed. Needs the frame allocator.
+pub fn check_valid_allocation() {
+    let allocator = crate::memory::frame_allocator();
+    let layout = Layout::from_size_align(4 * BASE_SIZE, BASE_SIZE).expect("Invalid layout");
+
+    let block = allocator.alloc(layout).expect("Block allocation failed");
+    assert!(
+        allocator.is_valid_allocation(block, layout),
+        "Live block rejected"
+    );
+
+    let middle = unsafe { block.add(BASE_SIZE) };
+    assert!(
+        !allocator.is_valid_allocation(middle, layout),
+        "Pointer into the middle of a block accepted"
+    );
+    assert!(
+        !allocator.is_valid_allocation(block, BASE_SIZE_LAYOUT),
+        "Block accepted with the wrong order"
+    );
+
+    allocator.dealloc(block, layout);
+    assert!(
+        !allocator.is_valid_allocation(block, layout),
+        "Freed block accepted"
+    );
+}
+
+/// Checks `reserve_range` takes exactly two frames out of the middle of a larger free
+/// block, leaves its neighbours on the free lists and refuses to reserve them twice.
+/// Needs the frame allocator.
+#[cfg(debug_assertions)]
+pub fn check_reserve_range() {
+    with_buffer_allocator(64, AllocatorConfig::default(), |allocator, map| {
+        let (block, order) = FrameAllocator::free_spans(map)
+            .flat_map(|(start, end)| FrameAllocator::span_blocks(start, end))
+            .max_by_key(|&(_, order)| order)
+            .expect("Buffer has no free frames");
+        assert!(order >= 2, "No free block of 4 frames to reserve from");
+
+        let frame = |idx: usize| block + idx * BASE_SIZE;
+        let state = |address| *unsafe { map.address_to_frame_ptr(address).as_ref() }.state();
+        let is_listed = |address| {
+            allocator
+                .lock_free_lists()
+                .find_block_containing(address, map)
+                .is_some()
+        };
+
+        assert_eq!(allocator.reserve_range(frame(1), 2 * BASE_SIZE), Ok(()));
+        for idx in [1, 2] {
+            assert_eq!(
+                state(frame(idx)),
+                State::Allocated,
+                "Frame {} not reserved",
+                idx
+            );
+            assert!(!is_listed(frame(idx)), "Reserved frame {} still free", idx);
+        }
+        for idx in [0, 3] {
+            assert!(is_listed(frame(idx)), "Neighbour frame {} lost", idx);
+        }
+
+        assert_eq!(
+            allocator.reserve_range(frame(1), 2 * BASE_SIZE),
+            Err(frame(1)),
+            "Frames reserved twice"
+        );
+        assert_eq!(
+            allocator.reserve_range(frame(0), 2 * BASE_SIZE),
+            Err(frame(1)),
+            "Range over a reserved frame accepted"
+        );
+        assert!(is_listed(frame(0)), "Failed reservation took a frame");
+    });
+}
+
+/// Checks `touch_hart_cache` tops a cache left below its low watermark back up from the
+/// free lists. Needs the frame allocator.
+#[cfg(debug_assertions)]
+pub fn check_touch_hart_cache() {
+    with_buffer_allocator(64, AllocatorConfig::default(), |allocator, _| {
+        let hart_id = current_hart_id();
+
+        // the miss refills a few frames and hands one out, leaving the cache short
+        let frame = allocator
+            .alloc(BASE_SIZE_LAYOUT)
+            .expect("Frame allocation failed");
+        let cache = allocator.hart_cache(hart_id);
+        let short = cache.len();
+        assert!(
+            short < cache.low_watermark(),
+            "Cache not drained below its watermark"
+        );
+
+        let before = listed_frames(allocator);
+        allocator.touch_hart_cache(hart_id);
+        let cache = allocator.hart_cache(hart_id);
+        assert!(
+            cache.len() >= cache.low_watermark(),
+            "Touch left the cache short"
+        );
+        assert!(cache.len() <= cache.target_size());
+        assert_eq!(
+            before - listed_frames(allocator),
+            cache.len() - short,
+            "Touch did not take its frames from the free lists"
+        );
+
+        allocator.dealloc(frame, BASE_SIZE_LAYOUT);
+        allocator.drain_all_caches();
+    });
+}
+
+/// Checks `drain_all_caches` empties every initialized hart cache, this hart's and another
+/// one's, and puts exactly the frames they held back on the free lists. Needs the frame
+/// allocator.
+#[cfg(debug_assertions)]
+pub fn check_drain_all_caches() {
+    with_buffer_allocator(64, AllocatorConfig::default(), |allocator, _| {
+        let hart_id = current_hart_id();
+        let other_hart = (hart_id + 1) % MAX_HARTS;
+
+        // the miss parks a refill batch in this hart's cache, hand part of it to another hart
+        let frame = allocator
+            .alloc(BASE_SIZE_LAYOUT)
+            .expect("Frame allocation failed");
+        allocator.dealloc(frame, BASE_SIZE_LAYOUT);
+        for _ in 0..2 {
+            let frame = allocator.hart_cache(hart_id).pop().expect("Cache empty");
+            allocator.hart_cache(other_hart).push(frame);
+        }
+
+        let cached_frames = || {
+            (0..MAX_HARTS)
+                .filter_map(|hart_id| allocator.hart_caches.get_initialized(hart_id))
+                .map(|cache| cache.len())
+                .sum::<usize>()
+        };
+        let cached = cached_frames();
+        assert!(
+            !allocator.hart_cache(hart_id).is_empty(),
+            "Nothing left in this hart's cache"
+        );
+
+        let before = listed_frames(allocator);
+        allocator.drain_all_caches();
+        assert_eq!(cached_frames(), 0, "A hart cache kept frames");
+        assert_eq!(
+            listed_frames(allocator) - before,
+            cached,
+            "Drained frames missing from the free lists"
+        );
+    });
+}
+
+/// Checks `flush_hart_cache` empties only the given hart's cache and puts exactly its
+/// frames back on the free lists. Needs the frame allocator.
+#[cfg(debug_assertions)]
+pub fn check_flush_hart_cache() {
+    with_buffer_allocator(64, AllocatorConfig::default(), |allocator, _| {
+        let hart_id = current_hart_id();
+        let offline_hart = (hart_id + 1) % MAX_HARTS;
+
+        let frame = allocator
+            .alloc(BASE_SIZE_LAYOUT)
+            .expect("Frame allocation failed");
+        allocator.dealloc(frame, BASE_SIZE_LAYOUT);
+        for _ in 0..2 {
+            let frame = allocator.hart_cache(hart_id).pop().expect("Cache empty");
+            allocator.hart_cache(offline_hart).push(frame);
+        }
+        let kept = allocator.hart_cache(hart_id).len();
+
+        let before = listed_frames(allocator);
+        allocator.flush_hart_cache(offline_hart);
+        assert!(
+            allocator.hart_cache(offline_hart).is_empty(),
+            "Flushed cache kept frames"
+        );
+        assert_eq!(
+            listed_frames(allocator) - before,
+            2,
+            "Flushed frames missing from the free lists"
+        );
+        assert_eq!(
+            allocator.hart_cache(hart_id).len(),
+            kept,
+            "Flush touched another hart's cache"
+        );
+
+        allocator.drain_all_caches();
+    });
+}
+
+/// Checks `try_alloc` rejects requests above `alloc_limit` or free memory with
+/// `AllocError::SizeTooLarge` instead of panicking. Needs the frame allocator.
+#[cfg(debug_assertions)]
+pub fn check_size_too_large() {
+    const LIMIT: usize = 4 * BASE_SIZE;
+
+    let config = AllocatorConfig {
+        alloc_limit: Some(LIMIT),
+        ..AllocatorConfig::default()
+    };
+    with_buffer_allocator(64, config, |allocator, _| {
+        let layout = |size| Layout::from_size_align(size, BASE_SIZE).expect("Invalid layout");
+        assert_eq!(allocator.alloc_size_limit(), LIMIT);
+
+        let block = allocator
+            .try_alloc(layout(LIMIT))
+            .expect("Request at the limit rejected");
+        allocator.dealloc(block, layout(LIMIT));
+
+        assert_eq!(
+            allocator.try_alloc(layout(LIMIT + 1)),
+            Err(AllocError::SizeTooLarge {
+                requested: LIMIT + 1,
+                available: LIMIT
+            })
+        );
+    });
+
+    with_buffer_allocator(64, AllocatorConfig::default(), |allocator, map| {
+        let free_size = map.free_size();
+        assert_eq!(allocator.alloc_size_limit(), free_size);
+
+        let requested = free_size + BASE_SIZE;
+        let layout = Layout::from_size_align(requested, BASE_SIZE).expect("Invalid layout");
+        assert_eq!(
+            allocator.try_alloc(layout),
+            Err(AllocError::SizeTooLarge {
+                requested,
+                available: free_size
+            })
+        );
+    });
+}
+
+/// Checks `zero_free_frames` clears whatever was left in free memory before the first
+/// frame is handed out, and that without it the old contents survive. Needs the frame
+/// allocator.
+#[cfg(debug_assertions)]
+pub fn check_zero_free_frames() {
+    use crate::memory::pmem_map::with_buffer_map;
+
+    const PATTERN: u8 = 0xa5;
+
+    for zero_free_frames in [false, true] {
+        with_buffer_map::<1>(32, |map| {
+            for region in map.free_regions() {
+                // SAFETY: the map's free memory is owned by this check, no allocator runs on it yet
+                unsafe { region.start().as_mut_slice(region.size()) }.fill(PATTERN);
+            }
+
+            let config = AllocatorConfig {
+                zero_free_frames,
+                ..AllocatorConfig::default()
+            };
+            let allocator =
+                unsafe { FrameAllocator::init(map as *const PhysicalMemoryMap, config) };
+            let frame = allocator
+                .alloc(BASE_SIZE_LAYOUT)
+                .expect("Frame allocation failed");
+
+            let expected = if zero_free_frames { 0 } else { PATTERN };
+            // SAFETY: the frame was just handed out and spans `BASE_SIZE` bytes
+            let contents = unsafe { core::slice::from_raw_parts(frame.as_ptr(), BASE_SIZE) };
+            assert!(
+                contents.iter().all(|&byte| byte == expected),
+                "Frame not {} with zero_free_frames {}",
+                if zero_free_frames {
+                    "zeroed"
+                } else {
+                    "left as is"
+                },
+                zero_free_frames
+            );
+        });
+    }
+}
@@ -1,63 +1,142 @@
-use crate::collections::DoublyLinkedList;
-use crate::memory::frame::Frame;
+use crate::collections::{Bitmap, DoublyLinkedList};
+use crate::memory::frame::{BASE_SIZE_LAYOUT, Frame, MAX_ORDER};
+use crate::memory::health::{HealthIssue, HealthReport};
+use crate::memory::{PhysicalAddress, PhysicalMemoryMap};
+use core::fmt;
 use core::ptr::NonNull;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(transparent)]
-struct Bitmap(u64);
+/// words in the free lists bitmap, one covers every order a 64-bit address space allows
+const BITMAP_WORDS: usize = 1;
 
-impl Bitmap {
-    #[inline]
-    pub const fn new() -> Self {
-        Self(0)
-    }
+/// one order per bitmap bit
+const MAX_ORDERS: usize = Bitmap::<BITMAP_WORDS>::BITS;
 
-    /// sets the bit corresponding to the given order
-    #[inline]
-    pub fn set(&mut self, order: u8) {
-        self.0 |= 1 << order;
+const _: () = assert!(
+    MAX_ORDER as usize <= MAX_ORDERS,
+    "Free lists bitmap can't cover every frame order"
+);
+
+/// Point-in-time copy of the per-order free block counts, safe to print without any lock held.
+#[derive(Debug, Clone, Copy)]
+pub struct FreeListsSnapshot {
+    blocks: [usize; MAX_ORDERS],
+    orders: usize,
+}
+
+impl FreeListsSnapshot {
+    pub fn blocks_at_order(&self, order: u8) -> usize {
+        self.blocks[order as usize]
     }
 
-    /// clears the bit corresponding to the given order
-    #[inline]
-    pub fn clear(&mut self, order: u8) {
-        self.0 &= !(1 << order);
+    pub fn orders(&self) -> usize {
+        self.orders
     }
+}
 
-    /// finds the first available order great than or equal to `requested_order`
-    #[inline]
-    pub fn find_first_set_from(&self, requested_order: u8) -> Option<u8> {
-        // create a mask to ignore orders smaller than requested
-        let suitable_mask = !((1 << requested_order) - 1);
-
-        // find >= orders
-        let suitable_blocks = self.0 & suitable_mask;
-
-        if suitable_blocks == 0 {
-            None
-        } else {
-            // return the smallest suitable
-            Some(suitable_blocks.trailing_zeros() as u8)
+impl fmt::Display for FreeListsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "free blocks per order:")?;
+        for (order, count) in self.blocks[..self.orders].iter().enumerate() {
+            if *count > 0 {
+                write!(f, " [{}]={}", order, count)?;
+            }
         }
+        Ok(())
     }
 }
 
 pub struct FreeLists {
     lists: &'static mut [DoublyLinkedList<Frame>],
-    bitmap: Bitmap,
+    // the bit of an order is set iff its list is non-empty
+    bitmap: Bitmap<BITMAP_WORDS>,
 }
 
 impl FreeLists {
     #[inline]
     pub fn new(lists: &'static mut [DoublyLinkedList<Frame>]) -> Self {
+        assert!(
+            lists.len() <= MAX_ORDERS,
+            "Too many orders for the free lists bitmap"
+        );
+
         Self {
             lists,
             bitmap: Bitmap::new(),
         }
     }
 
+    /// number of orders, one list each
+    pub fn orders(&self) -> u8 {
+        self.lists.len() as u8
+    }
+
+    /// bitmap of the non-empty orders `0..64`
     pub fn bitmap_bits(&self) -> u64 {
-        self.bitmap.0
+        self.bitmap.word(0)
+    }
+
+    /// highest order with a free block
+    #[inline]
+    pub fn find_last_free(&self) -> Option<u8> {
+        self.bitmap.find_last_set().map(|order| order as u8)
+    }
+
+    /// copies the per-order block counts
+    pub fn snapshot(&self) -> FreeListsSnapshot {
+        let mut blocks = [0; MAX_ORDERS];
+        for (count, list) in blocks.iter_mut().zip(self.lists.iter()) {
+            *count = list.len();
+        }
+
+        FreeListsSnapshot {
+            blocks,
+            orders: self.lists.len(),
+        }
+    }
+
+    /// number of free blocks currently held at the given order
+    #[inline]
+    pub fn blocks_at_order(&self, order: u8) -> usize {
+        self.lists[order as usize].len()
+    }
+
+    /// keeps the bitmap in lockstep with the list
+    #[inline]
+    fn check_order(&self, order: u8) {
+        debug_assert_eq!(
+            !self.lists[order as usize].is_empty(),
+            self.bitmap.is_set(order as usize),
+            "Free block count diverged from the bitmap at order {}",
+            order
+        );
+    }
+
+    /// records every length, bitmap or order mismatch in `report`, walking all free lists
+    pub fn check(&self, report: &mut HealthReport) {
+        for (order, list) in self.lists.iter().enumerate() {
+            let order = order as u8;
+            // one past the recorded length is enough to tell, and bounds a looping list
+            let listed = list.iter().take(list.len() + 1).count();
+
+            if list.len() != listed {
+                report.push(HealthIssue::FreeListCount {
+                    order,
+                    recorded: list.len(),
+                    listed,
+                });
+            }
+
+            if list.is_empty() == self.bitmap.is_set(order as usize) {
+                report.push(HealthIssue::FreeListBitmap { order });
+            }
+
+            for frame in list.iter().take(listed) {
+                let found = unsafe { frame.as_ref() }.order();
+                if found != order {
+                    report.push(HealthIssue::MisorderedBlock { order, found });
+                }
+            }
+        }
     }
 
     /// pushes a frame onto the front of the correct free list
@@ -65,16 +144,24 @@ impl FreeLists {
     pub fn push_frame(&mut self, frame: NonNull<Frame>) {
         let order = unsafe { frame.as_ref().order() };
         self.lists[order as usize].push_front(frame);
-        self.bitmap.set(order);
+        self.bitmap.set(order as usize);
+        self.check_order(order);
     }
 
     /// pops a frame from the front of the list for a given order
     #[inline]
     pub fn pop_frame(&mut self, order: u8) -> Option<NonNull<Frame>> {
         let frame = self.lists[order as usize].pop_front()?;
+        debug_assert_eq!(
+            unsafe { frame.as_ref().order() },
+            order,
+            "Popped a mis-ordered frame from the order {} free list",
+            order
+        );
         if self.lists[order as usize].is_empty() {
-            self.bitmap.clear(order);
+            self.bitmap.clear(order as usize);
         }
+        self.check_order(order);
         Some(frame)
     }
 
@@ -83,13 +170,175 @@ impl FreeLists {
         let order = unsafe { frame.as_ref().order() };
         self.lists[order as usize].remove(frame);
         if self.lists[order as usize].is_empty() {
-            self.bitmap.clear(order);
+            self.bitmap.clear(order as usize);
         }
+        self.check_order(order);
+    }
+
+    /// finds the free block whose frames span `address`, returning its head frame
+    ///
+    /// O(number of free blocks), meant for boot-time and diagnostic paths
+    pub fn find_block_containing(
+        &self,
+        address: PhysicalAddress,
+        memory_map: &PhysicalMemoryMap,
+    ) -> Option<NonNull<Frame>> {
+        self.lists
+            .iter()
+            .flat_map(|list| list.iter())
+            .find(|frame| {
+                let frame_ref = unsafe { frame.as_ref() };
+                let block_start = memory_map.frame_ref_to_address(frame_ref);
+                block_start <= address && address < block_start + frame_ref.size()
+            })
+    }
+
+    /// merges every free buddy pair, sweeping orders upward so merged blocks get another
+    /// chance one order higher, returns the number of merges
+    ///
+    /// frames parked in hart caches look like free order 0 buddies, they are left alone:
+    /// only frames on the lists are merged
+    pub fn coalesce_all(&mut self, memory_map: &PhysicalMemoryMap) -> usize {
+        let mut merged = 0;
+
+        for order in 0..self.lists.len().saturating_sub(1) as u8 {
+            let mut pending = core::mem::take(&mut self.lists[order as usize]);
+            self.bitmap.clear(order as usize);
+
+            // a buddy is pending iff it carries the mark, no list walk needed
+            for mut frame_ptr in pending.iter() {
+                unsafe { frame_ptr.as_mut() }.set_sweep_mark();
+            }
+
+            while let Some(mut frame_ptr) = pending.pop_front() {
+                let address = memory_map.frame_ref_to_address(unsafe { frame_ptr.as_ref() });
+
+                let buddy = memory_map
+                    .buddy_address(address, order)
+                    .map(|buddy_addr| (buddy_addr, memory_map.address_to_frame_ptr(buddy_addr)))
+                    .filter(|(_, buddy_ptr)| {
+                        let buddy_ref = unsafe { buddy_ptr.as_ref() };
+                        buddy_ref.is_free() && buddy_ref.has_sweep_mark()
+                    });
+
+                unsafe { frame_ptr.as_mut() }.set_order(order, self.orders());
+
+                let Some((buddy_addr, mut buddy_ptr)) = buddy else {
+                    self.push_frame(frame_ptr);
+                    continue;
+                };
+
+                pending.remove(buddy_ptr);
+                unsafe { buddy_ptr.as_mut() }.set_order(order, self.orders());
+
+                // the lower address heads the merged block
+                if buddy_addr < address {
+                    core::mem::swap(&mut frame_ptr, &mut buddy_ptr);
+                }
+                unsafe { frame_ptr.as_mut() }.set_order(order + 1, self.orders());
+                self.push_frame(frame_ptr);
+                merged += 1;
+            }
+            self.check_order(order);
+        }
+
+        merged
     }
 
     /// finds the first available order that is greater than or equal to `requested_order`
     #[inline]
     pub fn find_first_free_from(&self, from_order: u8) -> Option<u8> {
-        self.bitmap.find_first_set_from(from_order)
+        self.bitmap
+            .find_first_set_from(from_order as usize)
+            .map(|order| order as u8)
     }
 }
+
+/// Runs `f` on free lists of `orders` orders, their list heads carved out of a frame taken
+/// from the frame allocator and given back afterwards. Needs the frame allocator.
+fn with_frame_backed_lists(orders: usize, f: impl FnOnce(&mut FreeLists)) {
+    let storage = crate::memory::frame_allocator()
+        .alloc(BASE_SIZE_LAYOUT)
+        .expect("Failed to allocate the free list heads");
+    let heads = storage.as_ptr().cast::<DoublyLinkedList<Frame>>();
+    assert!(orders * size_of::<DoublyLinkedList<Frame>>() <= BASE_SIZE_LAYOUT.size());
+
+    // SAFETY: the frame is owned by this function and fits `orders` list heads
+    let lists = unsafe {
+        (0..orders).for_each(|order| heads.add(order).write(DoublyLinkedList::new()));
+        core::slice::from_raw_parts_mut(heads, orders)
+    };
+    f(&mut FreeLists::new(lists));
+
+    crate::memory::frame_allocator().dealloc(storage, BASE_SIZE_LAYOUT);
+}
+
+/// Checks the per-order block counts, the bitmap and the snapshot follow a sequence of
+/// pushes, pops and removals. Needs the frame allocator.
+pub fn check_counts() {
+    const ORDERS: u8 = 3;
+
+    with_frame_backed_lists(ORDERS as usize, |lists| {
+        let mut frames = [const { Frame::new() }; 3];
+        for (frame, order) in frames.iter_mut().zip([0, 0, 2]) {
+            frame.set_order(order, ORDERS);
+        }
+        let [a, b, c] = frames.each_mut().map(NonNull::from);
+
+        let counts = |lists: &FreeLists| [0, 1, 2].map(|order| lists.blocks_at_order(order));
+
+        lists.push_frame(a);
+        lists.push_frame(b);
+        lists.push_frame(c);
+        assert_eq!(counts(lists), [2, 0, 1], "Counts wrong after pushes");
+        assert_eq!(lists.bitmap_bits(), 0b101);
+
+        assert_eq!(lists.pop_frame(0), Some(b), "Pop is not LIFO");
+        lists.remove_frame(c);
+        assert_eq!(
+            counts(lists),
+            [1, 0, 0],
+            "Counts wrong after pop and remove"
+        );
+        assert_eq!(lists.bitmap_bits(), 0b001);
+        assert_eq!(lists.find_last_free(), Some(0));
+
+        assert_eq!(lists.pop_frame(2), None, "Popped from an empty order");
+        assert_eq!(lists.pop_frame(0), Some(a));
+
+        let snapshot = lists.snapshot();
+        assert!((0..ORDERS).all(|order| snapshot.blocks_at_order(order) == 0));
+        assert_eq!(lists.bitmap_bits(), 0);
+
+        let mut report = HealthReport::new();
+        lists.check(&mut report);
+        assert!(report.is_healthy(), "{}", report);
+    });
+}
+
+/// Checks `check` reports a block whose order changed while it sat on a free list, the
+/// corruption `pop_frame` asserts against. Needs the frame allocator.
+pub fn check_misordered_block() {
+    const ORDERS: u8 = 3;
+
+    with_frame_backed_lists(ORDERS as usize, |lists| {
+        let mut frame = Frame::new();
+        frame.set_order(1, ORDERS);
+        let mut frame_ptr = NonNull::from(&mut frame);
+        lists.push_frame(frame_ptr);
+
+        unsafe { frame_ptr.as_mut() }.set_order(2, ORDERS);
+        let mut report = HealthReport::new();
+        lists.check(&mut report);
+        assert!(
+            report
+                .issues()
+                .eq([&HealthIssue::MisorderedBlock { order: 1, found: 2 }]),
+            "Mis-ordered block missed: {}",
+            report
+        );
+
+        unsafe { frame_ptr.as_mut() }.set_order(1, ORDERS);
+        assert_eq!(lists.pop_frame(1), Some(frame_ptr));
+    });
+}
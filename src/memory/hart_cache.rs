@@ -1,8 +1,13 @@
 use crate::collections::{SinglyLinkable, SinglyLinkedList};
+use crate::sync::OnceLock;
+use core::cell::UnsafeCell;
 use core::ptr::NonNull;
 
 pub const MAX_HARTS: usize = 12; // TODO: make dynamic
 
+/// Number of back-to-back refills (or drains) after which the cache target is resized.
+const ADAPT_THRESHOLD: u32 = 4;
+
 /// A per-hart (per-CPU) cache of free memory frames.
 ///
 /// # Cache Line Alignment
@@ -24,6 +29,12 @@ pub struct HartCache<T: SinglyLinkable, S: CacheStrategy> {
     items: SinglyLinkedList<T>,
     strategy: S,
     target_size: usize,
+
+    // adaptive resizing
+    min_target: usize,
+    max_target: usize,
+    consecutive_refills: u32,
+    consecutive_drains: u32,
 }
 
 impl<T: SinglyLinkable, S: CacheStrategy> HartCache<T, S> {
@@ -32,9 +43,25 @@ impl<T: SinglyLinkable, S: CacheStrategy> HartCache<T, S> {
             items: SinglyLinkedList::new(),
             strategy,
             target_size,
+            min_target: 1,
+            max_target: usize::MAX,
+            consecutive_refills: 0,
+            consecutive_drains: 0,
         }
     }
 
+    /// Clamps adaptive `grow`/`shrink` to `[min_target, max_target]`.
+    pub fn with_target_bounds(mut self, min_target: usize, max_target: usize) -> Self {
+        assert!(
+            0 < min_target && min_target <= max_target,
+            "Invalid cache target bounds"
+        );
+        self.min_target = min_target;
+        self.max_target = max_target;
+        self.target_size = self.target_size.clamp(min_target, max_target);
+        self
+    }
+
     #[inline]
     pub fn len(&self) -> usize {
         self.items.len()
@@ -55,11 +82,60 @@ impl<T: SinglyLinkable, S: CacheStrategy> HartCache<T, S> {
         self.target_size
     }
 
+    /// Pushes an item without checking capacity, this is the allocator fast path.
+    ///
+    /// Callers are expected to check `is_full()` (and drain) beforehand,
+    /// or use `try_push` instead.
+    ///
+    /// # Panics
+    ///
+    /// Panics in debug builds if the cache already holds twice its `target_size`.
     #[inline]
     pub fn push(&mut self, item: NonNull<T>) {
+        debug_assert!(
+            self.len() < self.hard_cap(),
+            "HartCache overflow: {} items with target size {}",
+            self.len(),
+            self.target_size
+        );
         self.items.push_front(item);
     }
 
+    /// Pushes an already linked run of `count` items at once, see `SinglyLinkedList::prepend_chain`.
+    #[inline]
+    pub fn push_chain(&mut self, head: NonNull<T>, tail: NonNull<T>, count: usize) {
+        debug_assert!(
+            self.len() + count <= self.hard_cap(),
+            "HartCache overflow: {} + {} items with target size {}",
+            self.len(),
+            count,
+            self.target_size
+        );
+        self.items.prepend_chain(head, tail, count);
+    }
+
+    /// Pushes an item only if the cache is below its `target_size`,
+    /// otherwise hands the item back.
+    #[inline]
+    pub fn try_push(&mut self, item: NonNull<T>) -> Result<(), NonNull<T>> {
+        if self.len() >= self.target_size {
+            return Err(item);
+        }
+        self.items.push_front(item);
+        Ok(())
+    }
+
+    /// Upper bound no cache should ever reach, regardless of strategy.
+    #[inline]
+    fn hard_cap(&self) -> usize {
+        (2 * self.target_size).max(1)
+    }
+
+    /// Returns `true` if `item` is currently parked in the cache. O(n), for debug checks.
+    pub fn contains(&self, item: NonNull<T>) -> bool {
+        self.items.contains(item)
+    }
+
     #[inline]
     pub fn pop(&mut self) -> Option<NonNull<T>> {
         self.items.pop_front()
@@ -69,6 +145,17 @@ impl<T: SinglyLinkable, S: CacheStrategy> HartCache<T, S> {
         self.items.drain(self.drain_amount())
     }
 
+    /// Empties the cache regardless of strategy, e.g. when its hart goes offline.
+    pub fn drain_all(&mut self) -> impl Iterator<Item = NonNull<T>> {
+        self.items.drain(self.len())
+    }
+
+    /// Minimum number of items an idle cache should be kept at.
+    #[inline]
+    pub fn low_watermark(&self) -> usize {
+        self.strategy.low_watermark(self.target_size)
+    }
+
     #[inline]
     pub fn refill_amount(&self) -> usize {
         self.strategy.refill_amount(self.target_size(), self.len())
@@ -81,12 +168,96 @@ impl<T: SinglyLinkable, S: CacheStrategy> HartCache<T, S> {
 
     #[inline]
     pub fn grow(&mut self) {
-        self.target_size = self.strategy.increase_target(self.target_size)
+        self.target_size = self
+            .strategy
+            .increase_target(self.target_size)
+            .clamp(self.min_target, self.max_target)
     }
 
     #[inline]
     pub fn shrink(&mut self) {
-        self.target_size = self.strategy.decrease_target(self.target_size)
+        self.target_size = self
+            .strategy
+            .decrease_target(self.target_size)
+            .clamp(self.min_target, self.max_target)
+    }
+
+    /// Records a refill from the global pool. A cache that keeps running dry grows.
+    #[inline]
+    pub fn record_refill(&mut self) {
+        self.consecutive_drains = 0;
+        self.consecutive_refills += 1;
+
+        if self.consecutive_refills >= ADAPT_THRESHOLD {
+            self.consecutive_refills = 0;
+            self.grow();
+        }
+    }
+
+    /// Records a drain to the global pool. A cache that keeps overflowing shrinks.
+    #[inline]
+    pub fn record_drain(&mut self) {
+        self.consecutive_refills = 0;
+        self.consecutive_drains += 1;
+
+        if self.consecutive_drains >= ADAPT_THRESHOLD {
+            self.consecutive_drains = 0;
+            self.shrink();
+        }
+    }
+}
+
+/// One `HartCache` per hart, each created lazily by the first access for that hart.
+///
+/// A hart that comes online late sets up its own cache on its first allocation,
+/// nothing has to be prepared for it by the boot hart.
+pub struct HartCaches<T: SinglyLinkable, S: CacheStrategy> {
+    caches: [OnceLock<UnsafeCell<HartCache<T, S>>>; MAX_HARTS],
+    target_size: usize,
+    min_target: usize,
+    max_target: usize,
+}
+
+impl<T: SinglyLinkable, S: CacheStrategy + Default> HartCaches<T, S> {
+    pub const fn new(target_size: usize) -> Self {
+        Self {
+            caches: [const { OnceLock::new() }; MAX_HARTS],
+            target_size,
+            min_target: 1,
+            max_target: usize::MAX,
+        }
+    }
+
+    /// Bounds every cache created from now on, see `HartCache::with_target_bounds`.
+    pub fn with_target_bounds(mut self, min_target: usize, max_target: usize) -> Self {
+        self.min_target = min_target;
+        self.max_target = max_target;
+        self
+    }
+
+    /// The cache of `hart_id`, created on first access.
+    ///
+    /// Only the owning hart may use the returned cache, unless every other hart is parked.
+    #[inline]
+    #[allow(clippy::mut_from_ref)]
+    pub fn get(&self, hart_id: usize) -> &mut HartCache<T, S> {
+        let cell = self.caches[hart_id].get_or_init(|| {
+            UnsafeCell::new(
+                HartCache::new(self.target_size, S::default())
+                    .with_target_bounds(self.min_target, self.max_target),
+            )
+        });
+        unsafe { &mut *cell.get() }
+    }
+
+    /// The cache of `hart_id` if that hart ever used it, for walks over all harts
+    /// that shouldn't create caches as a side effect.
+    #[inline]
+    #[allow(clippy::mut_from_ref)]
+    pub fn get_initialized(&self, hart_id: usize) -> Option<&mut HartCache<T, S>> {
+        self.caches[hart_id]
+            .get()
+            .map(|cell| unsafe { &mut *cell.get() })
     }
 }
 
@@ -100,8 +271,11 @@ pub trait CacheStrategy {
     fn increase_target(&self, target_size: usize) -> usize;
 
     fn high_watermark(&self, target_size: usize) -> usize;
+
+    fn low_watermark(&self, target_size: usize) -> usize;
 }
 
+#[derive(Default)]
 pub struct Quartering;
 
 const QUARTERING_DENOMINATOR: usize = 4;
@@ -131,8 +305,14 @@ impl CacheStrategy for Quartering {
     fn high_watermark(&self, target_size: usize) -> usize {
         target_size
     }
+
+    #[inline]
+    fn low_watermark(&self, target_size: usize) -> usize {
+        target_size / QUARTERING_DENOMINATOR
+    }
 }
 
+#[derive(Default)]
 pub struct Greedy;
 
 impl CacheStrategy for Greedy {
@@ -160,4 +340,106 @@ impl CacheStrategy for Greedy {
     fn high_watermark(&self, target_size: usize) -> usize {
         target_size * 2
     }
+
+    #[inline]
+    fn low_watermark(&self, target_size: usize) -> usize {
+        target_size / 2
+    }
+}
+
+/// Checks a hart's cache only comes into existence on that hart's first access, with the
+/// configured target and bounds, and that later accesses return the same cache.
+#[cfg(debug_assertions)]
+pub fn check_lazy_init() {
+    use crate::collections::test_support::TestNode;
+
+    const LATE_HART: usize = MAX_HARTS - 1;
+
+    let caches: HartCaches<TestNode, Greedy> = HartCaches::new(64).with_target_bounds(2, 16);
+    assert!(
+        (0..MAX_HARTS).all(|hart_id| caches.get_initialized(hart_id).is_none()),
+        "Cache created before first use"
+    );
+
+    let mut node = TestNode::new(0);
+    let cache = caches.get(LATE_HART);
+    assert_eq!(cache.target_size(), 16, "Lazy cache ignored its bounds");
+    cache.push(NonNull::from(&mut node));
+
+    let again = caches
+        .get_initialized(LATE_HART)
+        .expect("First use did not create the cache");
+    assert_eq!(again.len(), 1, "Second access created a fresh cache");
+    assert!(
+        (0..LATE_HART).all(|hart_id| caches.get_initialized(hart_id).is_none()),
+        "First use created another hart's cache"
+    );
+    assert!(again.pop().is_some());
+}
+
+/// Checks `try_push` accepts items below `target_size` and hands back the one at it.
+#[cfg(debug_assertions)]
+pub fn check_try_push() {
+    use crate::collections::test_support::TestNode;
+
+    let mut nodes = [0, 1, 2].map(TestNode::new);
+    let [first, second, third] = nodes.each_mut().map(NonNull::from);
+
+    let mut cache: HartCache<TestNode, Greedy> = HartCache::new(2, Greedy);
+    assert_eq!(cache.try_push(first), Ok(()));
+    assert_eq!(cache.try_push(second), Ok(()));
+    assert_eq!(
+        cache.try_push(third),
+        Err(third),
+        "Pushed past the target size"
+    );
+    assert_eq!(cache.len(), 2);
+    assert!(!cache.contains(third));
+
+    // room again once an item leaves
+    assert_eq!(cache.pop(), Some(second));
+    assert_eq!(cache.try_push(third), Ok(()));
+    assert!(cache.contains(third) && cache.len() == 2);
+}
+
+/// Checks `ADAPT_THRESHOLD` back-to-back refills grow the target and drains shrink it,
+/// an interleaved event resets the streak, and both stay within the target bounds.
+#[cfg(debug_assertions)]
+pub fn check_adaptive_target() {
+    use crate::collections::test_support::TestNode;
+
+    let mut cache: HartCache<TestNode, Quartering> =
+        HartCache::new(16, Quartering).with_target_bounds(2, 64);
+
+    // a drain in between breaks the refill streak
+    for _ in 1..ADAPT_THRESHOLD {
+        cache.record_refill();
+    }
+    cache.record_drain();
+    cache.record_refill();
+    assert_eq!(
+        cache.target_size(),
+        16,
+        "Interleaved refills grew the cache"
+    );
+
+    // the streak restarted at the refill above
+    for _ in 2..ADAPT_THRESHOLD {
+        cache.record_refill();
+    }
+    assert_eq!(cache.target_size(), 16);
+    cache.record_refill();
+    assert_eq!(cache.target_size(), 64);
+    for _ in 0..ADAPT_THRESHOLD {
+        cache.record_refill();
+    }
+    assert_eq!(cache.target_size(), 64, "Grew past the upper bound");
+
+    // 4 / 4 is clamped to the lower bound
+    for expected in [16, 4, 2, 2] {
+        for _ in 0..ADAPT_THRESHOLD {
+            cache.record_drain();
+        }
+        assert_eq!(cache.target_size(), expected);
+    }
 }
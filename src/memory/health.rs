@@ -0,0 +1,169 @@
+use crate::memory::PhysicalAddress;
+use core::fmt;
+
+/// Maximum number of issues a `HealthReport` keeps, later ones are only counted.
+pub const MAX_HEALTH_ISSUES: usize = 16;
+
+/// A single broken allocator invariant found by `memory::health_check`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthIssue {
+    /// A structure could not be inspected because its lock is held.
+    LockHeld(&'static str),
+    /// A free list's recorded length disagrees with the blocks linked into it.
+    FreeListCount {
+        order: u8,
+        recorded: usize,
+        listed: usize,
+    },
+    /// The bitmap bit disagrees with whether the free list is empty.
+    FreeListBitmap { order: u8 },
+    /// A free list holds a block of a different order.
+    MisorderedBlock { order: u8, found: u8 },
+    /// A hart cache still holds frames while the allocator was expected to be idle.
+    HartCacheNotEmpty { hart_id: usize },
+    /// Free blocks were not merged back into the blocks `init` started with.
+    NotCoalesced {
+        order: u8,
+        expected: usize,
+        found: usize,
+    },
+    /// A listed slab's in-use count doesn't match the list it is on or its free chain.
+    SlabInUseCount {
+        object_size: usize,
+        slab: PhysicalAddress,
+        in_use: usize,
+        free_slots: usize,
+        slots_per_slab: usize,
+    },
+    /// A slab's free chain links a slot outside the slab or off the slot grid.
+    StraySlot {
+        object_size: usize,
+        slab: PhysicalAddress,
+        slot: usize,
+    },
+}
+
+impl fmt::Display for HealthIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            HealthIssue::LockHeld(what) => write!(f, "{}: lock held, not checked", what),
+            HealthIssue::FreeListCount {
+                order,
+                recorded,
+                listed,
+            } => write!(
+                f,
+                "Free list length diverged from its blocks at order {} ({} recorded, {} listed)",
+                order, recorded, listed
+            ),
+            HealthIssue::FreeListBitmap { order } => write!(
+                f,
+                "Free block count diverged from the bitmap at order {}",
+                order
+            ),
+            HealthIssue::MisorderedBlock { order, found } => write!(
+                f,
+                "Order {} free list holds an order {} block",
+                order, found
+            ),
+            HealthIssue::HartCacheNotEmpty { hart_id } => {
+                write!(f, "Hart {} cache still holds frames", hart_id)
+            }
+            HealthIssue::NotCoalesced {
+                order,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Free lists are not fully coalesced at order {} ({} blocks, expected {})",
+                order, found, expected
+            ),
+            HealthIssue::SlabInUseCount {
+                object_size,
+                slab,
+                in_use,
+                free_slots,
+                slots_per_slab,
+            } => write!(
+                f,
+                "{}-byte slab at {} in-use count is wrong ({} in use, {} free, {} slots)",
+                object_size, slab, in_use, free_slots, slots_per_slab
+            ),
+            HealthIssue::StraySlot {
+                object_size,
+                slab,
+                slot,
+            } => write!(
+                f,
+                "{}-byte slab at {} links a stray free slot {:#x}",
+                object_size, slab, slot
+            ),
+        }
+    }
+}
+
+/// Every invariant violation found by a health check, instead of panicking on the first.
+#[derive(Debug, Clone, Copy)]
+pub struct HealthReport {
+    issues: [Option<HealthIssue>; MAX_HEALTH_ISSUES],
+    len: usize,
+    // issues found after the report filled up
+    dropped: usize,
+}
+
+impl HealthReport {
+    pub const fn new() -> Self {
+        Self {
+            issues: [None; MAX_HEALTH_ISSUES],
+            len: 0,
+            dropped: 0,
+        }
+    }
+
+    pub fn push(&mut self, issue: HealthIssue) {
+        if self.len == MAX_HEALTH_ISSUES {
+            self.dropped += 1;
+            return;
+        }
+
+        self.issues[self.len] = Some(issue);
+        self.len += 1;
+    }
+
+    /// `true` if no issue was found.
+    pub fn is_healthy(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of issues found, including the ones that did not fit into the report.
+    pub fn issue_count(&self) -> usize {
+        self.len + self.dropped
+    }
+
+    pub fn issues(&self) -> impl Iterator<Item = &HealthIssue> {
+        self.issues[..self.len].iter().flatten()
+    }
+}
+
+impl Default for HealthReport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for HealthReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_healthy() {
+            return write!(f, "memory: healthy");
+        }
+
+        write!(f, "memory: {} issue(s)", self.issue_count())?;
+        for issue in self.issues() {
+            write!(f, "\n  - {}", issue)?;
+        }
+        if self.dropped > 0 {
+            write!(f, "\n  ... and {} more", self.dropped)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+//! Boot-time test of free RAM, selected with `memtest=` on the kernel command line.
+//!
+//! Runs once the frame allocator is built and before anything is allocated from it.
+//! Frames that don't hold a pattern are taken out of the free lists for good.
+
+use crate::memory::frame::{BASE_SIZE, State};
+use crate::memory::{FrameAllocator, PhysicalAddress, PhysicalMemoryMap};
+use core::fmt;
+
+/// `Quick` tests one frame in this many.
+pub const QUICK_STRIDE: usize = 64;
+
+const PATTERNS: [u64; 2] = [0x5555_5555_5555_5555, 0xaaaa_aaaa_aaaa_aaaa];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MemtestMode {
+    #[default]
+    Off,
+    /// Every `QUICK_STRIDE`-th free frame.
+    Quick,
+    /// Every free frame.
+    Full,
+}
+
+impl MemtestMode {
+    /// Parses the value of `memtest=`.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "off" => Some(Self::Off),
+            "quick" => Some(Self::Quick),
+            "full" => Some(Self::Full),
+            _ => None,
+        }
+    }
+
+    /// Frames skipped between two tested ones, `None` when off.
+    fn stride(self) -> Option<usize> {
+        match self {
+            Self::Off => None,
+            Self::Quick => Some(QUICK_STRIDE),
+            Self::Full => Some(1),
+        }
+    }
+}
+
+impl fmt::Display for MemtestMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Off => "off",
+            Self::Quick => "quick",
+            Self::Full => "full",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemtestReport {
+    pub tested: usize,
+    /// Frames that failed and are now permanently allocated.
+    pub bad: usize,
+}
+
+/// Writes each pattern and then every word's own address over the frame at `frame`,
+/// reading each back. Leaves the frame zeroed.
+///
+/// The frame must be free and owned by the caller.
+pub fn test_frame(frame: PhysicalAddress) -> bool {
+    let words = frame.as_mut_ptr::<u64>();
+    let len = BASE_SIZE / size_of::<u64>();
+
+    // volatile: the compiler must not fold the read back into the write
+    let holds = |value: &dyn Fn(usize) -> u64| {
+        (0..len).for_each(|i| unsafe { words.add(i).write_volatile(value(i)) });
+        (0..len).all(|i| unsafe { words.add(i).read_volatile() } == value(i))
+    };
+
+    let intact = PATTERNS.iter().all(|&pattern| holds(&|_| pattern))
+        && holds(&|i| unsafe { words.add(i) } as u64);
+
+    (0..len).for_each(|i| unsafe { words.add(i).write_volatile(0) });
+    intact
+}
+
+/// Runs `test` on the free frames of `map` picked by `mode`, reserving the ones it fails.
+///
+/// `allocator` must be built over `map` with nothing allocated from it yet, so every free
+/// frame can be overwritten.
+pub fn run(
+    allocator: &FrameAllocator,
+    map: &PhysicalMemoryMap,
+    mode: MemtestMode,
+    mut test: impl FnMut(PhysicalAddress) -> bool,
+) -> MemtestReport {
+    let mut report = MemtestReport::default();
+    let Some(stride) = mode.stride() else {
+        return report;
+    };
+
+    for region in map.free_regions() {
+        for frame in (0..region.size() / BASE_SIZE)
+            .step_by(stride)
+            .map(|idx| region.start() + idx * BASE_SIZE)
+        {
+            // reserved regions and frames already taken out by an earlier run
+            let state = unsafe { map.address_to_frame_ptr(frame).as_ref() }.state();
+            if !matches!(state, State::Free) {
+                continue;
+            }
+
+            report.tested += 1;
+            if !test(frame) {
+                allocator
+                    .reserve_range(frame, BASE_SIZE)
+                    .expect("Bad frame is not in the free lists");
+                report.bad += 1;
+            }
+        }
+    }
+
+    report
+}
+
+/// Checks `memtest=` parsing, and that each mode tests the expected frames and takes an
+/// injected bad frame out of a buffer-backed allocator. Needs the frame allocator.
+#[cfg(debug_assertions)]
+pub fn check_memtest() {
+    use crate::memory::AllocatorConfig;
+    use crate::memory::frame_allocator::with_buffer_allocator;
+
+    const BUFFER_FRAMES: usize = 32;
+
+    let parse = |bootargs| AllocatorConfig::from_bootargs(Some(bootargs)).memtest;
+    assert_eq!(parse("memtest=full"), MemtestMode::Full);
+    assert_eq!(parse("console=ttyS0 memtest=quick"), MemtestMode::Quick);
+    assert_eq!(parse("memtest=off"), MemtestMode::Off);
+    assert_eq!(parse("memtest=sometimes"), MemtestMode::Off);
+    assert_eq!(AllocatorConfig::default().memtest, MemtestMode::Off);
+
+    with_buffer_allocator(
+        BUFFER_FRAMES,
+        AllocatorConfig::default(),
+        |allocator, map| {
+            let free_frames = map.free_memory.size() / BASE_SIZE;
+            let bad = map.free_memory.start();
+
+            assert_eq!(
+                run(allocator, map, MemtestMode::Off, |_| false),
+                MemtestReport::default(),
+                "memtest=off tested frames"
+            );
+
+            let report = run(allocator, map, MemtestMode::Quick, |frame| {
+                frame != bad && test_frame(frame)
+            });
+            assert_eq!(
+                report,
+                MemtestReport {
+                    tested: free_frames.div_ceil(QUICK_STRIDE),
+                    bad: 1
+                }
+            );
+            assert_eq!(
+                allocator.reserve_range(bad, BASE_SIZE),
+                Err(bad),
+                "Bad frame is still free"
+            );
+
+            // the bad frame is skipped, every other frame of the buffer is sound
+            let report = run(allocator, map, MemtestMode::Full, test_frame);
+            assert_eq!(
+                report,
+                MemtestReport {
+                    tested: free_frames - 1,
+                    bad: 0
+                }
+            );
+        },
+    );
+}
@@ -1,19 +1,48 @@
 pub mod address;
+pub mod dma;
 pub mod frame;
 pub mod frame_allocator;
 pub mod free_lists;
 pub mod hart_cache;
+pub mod health;
+pub mod memtest;
 pub mod pmem_map;
 pub mod slub;
+pub mod trace;
+pub mod watch;
 
 pub use address::PhysicalAddress;
-pub use frame_allocator::FrameAllocator;
+pub use dma::{
+    DmaBuffer, DmaDirection, dma_alloc, dma_alloc_coherent, dma_alloc_streaming, dma_free,
+    dma_free_streaming, dma_sync_for_cpu, dma_sync_for_device, reserve_dma_pool,
+};
+pub use frame_allocator::{AllocError, AllocatorConfig, FrameAllocator};
 pub use hart_cache::HartCache;
-pub use pmem_map::PhysicalMemoryMap;
-pub use slub::{KernelAllocator, SlubAllocator};
+pub use health::{HealthIssue, HealthReport};
+pub use memtest::MemtestMode;
+pub use pmem_map::{MemoryRegion, PhysicalMemoryMap, RamRegions, RegionKind, ReservedRegions};
+pub use slub::{KernelAllocator, ReclaimCursor, SlubAllocator};
+pub use watch::Watch;
 
+use crate::cpu::{CACHE_LINE_SIZE, current_hart_id};
+use crate::devices;
+use crate::drivers;
+use crate::memory::frame::{BASE_SIZE, BASE_SIZE_LAYOUT};
+use crate::memory::hart_cache::MAX_HARTS;
 use crate::sync::OnceLock;
-use fdt::standard_nodes::Memory;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::NonNull;
+use fdt::Fdt;
+
+// Allocator lock hierarchy, debug builds check it (see `OrderedSpinlock`).
+// A hart only ever blocks on a higher level than anything it holds:
+// a slab's `SlabInfo` -> the slab lists -> the buddy free lists.
+// E.g. `return_to_slab` releases a slab to the free lists while still holding a `SlabInfo`,
+// so nothing may take a `SlabInfo` with a slab list or the free lists held.
+pub const SLAB_INFO_LOCK_LEVEL: u32 = 0;
+pub const PARTIAL_SLABS_LOCK_LEVEL: u32 = 1;
+pub const EMPTY_SLABS_LOCK_LEVEL: u32 = 2;
+pub const FREE_LISTS_LOCK_LEVEL: u32 = 3;
 
 // SAFETY: PhysicalMemoryMap is immutable
 pub static PMEM_MAP: OnceLock<PhysicalMemoryMap> = OnceLock::new();
@@ -33,40 +62,605 @@ pub fn frame_allocator() -> &'static FrameAllocator {
 #[global_allocator]
 static KERNEL_ALLOCATOR: KernelAllocator = KernelAllocator::new();
 
-// FIXME:
-// #[alloc_error_handler]
-// fn alloc_error_handler(layout: core::alloc::Layout) -> ! {
-//     panic!("Kernel allocation error: {:?}", layout);
-// }
+/// Allocates `size` bytes aligned to `CACHE_LINE_SIZE`, e.g. for per-hart or lock structures
+/// that must not share a cache line with anything else.
+///
+/// Must be freed with `dealloc_cache_aligned` using the same `size`. Returns `None` for a
+/// zero `size`, which `GlobalAlloc::alloc` does not allow.
+pub fn alloc_cache_aligned(size: usize) -> Option<NonNull<u8>> {
+    if size == 0 {
+        return None;
+    }
+    let layout = Layout::from_size_align(size, CACHE_LINE_SIZE).ok()?;
+    let ptr = NonNull::new(unsafe { KERNEL_ALLOCATOR.alloc(layout) })?;
+
+    debug_assert!(
+        PhysicalAddress::from(ptr.as_ptr() as usize).is_aligned_to(CACHE_LINE_SIZE),
+        "Allocation is not cache line aligned"
+    );
+
+    Some(ptr)
+}
+
+/// Frees memory obtained from `alloc_cache_aligned`.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by `alloc_cache_aligned(size)` and not freed since.
+pub unsafe fn dealloc_cache_aligned(ptr: NonNull<u8>, size: usize) {
+    let layout =
+        Layout::from_size_align(size, CACHE_LINE_SIZE).expect("Invalid cache aligned layout");
+    unsafe { KERNEL_ALLOCATOR.dealloc(ptr.as_ptr(), layout) };
+}
+
+/// Fills the `BASE_SIZE` frame at `frame_ptr` with zeros, e.g. for a fresh page table.
+///
+/// # Safety
+///
+/// `frame_ptr` must be frame aligned and point to a whole frame the caller owns.
+pub unsafe fn zero_frame(frame_ptr: NonNull<u8>) {
+    debug_assert!(
+        PhysicalAddress::from(frame_ptr.as_ptr() as usize).is_aligned_to(BASE_SIZE),
+        "Zeroing a frame at an unaligned address"
+    );
+
+    unsafe { frame_ptr.write_bytes(0, BASE_SIZE) };
+}
+
+/// Returns everything parked in per-hart caches to the global free lists, so the frame
+/// allocator describes all free memory before suspend or kexec.
+///
+/// Other harts must be parked, the caches are drained without their owners' cooperation.
+pub fn prepare_suspend() {
+    // slabs emptied by the slub drain land in a frame cache, so it goes first
+    KERNEL_ALLOCATOR.drain_all_caches();
+    frame_allocator().drain_all_caches();
+}
+
+/// Returns empty slabs past each size class' high watermark to the frame allocator, see
+/// `SizeClassManager::reclaim_empty_slabs`. `now` is the current `mtime`.
+pub fn reclaim_empty_slabs(now: u64) -> usize {
+    KERNEL_ALLOCATOR.reclaim_empty_slabs(now)
+}
+
+/// Size classes visited by one `maintenance_tick`.
+pub const MAINTENANCE_CLASSES_PER_TICK: usize = 2;
+/// Empty slabs released by one `maintenance_tick` at most.
+pub const MAINTENANCE_SLABS_PER_TICK: usize = 4;
+
+static MAINTENANCE_CURSOR: ReclaimCursor = ReclaimCursor::new();
+
+/// A bounded slice of periodic allocator upkeep, meant as a timer wheel callback, e.g.
+/// `timer::schedule_periodic(us_to_ticks(JIFFY_US), memory::maintenance_tick)`.
+///
+/// Trims the current hart's frame cache back to its target and reclaims idle slabs from
+/// the next few size classes, so repeated ticks get around every class without any one
+/// of them stalling. Takes allocator locks: nothing may hold them with interrupts enabled.
+pub fn maintenance_tick() {
+    run_maintenance(devices::clint().mtime());
+}
+
+/// `maintenance_tick` at `now`, returning the number of slabs released.
+pub fn run_maintenance(now: u64) -> usize {
+    if let Some(frame_allocator) = FRAME_ALLOCATOR.get() {
+        frame_allocator.touch_hart_cache(current_hart_id());
+    }
+
+    KERNEL_ALLOCATOR.slub().map_or(0, |slub| {
+        slub.reclaim_step(
+            &MAINTENANCE_CURSOR,
+            now,
+            MAINTENANCE_CLASSES_PER_TICK,
+            MAINTENANCE_SLABS_PER_TICK,
+        )
+    })
+}
+
+/// Returns everything `hart_id` has parked in per-hart caches to the shared lists,
+/// so its frames and slots aren't stranded while it is offline.
+///
+/// The hart must already be parked.
+pub fn flush_hart(hart_id: usize) {
+    // slabs emptied by the slub flush land in a frame cache of the current hart
+    KERNEL_ALLOCATOR.flush_hart_cache(hart_id);
+    frame_allocator().flush_hart_cache(hart_id);
+}
+
+/// Prints whatever allocator state can be read without blocking, for crash reports.
+///
+/// Structures whose lock is held (possibly by the panicking hart itself) are skipped.
+pub fn dump_state(mut print: impl FnMut(core::fmt::Arguments)) {
+    let Some(frame_allocator) = FRAME_ALLOCATOR.get() else {
+        print(format_args!("memory: not initialized\n"));
+        return;
+    };
+
+    match frame_allocator.try_snapshot() {
+        Some(snapshot) => print(format_args!("{}\n", snapshot)),
+        None => print(format_args!("free lists: lock held, skipping\n")),
+    }
+
+    let Some(slub) = KERNEL_ALLOCATOR.slub() else {
+        return;
+    };
+
+    for class in slub.classes() {
+        match class.try_snapshot() {
+            Some(snapshot) => print(format_args!("{}\n", snapshot)),
+            None => print(format_args!(
+                "{:>5}-byte class: lock held, skipping\n",
+                class.object_size()
+            )),
+        }
+    }
+}
+
+/// Runs every allocator consistency check and collects the failures instead of panicking.
+///
+/// `idle` also checks the free lists are fully coalesced, which only holds with nothing
+/// allocated and every cache drained (see `prepare_suspend`).
+pub fn health_check(idle: bool) -> HealthReport {
+    let mut report = HealthReport::new();
+
+    let Some(frame_allocator) = FRAME_ALLOCATOR.get() else {
+        return report;
+    };
+
+    // slabs still come and go from the hart caches, the frame side holds still
+    frame_allocator.with_frozen(|| {
+        frame_allocator.check_health(idle, &mut report);
+
+        if let Some(slub) = KERNEL_ALLOCATOR.slub() {
+            for class in slub.classes() {
+                class.check_consistency(&mut report);
+            }
+        }
+    });
+
+    report
+}
+
+/// Checks that freeing a slot with a different layout of the same size class hands it
+/// back to the class it came from. No-op without the slub.
+pub fn check_dealloc_routing() {
+    if KERNEL_ALLOCATOR.slub().is_none() {
+        return;
+    }
+
+    // a full hart cache would send the freed slot straight back to its slab
+    KERNEL_ALLOCATOR.flush_hart_cache(crate::cpu::current_hart_id());
+
+    let layout = Layout::from_size_align(40, 8).expect("Invalid test layout");
+    let ptr = unsafe { KERNEL_ALLOCATOR.alloc(layout) };
+    assert!(!ptr.is_null(), "40-byte allocation failed");
+
+    // also lands in the 64-byte class, as a `Box<dyn>` drop with a looser layout would
+    let same_class = Layout::from_size_align(33, 1).expect("Invalid test layout");
+    unsafe { KERNEL_ALLOCATOR.dealloc(ptr, same_class) };
+
+    // the hart cache hands the most recently freed slot out first
+    let again = unsafe { KERNEL_ALLOCATOR.alloc(layout) };
+    assert_eq!(
+        again, ptr,
+        "Slot freed with a same-class layout went astray"
+    );
+    unsafe { KERNEL_ALLOCATOR.dealloc(again, layout) };
+}
+
+/// Checks a 64 KiB request, too big for any size class, is served and freed as a whole
+/// frame block. No-op without the slub.
+pub fn check_oversize_alloc() {
+    let Some(slub) = KERNEL_ALLOCATOR.slub() else {
+        return;
+    };
+
+    let layout = Layout::from_size_align(64 * 1024, 8).expect("Invalid test layout");
+    let ptr =
+        NonNull::new(unsafe { KERNEL_ALLOCATOR.alloc(layout) }).expect("64 KiB allocation failed");
 
-pub fn init(memory: Memory) {
-    let main_region = memory
-        .regions()
-        .next()
-        .expect("No memory regions defined in FDT");
+    assert!(
+        slub.class_for_ptr(ptr).is_none(),
+        "64 KiB served from a slab"
+    );
+    assert!(
+        frame_allocator().is_valid_allocation(ptr, layout),
+        "64 KiB not served as one frame block"
+    );
+    // the whole block is usable
+    unsafe {
+        ptr.write(0xa5);
+        ptr.add(layout.size() - 1).write(0x5a);
+    }
 
-    let ram_start = PhysicalAddress::new(main_region.starting_address as usize);
-    let ram_size = main_region
-        .size
-        .expect("No size defined for the main memory region");
+    unsafe { KERNEL_ALLOCATOR.dealloc(ptr.as_ptr(), layout) };
+    assert!(
+        !frame_allocator().is_valid_allocation(ptr, layout),
+        "64 KiB block still allocated after free"
+    );
+}
+
+/// Checks requests from 1 byte to several frames go to the smallest fitting size class
+/// while one fits and to the frame allocator past that, and come back the same way.
+/// No-op without the slub.
+pub fn check_size_routing() {
+    let Some(slub) = KERNEL_ALLOCATOR.slub() else {
+        return;
+    };
 
-    let pmem_map = PhysicalMemoryMap::calculate(ram_start, ram_size);
+    let largest_slot = SlubAllocator::max_slab_alloc_size();
+    for size in [1, 33, 512, largest_slot, largest_slot + 1, 3 * BASE_SIZE] {
+        let layout = Layout::from_size_align(size, 8).expect("Invalid test layout");
+        let ptr = NonNull::new(unsafe { KERNEL_ALLOCATOR.alloc(layout) })
+            .unwrap_or_else(|| panic!("{}-byte allocation failed", size));
+
+        match slub.class_for_ptr(ptr) {
+            Some(class) => {
+                assert!(
+                    size <= largest_slot,
+                    "{}-byte request served from a slab",
+                    size
+                );
+                let smallest_fit = slub
+                    .classes()
+                    .map(|class| class.object_size())
+                    .find(|&object_size| object_size >= size);
+                assert_eq!(
+                    Some(class.object_size()),
+                    smallest_fit,
+                    "{}-byte request not in the smallest fitting class",
+                    size
+                );
+            }
+            None => {
+                assert!(
+                    size > largest_slot,
+                    "{}-byte request skipped the slub",
+                    size
+                );
+                assert!(frame_allocator().is_valid_allocation(ptr, layout));
+            }
+        }
+
+        unsafe { KERNEL_ALLOCATOR.dealloc(ptr.as_ptr(), layout) };
+    }
+}
+
+/// Checks `SlubAllocator::class_for_ptr` maps a fresh 64-byte allocation back to the
+/// 64-byte class and rejects memory the slub does not own. No-op without the slub.
+pub fn check_class_for_ptr() {
+    let Some(slub) = KERNEL_ALLOCATOR.slub() else {
+        return;
+    };
+
+    let layout = Layout::from_size_align(64, 8).expect("Invalid test layout");
+    let ptr =
+        NonNull::new(unsafe { KERNEL_ALLOCATOR.alloc(layout) }).expect("64-byte allocation failed");
+
+    let class = slub.class_for_ptr(ptr);
+    assert_eq!(
+        class.map(|class| class.object_size()),
+        Some(64),
+        "64-byte allocation not traced back to its class"
+    );
+    unsafe { KERNEL_ALLOCATOR.dealloc(ptr.as_ptr(), layout) };
+
+    // page-backed and off-heap memory belongs to no class
+    let frame = frame_allocator()
+        .alloc(BASE_SIZE_LAYOUT)
+        .expect("Frame allocation failed");
+    assert!(
+        slub.class_for_ptr(frame).is_none(),
+        "Frame claimed by a size class"
+    );
+    frame_allocator().dealloc(frame, BASE_SIZE_LAYOUT);
+
+    let on_stack = 0u8;
+    assert!(slub.class_for_ptr(NonNull::from(&on_stack)).is_none());
+}
+
+/// Runs `check` on the heap with one live 64-byte slot on a partial slab, then again with
+/// that slab's in-use count off by one, restoring it afterwards. Returns both results and
+/// the slab's address.
+fn with_corrupted_slab<R>(mut check: impl FnMut() -> R) -> (R, R, PhysicalAddress) {
+    let layout = Layout::from_size_align(64, 8).expect("Invalid test layout");
+    let ptr =
+        NonNull::new(unsafe { KERNEL_ALLOCATOR.alloc(layout) }).expect("64-byte allocation failed");
+
+    // the rest of the refill goes back to the slab, leaving it on the partial list
+    KERNEL_ALLOCATOR.flush_hart_cache(crate::cpu::current_hart_id());
+    let healthy = check();
+
+    let frame = pmem_map().address_to_frame_ptr(PhysicalAddress::from(ptr.as_ptr() as usize));
+    let slab = unsafe { frame.as_ref() }.slab_head().unwrap_or(frame);
+    let slab_address = pmem_map().frame_ref_to_address(unsafe { slab.as_ref() });
+
+    unsafe { slab.as_ref() }.lock_slab_info().in_use_count += 1;
+    let corrupted = check();
+    unsafe { slab.as_ref() }.lock_slab_info().in_use_count -= 1;
+
+    unsafe { KERNEL_ALLOCATOR.dealloc(ptr.as_ptr(), layout) };
+    (healthy, corrupted, slab_address)
+}
+
+/// Checks that `validate_heap` passes on a healthy heap and catches a slab whose
+/// in-use count was corrupted. No-op without the slub.
+pub fn check_validate_heap() {
+    let Some(slub) = KERNEL_ALLOCATOR.slub() else {
+        return;
+    };
+
+    let (healthy, corrupted, slab_address) = with_corrupted_slab(|| slub.try_validate_heap());
+    assert_eq!(healthy, Ok(()), "Healthy heap failed validation");
+    assert!(
+        matches!(
+            corrupted,
+            Err(HealthIssue::SlabInUseCount { slab, .. }) if slab == slab_address
+        ),
+        "Corrupted in-use count not detected: {:?}",
+        corrupted
+    );
+
+    slub.validate_heap();
+}
+
+/// Checks that `health_check` reports nothing on a healthy heap and lists a slab whose
+/// in-use count was corrupted. No-op without the slub.
+pub fn check_health_report() {
+    if KERNEL_ALLOCATOR.slub().is_none() {
+        return;
+    }
+
+    let (healthy, corrupted, slab_address) = with_corrupted_slab(|| health_check(false));
+    assert!(healthy.is_healthy(), "Healthy heap reported: {}", healthy);
+    assert!(
+        corrupted.issues().any(|issue| matches!(
+            issue,
+            HealthIssue::SlabInUseCount { slab, .. } if *slab == slab_address
+        )),
+        "Corrupted in-use count not reported: {}",
+        corrupted
+    );
+}
+
+/// Checks that `realloc` keeps the slot within a size class and preserves the contents
+/// when moving to another class or onto whole frames. No-op without the slub.
+pub fn check_realloc() {
+    if KERNEL_ALLOCATOR.slub().is_none() {
+        return;
+    }
+
+    let layout = Layout::from_size_align(40, 8).expect("Invalid test layout");
+    let ptr = unsafe { KERNEL_ALLOCATOR.alloc(layout) };
+    assert!(!ptr.is_null(), "40-byte allocation failed");
+    for i in 0..40 {
+        unsafe { ptr.add(i).write(i as u8) };
+    }
+
+    let same_class = unsafe { KERNEL_ALLOCATOR.realloc(ptr, layout, 48) };
+    assert_eq!(
+        same_class, ptr,
+        "Realloc within the 64-byte class moved the slot"
+    );
+
+    let mut layout = Layout::from_size_align(48, 8).expect("Invalid test layout");
+    let mut ptr = same_class;
+    // next class up, then past the largest class onto whole frames, then back down
+    for new_size in [100, 3 * BASE_SIZE, 16] {
+        let moved = unsafe { KERNEL_ALLOCATOR.realloc(ptr, layout, new_size) };
+        assert!(!moved.is_null(), "Realloc to {} bytes failed", new_size);
+        assert_ne!(
+            moved, ptr,
+            "Realloc to {} bytes kept the old slot",
+            new_size
+        );
+
+        let kept = layout.size().min(new_size).min(40);
+        assert!(
+            (0..kept).all(|i| unsafe { moved.add(i).read() } == i as u8),
+            "Realloc to {} bytes lost the contents",
+            new_size
+        );
+
+        ptr = moved;
+        layout = Layout::from_size_align(new_size, 8).expect("Invalid test layout");
+    }
+
+    unsafe { KERNEL_ALLOCATOR.dealloc(ptr, layout) };
+}
+
+/// Checks `alloc_cache_aligned` refuses a zero size and aligns a one byte request.
+/// Needs the kernel allocator.
+pub fn check_cache_aligned() {
+    assert!(
+        alloc_cache_aligned(0).is_none(),
+        "Zero sized allocation handed out"
+    );
+
+    let ptr = alloc_cache_aligned(1).expect("One byte allocation failed");
+    assert!(PhysicalAddress::from(ptr.as_ptr() as usize).is_aligned_to(CACHE_LINE_SIZE));
+    unsafe { dealloc_cache_aligned(ptr, 1) };
+}
+
+/// Checks that `zero_frame` and `alloc_zeroed` clear memory that was dirtied before,
+/// for whole blocks and, with the slub, for slots. Needs the frame allocator.
+pub fn check_alloc_zeroed() {
+    const DIRT: u8 = 0xa5;
+
+    let frame = frame_allocator()
+        .alloc(BASE_SIZE_LAYOUT)
+        .expect("Frame allocation failed");
+    unsafe {
+        frame.write_bytes(DIRT, BASE_SIZE);
+        zero_frame(frame);
+    }
+    assert!(
+        (0..BASE_SIZE).all(|i| unsafe { frame.add(i).read() } == 0),
+        "zero_frame left data behind"
+    );
+    frame_allocator().dealloc(frame, BASE_SIZE_LAYOUT);
+
+    // 3 frames round up to a 4 frame block, all of which must come back zeroed
+    let layout = Layout::from_size_align(3 * BASE_SIZE, BASE_SIZE).expect("Invalid test layout");
+    let block_size = 4 * BASE_SIZE;
+    let block = frame_allocator()
+        .alloc(layout)
+        .expect("Block allocation failed");
+    unsafe { block.write_bytes(DIRT, block_size) };
+    frame_allocator().dealloc(block, layout);
+
+    let block = frame_allocator()
+        .alloc_zeroed(layout)
+        .expect("Zeroed block allocation failed");
+    assert!(
+        (0..block_size).all(|i| unsafe { block.add(i).read() } == 0),
+        "alloc_zeroed returned a dirty block"
+    );
+    frame_allocator().dealloc(block, layout);
+
+    if KERNEL_ALLOCATOR.slub().is_none() {
+        return;
+    }
+
+    // a dirtied slot is likely handed out again by the hart cache
+    let layout = Layout::from_size_align(40, 8).expect("Invalid test layout");
+    let slot = unsafe { KERNEL_ALLOCATOR.alloc(layout) };
+    assert!(!slot.is_null(), "40-byte allocation failed");
+    unsafe {
+        slot.write_bytes(DIRT, layout.size());
+        KERNEL_ALLOCATOR.dealloc(slot, layout);
+    }
+
+    let slot = unsafe { KERNEL_ALLOCATOR.alloc_zeroed(layout) };
+    assert!(!slot.is_null(), "Zeroed 40-byte allocation failed");
+    assert!(
+        (0..layout.size()).all(|i| unsafe { slot.add(i).read() } == 0),
+        "alloc_zeroed returned a dirty slot"
+    );
+    unsafe { KERNEL_ALLOCATOR.dealloc(slot, layout) };
+}
+
+/// Called when a `Box`/`Vec`/... allocation fails, i.e. `GlobalAlloc::alloc` returned null.
+///
+/// The panic handler follows up with `dump_state`, so the report has the per-order free
+/// blocks and slab stats next to the failing layout. `#[alloc_error_handler]` is unstable,
+/// stable builds fall back to the default handler, whose message has the size only.
+#[cfg(nightly)]
+#[alloc_error_handler]
+fn alloc_error_handler(layout: Layout) -> ! {
+    panic!(
+        "Kernel allocation error: size={}, align={}",
+        layout.size(),
+        layout.align()
+    );
+}
+
+/// The boot hart's stack at the top of RAM, it is in use long before and after `init`.
+fn boot_stack_region() -> MemoryRegion {
+    // defined by the linker script
+    unsafe extern "C" {
+        static _stack_bottom: [u8; 0];
+        static _stack_top: [u8; 0];
+    }
+    let bottom = unsafe { _stack_bottom.as_ptr() as usize };
+    let top = unsafe { _stack_top.as_ptr() as usize };
+
+    MemoryRegion::new(PhysicalAddress::new(bottom), top - bottom)
+}
+
+/// Collects every RAM range the frame allocator must stay away from:
+/// FDT `/reserved-memory` children, the DTB blob itself and the boot stack.
+fn reserved_regions(fdt: &Fdt, dtb_ptr: PhysicalAddress) -> ReservedRegions {
+    let mut reserved = ReservedRegions::new();
+
+    reserved.push(MemoryRegion::new(dtb_ptr, fdt.total_size()));
+    reserved.push(boot_stack_region());
+
+    if let Some(reserved_memory) = fdt.find_node("/reserved-memory") {
+        for node in reserved_memory.children() {
+            // the cast addresses below lose the high cell with narrow pointers, so a
+            // region past the address space would land somewhere in low memory instead
+            let Some(address) = drivers::reg_address(&node) else {
+                continue;
+            };
+            if !drivers::fits_pointer(address, usize::BITS) {
+                log_warn!(
+                    "Reserved region {} at {:#x} is out of the address space, skipping",
+                    node.name,
+                    address
+                );
+                continue;
+            }
+
+            for region in node.reg().into_iter().flatten() {
+                if let Some(size) = region.size {
+                    reserved.push(MemoryRegion::new(
+                        PhysicalAddress::new(region.starting_address as usize),
+                        size,
+                    ));
+                }
+            }
+        }
+    }
+
+    reserved
+}
+
+pub fn init(fdt: &Fdt, dtb_ptr: PhysicalAddress, config: AllocatorConfig) {
+    let mut banks = RamRegions::new();
+    for region in fdt.memory().regions() {
+        let size = region.size.expect("No size defined for a memory region");
+        let bank = MemoryRegion::new(PhysicalAddress::new(region.starting_address as usize), size);
+        if let Err(bank) = banks.push(bank) {
+            log_warn!(
+                "RAM bank {} left unused, at most {} banks are supported",
+                bank,
+                pmem_map::MAX_RAM_REGIONS
+            );
+        }
+    }
+    assert!(!banks.is_empty(), "No memory regions defined in FDT");
+
+    let reserved = reserved_regions(fdt, dtb_ptr);
+    let pmem_map = PhysicalMemoryMap::calculate(&banks, &reserved);
 
     PMEM_MAP.set(pmem_map).expect("Failed to set PMEM_MAP");
     println!("{}", PMEM_MAP.get().unwrap());
 
     let frame_allocator = unsafe {
-        FrameAllocator::init(PMEM_MAP.get().expect("PMEM_MAP not set") as *const PhysicalMemoryMap)
+        FrameAllocator::init(
+            PMEM_MAP.get().expect("PMEM_MAP not set") as *const PhysicalMemoryMap,
+            config,
+        )
     };
 
+    if config.memtest != MemtestMode::Off {
+        let pmem_map = PMEM_MAP.get().expect("PMEM_MAP not set");
+        let report = memtest::run(
+            &frame_allocator,
+            pmem_map,
+            config.memtest,
+            memtest::test_frame,
+        );
+        match report.bad {
+            0 => println!(
+                "[ OK ] memtest ({}): {} frames tested",
+                config.memtest, report.tested
+            ),
+            bad => println!(
+                "[WARN] memtest ({}): {} of {} frames bad, reserved for good",
+                config.memtest, bad, report.tested
+            ),
+        }
+    }
+
     let orders = frame_allocator.orders();
     let bitmap = frame_allocator.bitmap();
 
     match FRAME_ALLOCATOR.set(frame_allocator) {
         Ok(_) => {
             println!(
-                "[ OK ] FrameAllocator successfully initialized (orders: {}, bitmap: {:b})",
-                orders, bitmap
+                "[ OK ] FrameAllocator successfully initialized (orders: {}, bitmap: {:b}, zeroed: {})",
+                orders, bitmap, config.zero_free_frames
             );
         }
         Err(_) => {
@@ -74,5 +668,19 @@ pub fn init(memory: Memory) {
         }
     }
 
+    // with `no-slub` every allocation goes straight to the frame allocator
+    if !cfg!(feature = "no-slub") {
+        KERNEL_ALLOCATOR.init(SlubAllocator::new(MAX_HARTS));
+        println!("[ OK ] SlubAllocator successfully initialized");
+    }
+
+    let dma_pool_size = config.dma_pool_size.unwrap_or(dma::DEFAULT_DMA_POOL_SIZE);
+    if dma_pool_size > 0 {
+        match reserve_dma_pool(dma_pool_size) {
+            Ok(()) => println!("[ OK ] DMA pool reserved ({} KiB)", dma_pool_size / 1024),
+            Err(e) => log_warn!("DMA pool not reserved: {}", e),
+        }
+    }
+
     // TODO: check kernel allocator initialization
 }
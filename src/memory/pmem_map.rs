@@ -5,7 +5,15 @@ use crate::memory::frame::{BASE_SIZE, Frame};
 use core::fmt;
 use core::ptr::NonNull;
 
-#[derive(Debug)]
+pub const MAX_RESERVED_REGIONS: usize = 16;
+pub const MAX_RAM_REGIONS: usize = 8;
+/// Size of the crash log region at the top of the kernel's RAM bank, see `crate::crash_log`.
+pub const CRASH_LOG_SIZE: usize = BASE_SIZE;
+/// The frame pool may take at most this fraction of the kernel's RAM bank, extra banks that
+/// would need more (e.g. far away, with a huge hole in between) are left unused.
+pub const FRAME_POOL_MAX_SHARE: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct MemoryRegion {
     start: PhysicalAddress,
     size: usize,
@@ -31,13 +39,229 @@ impl MemoryRegion {
     pub fn contains(&self, address: PhysicalAddress) -> bool {
         address >= self.start && address < self.end()
     }
+
+    /// Returns `true` if the two regions share at least one byte.
+    pub fn overlaps(&self, other: &MemoryRegion) -> bool {
+        self.start < other.end() && other.start < self.end()
+    }
+
+    /// Smallest region covering both, including the hole between them.
+    pub fn span(&self, other: &MemoryRegion) -> MemoryRegion {
+        let start = self.start.min(other.start);
+        let end = self.end().max(other.end());
+        MemoryRegion::new(start, end - start)
+    }
+
+    /// Yields the frame pool index of every `BASE_SIZE` frame the region spans,
+    /// counted from `base`.
+    ///
+    /// The region is expected to be frame-aligned and to start at or after `base`.
+    pub fn frame_indices(&self, base: PhysicalAddress) -> impl Iterator<Item = usize> {
+        debug_assert!(
+            self.start.is_aligned_to(BASE_SIZE) && self.size.is_multiple_of(BASE_SIZE),
+            "Region {} is not frame aligned",
+            self
+        );
+
+        let first = self.start.offset_from(base) / BASE_SIZE;
+        first..first + self.size / BASE_SIZE
+    }
+}
+
+/// A fixed-capacity list of memory regions that must never be handed out
+/// by the frame allocator (FDT `/reserved-memory` nodes, the DTB blob, ...).
+///
+/// Regions are kept sorted by start address and expanded to whole frames.
+#[derive(Debug, Clone, Copy)]
+pub struct ReservedRegions {
+    regions: [MemoryRegion; MAX_RESERVED_REGIONS],
+    len: usize,
+}
+
+impl ReservedRegions {
+    pub const fn new() -> Self {
+        Self {
+            regions: [MemoryRegion::new(PhysicalAddress::new(0), 0); MAX_RESERVED_REGIONS],
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Adds a region, rounding it outwards to frame boundaries.
+    ///
+    /// # Panics
+    ///
+    /// Panics if more than `MAX_RESERVED_REGIONS` non-empty regions are added, empty ones
+    /// are ignored.
+    pub fn push(&mut self, region: MemoryRegion) {
+        if region.size() == 0 {
+            return;
+        }
+
+        assert!(
+            self.len < MAX_RESERVED_REGIONS,
+            "Too many reserved memory regions (max {})",
+            MAX_RESERVED_REGIONS
+        );
+
+        let start = region.start().align_down(BASE_SIZE);
+        let end = region.end().align_up(BASE_SIZE);
+        let region = MemoryRegion::new(start, end - start);
+
+        // keep sorted by start address
+        let idx = self.regions[..self.len]
+            .iter()
+            .position(|r| r.start() > start)
+            .unwrap_or(self.len);
+
+        self.regions.copy_within(idx..self.len, idx + 1);
+        self.regions[idx] = region;
+        self.len += 1;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &MemoryRegion> {
+        self.regions[..self.len].iter()
+    }
+
+    pub fn contains(&self, address: PhysicalAddress) -> bool {
+        self.iter().any(|region| region.contains(address))
+    }
+}
+
+impl Default for ReservedRegions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A fixed-capacity list of RAM banks, e.g. the FDT `/memory` `reg` entries.
+///
+/// Banks are kept sorted by start address and shrunk to whole frames.
+#[derive(Debug, Clone, Copy)]
+pub struct RamRegions {
+    regions: [MemoryRegion; MAX_RAM_REGIONS],
+    len: usize,
+}
+
+impl RamRegions {
+    pub const fn new() -> Self {
+        Self {
+            regions: [MemoryRegion::new(PhysicalAddress::new(0), 0); MAX_RAM_REGIONS],
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Adds a bank, rounding it inwards to frame boundaries. Banks smaller than a frame
+    /// are dropped.
+    ///
+    /// Returns the rounded bank back if all `MAX_RAM_REGIONS` slots are taken.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bank overlaps another.
+    pub fn push(&mut self, region: MemoryRegion) -> Result<(), MemoryRegion> {
+        let start = region.start().align_up(BASE_SIZE);
+        let end = region.end().align_down(BASE_SIZE);
+        if start >= end {
+            return Ok(());
+        }
+        let region = MemoryRegion::new(start, end - start);
+
+        if self.len == MAX_RAM_REGIONS {
+            return Err(region);
+        }
+
+        assert!(
+            !self.iter().any(|bank| bank.overlaps(&region)),
+            "RAM region {} overlaps another one",
+            region
+        );
+
+        // keep sorted by start address
+        let idx = self.regions[..self.len]
+            .iter()
+            .position(|r| r.start() > start)
+            .unwrap_or(self.len);
+
+        self.regions.copy_within(idx..self.len, idx + 1);
+        self.regions[idx] = region;
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &MemoryRegion> {
+        self.regions[..self.len].iter()
+    }
+
+    pub fn contains(&self, address: PhysicalAddress) -> bool {
+        self.iter().any(|region| region.contains(address))
+    }
+}
+
+impl Default for RamRegions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Which part of the physical memory layout an address falls into, see `PhysicalMemoryMap::classify`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    Kernel,
+    FramePool,
+    AllocatorMeta,
+    CrashLog,
+    /// A `reserved` region carved out of free memory.
+    Reserved,
+    FreeMemory,
+    /// RAM not covered by any of the regions above, e.g. alignment padding.
+    Ram,
+    Outside,
+}
+
+impl fmt::Display for RegionKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            RegionKind::Kernel => "kernel image",
+            RegionKind::FramePool => "frame pool",
+            RegionKind::AllocatorMeta => "allocator metadata",
+            RegionKind::CrashLog => "crash log",
+            RegionKind::Reserved => "reserved region",
+            RegionKind::FreeMemory => "free memory",
+            RegionKind::Ram => "unmapped RAM",
+            RegionKind::Outside => "outside of RAM",
+        };
+        f.write_str(name)
+    }
 }
 
 #[derive(Debug)]
 pub struct PhysicalMemoryMap {
-    /// The total available physical RAM discovered from the hardware.
+    /// The RAM bank holding the kernel image, all metadata regions below live in it.
     pub ram: MemoryRegion,
 
+    /// Every other RAM bank, free memory in its entirety apart from `reserved` regions.
+    pub extra_ram: RamRegions,
+
+    /// All RAM banks and the holes between them. The frame pool has an entry for every
+    /// frame of it, frames in holes stay `Reserved` for good.
+    pub frame_span: MemoryRegion,
+
     /// The region occupied by the kernel's binary (.text, .rodata, .data, .bss).
     pub kernel: MemoryRegion,
 
@@ -48,40 +272,230 @@ pub struct PhysicalMemoryMap {
     /// The region reserved within RAM to store the allocator's internal data.
     pub frame_allocator_metadata: MemoryRegion,
 
+    /// The last frames of `ram`, left untouched across warm reboots so a panic record
+    /// written there can be read on the next boot. Empty for buffer-backed maps.
+    pub crash_log: MemoryRegion,
+
     /// The start address of the first physical page that is available for
     /// general-purpose allocation by the frame allocator.
     pub free_memory: MemoryRegion,
+
+    /// Regions inside `free_memory` or `extra_ram` that must be skipped by the frame allocator.
+    pub reserved: ReservedRegions,
 }
 
 impl PhysicalMemoryMap {
-    pub fn calculate(ram_start: PhysicalAddress, ram_size: usize) -> Self {
-        let ram = MemoryRegion::new(ram_start, ram_size);
-
-        assert_eq!(ram.size() % BASE_SIZE, 0, "RAM size is not page-aligned");
+    /// Lays out the kernel metadata regions in the RAM bank holding the kernel, right after
+    /// the kernel image. The other banks only add free memory.
+    ///
+    /// `reserved` regions outside of RAM are ignored, regions inside the free memory
+    /// are carved out of it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no bank holds the kernel, a reserved region overlaps the kernel image
+    /// or the allocator metadata, or the kernel image ends up in memory the frame allocator
+    /// hands out.
+    pub fn calculate(banks: &RamRegions, reserved: &ReservedRegions) -> Self {
+        let (kernel_start, _) = Self::kernel_bounds();
+        let ram = *banks
+            .iter()
+            .find(|bank| bank.contains(kernel_start))
+            .expect("No RAM region holds the kernel image");
 
         let kernel_region = Self::init_kernel_region(&ram);
 
-        let frame_pool_region = Self::init_frame_pool_region(&ram, kernel_region.end());
+        let map = Self::layout(
+            ram,
+            Self::other_banks(banks, &ram),
+            kernel_region,
+            CRASH_LOG_SIZE,
+            reserved,
+        );
+
+        // the layout assumes the kernel sits below everything it hands out, check the
+        // image the linker placed against the result
+        let (kernel_start, kernel_end) = Self::kernel_bounds();
+        let image = MemoryRegion::new(kernel_start, kernel_end.offset_from(kernel_start));
+        if let Some(region) = map.kernel_overlap(&image) {
+            panic!(
+                "Kernel image {} overlaps allocatable memory {}",
+                image, region
+            );
+        }
+
+        map
+    }
+
+    /// Lays out the allocator regions inside a caller-provided buffer instead of hardware RAM,
+    /// so the frame allocator (and the slub on top of it) can run without QEMU.
+    ///
+    /// The buffer is trimmed to whole frames and contains no kernel image.
+    ///
+    /// For the allocator checks only, so debug builds only: buffer addresses are taken as physical ones, which
+    /// holds only while the kernel runs identity mapped, and nothing else may touch the
+    /// buffer while the map or an allocator built over it is in use.
+    #[cfg(debug_assertions)]
+    pub(crate) fn from_buffer(buf: &'static mut [u8]) -> Self {
+        Self::from_buffers([buf])
+    }
+
+    /// Like `from_buffer` with several discontiguous banks, the first one holds the metadata.
+    /// The same restrictions apply.
+    #[cfg(debug_assertions)]
+    pub(crate) fn from_buffers<const N: usize>(bufs: [&'static mut [u8]; N]) -> Self {
+        let mut banks = RamRegions::new();
+        for buf in &bufs {
+            let buf_start = PhysicalAddress::from(buf.as_ptr() as usize);
+            banks
+                .push(MemoryRegion::new(buf_start, buf.len()))
+                .expect("Too many buffers for the RAM banks");
+        }
+
+        let first = PhysicalAddress::from(bufs[0].as_ptr() as usize);
+        let ram = *banks
+            .iter()
+            .find(|bank| bank.contains(first.align_up(BASE_SIZE)))
+            .expect("Buffer is too small to hold a single frame");
+        let kernel_region = MemoryRegion::new(ram.start(), 0);
+
+        Self::layout(
+            ram,
+            Self::other_banks(&banks, &ram),
+            kernel_region,
+            // a buffer does not outlive the boot, nothing to keep a crash log in
+            0,
+            &ReservedRegions::new(),
+        )
+    }
+
+    fn other_banks(banks: &RamRegions, ram: &MemoryRegion) -> RamRegions {
+        let mut extra_ram = RamRegions::new();
+        for bank in banks.iter().filter(|bank| bank.start() != ram.start()) {
+            extra_ram.push(*bank).expect("Fewer banks than the source");
+        }
+        extra_ram
+    }
+
+    /// Picks the `candidates` the frame pool can cover next to `ram`, returning them and the
+    /// span their frame pool entries cover.
+    ///
+    /// The frame pool has an entry for every frame of the span, holes between banks
+    /// included, and may take at most a `FRAME_POOL_MAX_SHARE`th of `ram`. Banks growing the
+    /// span the least are taken first, the rest are reported and left unused.
+    fn frame_pool_banks(ram: &MemoryRegion, candidates: &RamRegions) -> (RamRegions, MemoryRegion) {
+        let budget = ram.size() / FRAME_POOL_MAX_SHARE;
+        let mut taken = [false; MAX_RAM_REGIONS];
+        let mut banks = RamRegions::new();
+        let mut span = *ram;
+
+        while let Some((idx, grown)) = candidates
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| !taken[*idx])
+            .map(|(idx, bank)| (idx, span.span(bank)))
+            .min_by_key(|(_, grown)| grown.size())
+            .filter(|(_, grown)| frame_pool_size(grown) <= budget)
+        {
+            taken[idx] = true;
+            span = grown;
+        }
+
+        for (bank, taken) in candidates.iter().zip(taken) {
+            if taken {
+                banks.push(*bank).expect("Fewer banks than the candidates");
+            } else {
+                log_warn!(
+                    "RAM bank {} left unused, its frame metadata would not fit",
+                    bank
+                );
+            }
+        }
+
+        (banks, span)
+    }
+
+    fn layout(
+        ram: MemoryRegion,
+        extra_ram: RamRegions,
+        kernel_region: MemoryRegion,
+        crash_log_size: usize,
+        reserved: &ReservedRegions,
+    ) -> Self {
+        // every bank gets frame pool entries, indexed from the lowest one
+        let (extra_ram, frame_span) = Self::frame_pool_banks(&ram, &extra_ram);
+
+        let frame_pool_region =
+            Self::init_frame_pool_region(&ram, &frame_span, kernel_region.end());
 
         let allocator_metadata_region =
-            Self::init_allocator_metadata_region(&ram, frame_pool_region.end());
+            Self::init_allocator_metadata_region(&ram, &frame_span, frame_pool_region.end());
+
+        let crash_log_region = Self::init_crash_log_region(&ram, crash_log_size);
 
-        let free_memory_region =
-            Self::init_free_memory_region(&ram, allocator_metadata_region.end());
+        let free_memory_region = Self::init_free_memory_region(
+            allocator_metadata_region.end(),
+            crash_log_region.start(),
+        );
+
+        let reserved = Self::init_reserved_regions(
+            reserved,
+            &free_memory_region,
+            &extra_ram,
+            &[
+                ("Kernel", &kernel_region),
+                ("Frame Pool", &frame_pool_region),
+                ("Allocator", &allocator_metadata_region),
+                ("Crash Log", &crash_log_region),
+            ],
+        );
 
         PhysicalMemoryMap {
             ram,
+            extra_ram,
+            frame_span,
             kernel: kernel_region,
             frame_pool: frame_pool_region,
             frame_allocator_metadata: allocator_metadata_region,
+            crash_log: crash_log_region,
             free_memory: free_memory_region,
+            reserved,
         }
     }
 
+    /// Checks `classify` against the first address of each laid out region and one beyond RAM.
+    pub fn check_classify(&self) {
+        let regions = [
+            (&self.kernel, RegionKind::Kernel),
+            (&self.frame_pool, RegionKind::FramePool),
+            (&self.frame_allocator_metadata, RegionKind::AllocatorMeta),
+            (&self.crash_log, RegionKind::CrashLog),
+        ];
+
+        for (region, kind) in regions.into_iter().filter(|(region, _)| region.size() > 0) {
+            assert_eq!(self.classify(region.start()), kind);
+        }
+
+        for region in self.reserved.iter() {
+            assert_eq!(self.classify(region.start()), RegionKind::Reserved);
+        }
+
+        for region in self.free_regions() {
+            if let Some(free) = (0..region.size() / BASE_SIZE)
+                .map(|i| region.start() + i * BASE_SIZE)
+                .find(|address| !self.reserved.contains(*address))
+            {
+                assert_eq!(self.classify(free), RegionKind::FreeMemory);
+            }
+        }
+
+        assert_eq!(self.classify(self.frame_span.end()), RegionKind::Outside);
+    }
+
     // INITIALIZERS
 
-    //
-    fn init_kernel_region(ram: &MemoryRegion) -> MemoryRegion {
+    /// Start and end of the kernel image.
+    fn kernel_bounds() -> (PhysicalAddress, PhysicalAddress) {
         // these symbols are defined by the linker script
         unsafe extern "C" {
             static _kernel_start: [u8; 0];
@@ -91,6 +505,14 @@ impl PhysicalMemoryMap {
         let kernel_start = unsafe { _kernel_start.as_ptr() as usize };
         let kernel_end = unsafe { _kernel_end.as_ptr() as usize };
 
+        (kernel_start.into(), kernel_end.into())
+    }
+
+    //
+    fn init_kernel_region(ram: &MemoryRegion) -> MemoryRegion {
+        let (kernel_start, kernel_end) = Self::kernel_bounds();
+        let (kernel_start, kernel_end) = (kernel_start.as_usize(), kernel_end.as_usize());
+
         assert!(
             ram.contains(kernel_start.into()),
             "Kernel start address is out of RAM bounds"
@@ -108,10 +530,10 @@ impl PhysicalMemoryMap {
 
     fn init_frame_pool_region(
         ram: &MemoryRegion,
+        frame_span: &MemoryRegion,
         kernel_region_end: PhysicalAddress,
     ) -> MemoryRegion {
-        let num_frames = ram.size() / BASE_SIZE;
-        let frame_pool_size = align_up(num_frames * size_of::<Frame>(), BASE_SIZE);
+        let frame_pool_size = frame_pool_size(frame_span);
 
         assert!(
             ram.contains(kernel_region_end + frame_pool_size),
@@ -123,9 +545,10 @@ impl PhysicalMemoryMap {
 
     fn init_allocator_metadata_region(
         ram: &MemoryRegion,
+        frame_span: &MemoryRegion,
         frame_pool_end: PhysicalAddress,
     ) -> MemoryRegion {
-        let num_frames = ram.size() / BASE_SIZE;
+        let num_frames = frame_span.size() / BASE_SIZE;
         let allocator_num_orders = (num_frames.ilog2() + 1) as usize;
         let allocator_metadata_size = align_up(
             allocator_num_orders * size_of::<DoublyLinkedList<Frame>>(),
@@ -140,32 +563,105 @@ impl PhysicalMemoryMap {
         MemoryRegion::new(frame_pool_end, allocator_metadata_size)
     }
 
+    /// Carves `size` bytes off the top of `ram`, at the same address on every boot.
+    fn init_crash_log_region(ram: &MemoryRegion, size: usize) -> MemoryRegion {
+        let size = align_up(size, BASE_SIZE);
+
+        assert!(size <= ram.size(), "Crash Log Region does not fit in RAM");
+
+        MemoryRegion::new(ram.end() - size, size)
+    }
+
     fn init_free_memory_region(
-        ram: &MemoryRegion,
         allocator_metadata_end: PhysicalAddress,
+        crash_log_start: PhysicalAddress,
     ) -> MemoryRegion {
         let free_memory_start = allocator_metadata_end;
 
-        assert_eq!(
-            free_memory_start.as_usize() % BASE_SIZE,
-            0,
+        assert!(
+            free_memory_start.is_aligned_to(BASE_SIZE),
             "Free memory region is not page-aligned"
         );
 
-        let free_memory_size = ram.end() - free_memory_start;
+        assert!(
+            free_memory_start <= crash_log_start,
+            "Crash Log Region overlaps the allocator metadata"
+        );
+
+        let free_memory_size = crash_log_start - free_memory_start;
 
         MemoryRegion::new(free_memory_start, free_memory_size)
     }
 
+    fn init_reserved_regions(
+        requested: &ReservedRegions,
+        free_memory: &MemoryRegion,
+        extra_ram: &RamRegions,
+        kernel_regions: &[(&str, &MemoryRegion)],
+    ) -> ReservedRegions {
+        let mut reserved = ReservedRegions::new();
+
+        for region in requested.iter() {
+            for (name, kernel_region) in kernel_regions {
+                assert!(
+                    !region.overlaps(kernel_region),
+                    "Reserved region {} overlaps the {} region {}",
+                    region,
+                    name,
+                    kernel_region
+                );
+            }
+
+            // clip to each free region, anything outside of them is never handed out anyway
+            for free in core::iter::once(free_memory).chain(extra_ram.iter()) {
+                let start = region.start().max(free.start());
+                let end = region.end().min(free.end());
+
+                if start < end {
+                    reserved.push(MemoryRegion::new(start, end - start));
+                }
+            }
+        }
+
+        reserved
+    }
+
+    /// The first region the frame allocator may hand out, or a reserved region carved from
+    /// one, that overlaps the kernel `image`.
+    pub fn kernel_overlap(&self, image: &MemoryRegion) -> Option<MemoryRegion> {
+        self.free_regions()
+            .chain(self.reserved.iter().copied())
+            .find(|region| region.overlaps(image))
+    }
+
     pub fn num_frames(&self) -> usize {
-        self.ram.size() / BASE_SIZE
+        self.frame_span.size() / BASE_SIZE
+    }
+
+    /// `true` if `address` lies in any RAM bank.
+    pub fn is_ram(&self, address: PhysicalAddress) -> bool {
+        self.ram.contains(address) || self.extra_ram.contains(address)
+    }
+
+    /// Memory the frame allocator manages: `free_memory` and every extra bank, `reserved`
+    /// regions included.
+    pub fn free_regions(&self) -> impl Iterator<Item = MemoryRegion> + '_ {
+        core::iter::once(self.free_memory).chain(self.extra_ram.iter().copied())
+    }
+
+    /// Total size of `free_regions`.
+    pub fn free_size(&self) -> usize {
+        self.free_regions().map(|region| region.size()).sum()
     }
 
     /// Returns corresponding frame pool index for a given physical address
     pub fn frame_idx_from_address(&self, address: PhysicalAddress) -> usize {
-        assert!(self.ram.contains(address), "Address is out of bounds");
+        assert!(
+            self.frame_span.contains(address),
+            "Address is out of bounds"
+        );
 
-        address.offset_from(self.ram.start()) / BASE_SIZE
+        address.offset_from(self.frame_span.start()) / BASE_SIZE
     }
 
     /// Converts a physical address to a raw pointer to the corresponding `Frame`
@@ -182,17 +678,95 @@ impl PhysicalMemoryMap {
     }
 
     /// Converts a `Frame` metadata reference to the corresponding memory region start address
+    ///
+    /// The returned address is always `BASE_SIZE`-aligned and maps back to the same
+    /// `Frame` through `address_to_frame_ptr()`, see `check_frame_round_trip`.
     pub fn frame_ref_to_address(&self, frame: &Frame) -> PhysicalAddress {
         let frame_addr = PhysicalAddress::new(frame as *const Frame as usize);
-        let frame_idx =
-            frame_addr.offset_from(self.frame_pool.start()) / core::mem::size_of::<Frame>();
+        let frame_offset = frame_addr.offset_from(self.frame_pool.start());
+
+        debug_assert_eq!(
+            frame_offset % core::mem::size_of::<Frame>(),
+            0,
+            "Frame reference is not aligned to a frame pool entry"
+        );
+
+        let frame_idx = frame_offset / core::mem::size_of::<Frame>();
+
+        debug_assert!(
+            frame_idx < self.num_frames(),
+            "Frame reference is outside of the frame pool"
+        );
+
+        let address = self.frame_span.start() + frame_idx * BASE_SIZE;
+
+        debug_assert!(address.is_aligned_to(BASE_SIZE));
+
+        address
+    }
+
+    /// Tells which region `address` belongs to, meant for fault and bad-pointer diagnostics.
+    pub fn classify(&self, address: PhysicalAddress) -> RegionKind {
+        if !self.is_ram(address) {
+            RegionKind::Outside
+        } else if self.kernel.contains(address) {
+            RegionKind::Kernel
+        } else if self.frame_pool.contains(address) {
+            RegionKind::FramePool
+        } else if self.frame_allocator_metadata.contains(address) {
+            RegionKind::AllocatorMeta
+        } else if self.crash_log.contains(address) {
+            RegionKind::CrashLog
+        } else if self.reserved.contains(address) {
+            RegionKind::Reserved
+        } else if self.free_regions().any(|region| region.contains(address)) {
+            RegionKind::FreeMemory
+        } else {
+            RegionKind::Ram
+        }
+    }
+
+    /// Returns the address of the buddy of the order `order` block starting at `address`,
+    /// or `None` if the buddy would not lie entirely within the same free region.
+    ///
+    /// Blocks are naturally aligned, so the buddy differs from `address` by exactly the block size bit.
+    pub fn buddy_address(&self, address: PhysicalAddress, order: u8) -> Option<PhysicalAddress> {
+        let block_size = (1 << order) * BASE_SIZE;
+
+        assert!(
+            address.is_aligned_to(block_size),
+            "Order {} block at {} is not aligned to its size",
+            order,
+            address
+        );
+        let region = self
+            .free_regions()
+            .find(|region| region.contains(address))
+            .unwrap_or_else(|| panic!("Block at {} is outside of free memory", address));
+
+        let buddy = address.buddy(block_size);
+
+        debug_assert_eq!(
+            buddy.as_usize() ^ address.as_usize(),
+            block_size,
+            "Buddy must differ from the block by exactly the order {} size bit",
+            order
+        );
+        debug_assert!(buddy.is_aligned_to(block_size));
 
-        self.ram.start() + frame_idx * BASE_SIZE
+        // the buddy of a block at the top of the address space would end past usize::MAX
+        let buddy_end = buddy.checked_add(block_size)?;
+        (region.contains(buddy) && buddy_end <= region.end()).then_some(buddy)
     }
 }
 
+/// Frame pool bytes for one `Frame` per frame of `span`.
+fn frame_pool_size(span: &MemoryRegion) -> usize {
+    align_up(span.size() / BASE_SIZE * size_of::<Frame>(), BASE_SIZE)
+}
+
 fn align_up(addr: usize, align: usize) -> usize {
-    (addr + align - 1) & !(align - 1)
+    PhysicalAddress::new(addr).align_up(align).as_usize()
 }
 
 impl fmt::Display for MemoryRegion {
@@ -220,16 +794,279 @@ impl fmt::Display for PhysicalMemoryMap {
             ("Frame Pool", &self.frame_pool),
             ("Allocator", &self.frame_allocator_metadata),
             ("Free RAM", &self.free_memory),
+            ("Crash Log", &self.crash_log),
         ];
 
         for (name, region) in regions {
             writeln!(f, "{name:<12} | {region}")?;
         }
+        for region in self.extra_ram.iter() {
+            writeln!(f, "{:<12} | {region}", "RAM Bank")?;
+        }
+        for region in self.reserved.iter() {
+            writeln!(f, "{:<12} | {region}", "  Reserved")?;
+        }
         writeln!(f, "{line}")?;
 
-        writeln!(f, "Total RAM:    {} KiB", self.ram.size() / 1024)?;
+        let total_ram =
+            self.ram.size() + self.extra_ram.iter().map(|bank| bank.size()).sum::<usize>();
+        writeln!(f, "Total RAM:    {} KiB", total_ram / 1024)?;
         writeln!(f, "Total Frames: {}", self.num_frames())?;
 
         Ok(())
     }
 }
+
+/// Checks `MemoryRegion::overlaps` for adjacent, nested and disjoint regions, and that
+/// `frame_indices` yields one index per frame counted from the base.
+pub fn check_memory_region() {
+    let base = PhysicalAddress::new(0x8000_0000);
+    let region = |frame: usize, frames: usize| {
+        MemoryRegion::new(base + frame * BASE_SIZE, frames * BASE_SIZE)
+    };
+
+    let outer = region(4, 8);
+    let cases = [
+        (region(0, 4), false, "adjacent below"),
+        (region(12, 2), false, "adjacent above"),
+        (region(6, 2), true, "nested"),
+        (region(4, 8), true, "identical"),
+        (region(10, 4), true, "straddling the end"),
+        (region(32, 4), false, "disjoint"),
+    ];
+    for (other, expected, case) in cases {
+        assert_eq!(
+            outer.overlaps(&other),
+            expected,
+            "Overlap of {} regions",
+            case
+        );
+        assert_eq!(
+            other.overlaps(&outer),
+            expected,
+            "Overlap of {} regions",
+            case
+        );
+    }
+
+    assert!(
+        outer.frame_indices(base).eq(4..12),
+        "Frame indices of a region are off"
+    );
+    assert_eq!(
+        outer.frame_indices(outer.start()).count(),
+        outer.size() / BASE_SIZE
+    );
+    assert_eq!(region(4, 0).frame_indices(base).count(), 0);
+}
+
+/// Checks that `ReservedRegions::push` keeps regions sorted and frame aligned, and ignores
+/// an empty region once the list is full.
+pub fn check_reserved_regions() {
+    let base = PhysicalAddress::new(0x8000_0000);
+
+    let mut reserved = ReservedRegions::new();
+    for idx in (0..MAX_RESERVED_REGIONS).rev() {
+        reserved.push(MemoryRegion::new(base + idx * 2 * BASE_SIZE + 8, 16));
+    }
+    // must not trip the capacity assert
+    reserved.push(MemoryRegion::new(base, 0));
+
+    assert_eq!(reserved.iter().count(), MAX_RESERVED_REGIONS);
+    assert!(
+        reserved
+            .iter()
+            .map(|region| region.start())
+            .eq((0..MAX_RESERVED_REGIONS).map(|idx| base + idx * 2 * BASE_SIZE)),
+        "Reserved regions not sorted and aligned down"
+    );
+    assert!(reserved.iter().all(|region| region.size() == BASE_SIZE));
+}
+
+/// Checks that `RamRegions::push` skips empty banks and hands back the ones beyond capacity,
+/// that banks too far away for the frame pool are dropped, and that a map over two
+/// discontiguous buffers indexes every free frame of both. Needs the frame allocator.
+#[cfg(debug_assertions)]
+pub fn check_ram_banks() {
+    const BANK_FRAMES: usize = 16;
+    const MIB: usize = 1 << 20;
+
+    let mut banks = RamRegions::new();
+    for idx in 0..MAX_RAM_REGIONS {
+        let start = PhysicalAddress::new(0x8000_0000 + idx * 2 * BASE_SIZE);
+        banks
+            .push(MemoryRegion::new(start, BASE_SIZE))
+            .expect("Bank rejected below capacity");
+    }
+    let empty = MemoryRegion::new(PhysicalAddress::new(0x9000_0001), BASE_SIZE - 1);
+    assert_eq!(banks.push(empty), Ok(()), "Empty bank rejected when full");
+    let extra = MemoryRegion::new(PhysicalAddress::new(0x9000_0000), BASE_SIZE);
+    assert_eq!(
+        banks.push(extra),
+        Err(extra),
+        "Bank beyond capacity accepted"
+    );
+    assert_eq!(banks.len(), MAX_RAM_REGIONS);
+
+    let ram = MemoryRegion::new(PhysicalAddress::new(0x8000_0000), 128 * MIB);
+    let near = MemoryRegion::new(ram.end() + 16 * MIB, 16 * MIB);
+    let far = MemoryRegion::new(PhysicalAddress::new(1 << 40), 16 * MIB);
+    let mut candidates = RamRegions::new();
+    candidates.push(far).expect("Bank rejected below capacity");
+    candidates.push(near).expect("Bank rejected below capacity");
+    let (kept, span) = PhysicalMemoryMap::frame_pool_banks(&ram, &candidates);
+    assert!(
+        kept.iter().eq([near].iter()),
+        "Frame pool bounding kept the wrong banks"
+    );
+    assert_eq!(span, ram.span(&near), "Frame span covers a dropped bank");
+
+    with_buffer_map::<2>(BANK_FRAMES, |map| {
+        assert_eq!(map.extra_ram.len(), 1, "Second bank was not tracked");
+        map.check_classify();
+
+        for region in map.free_regions() {
+            for frame in (0..region.size() / BASE_SIZE).map(|idx| region.start() + idx * BASE_SIZE)
+            {
+                assert_eq!(map.classify(frame), RegionKind::FreeMemory);
+                assert!(map.frame_idx_from_address(frame) < map.num_frames());
+            }
+        }
+    });
+}
+
+/// Checks `kernel_overlap` flags a kernel image placed mid-RAM, inside the free memory or
+/// an extra bank of a two-bank buffer map, and passes the image where the linker put it.
+/// Needs the frame allocator.
+#[cfg(debug_assertions)]
+pub fn check_kernel_overlap() {
+    with_buffer_map::<2>(16, |map| {
+        let extra = map
+            .extra_ram
+            .iter()
+            .next()
+            .expect("Second bank was not tracked");
+        let mid = |region: &MemoryRegion| {
+            MemoryRegion::new(region.start() + region.size() / 2, BASE_SIZE)
+        };
+
+        assert_eq!(
+            map.kernel_overlap(&mid(&map.free_memory)),
+            Some(map.free_memory),
+            "Kernel image inside free memory not flagged"
+        );
+        assert_eq!(
+            map.kernel_overlap(&mid(extra)),
+            Some(*extra),
+            "Kernel image inside an extra bank not flagged"
+        );
+        // right below the free memory, where the layout puts the metadata
+        let below = MemoryRegion::new(map.frame_pool.start(), map.frame_pool.size());
+        assert_eq!(map.kernel_overlap(&below), None);
+
+        let (kernel_start, kernel_end) = PhysicalMemoryMap::kernel_bounds();
+        let image = MemoryRegion::new(kernel_start, kernel_end.offset_from(kernel_start));
+        assert_eq!(
+            map.kernel_overlap(&image),
+            None,
+            "Linked kernel image flagged"
+        );
+    });
+}
+
+/// Checks every frame pool entry of a buffer map converts to its frame's address and back
+/// to the same entry. Needs the frame allocator.
+#[cfg(debug_assertions)]
+pub fn check_frame_round_trip() {
+    with_buffer_map::<1>(32, |map| {
+        for idx in 0..map.num_frames() {
+            let address = map.frame_span.start() + idx * BASE_SIZE;
+            let frame = map.address_to_frame_ptr(address);
+            // the pool is not initialized without an allocator over the map
+            unsafe { frame.as_ptr().write(Frame::new()) };
+
+            let round_trip = map.frame_ref_to_address(unsafe { frame.as_ref() });
+            assert_eq!(
+                round_trip, address,
+                "Frame {} maps to the wrong address",
+                idx
+            );
+            assert_eq!(map.address_to_frame_ptr(round_trip), frame);
+        }
+    });
+}
+
+/// Checks order-0 buddies differ from their block by exactly the `BASE_SIZE` bit and that
+/// buddies outside the free region are `None`. Needs the frame allocator.
+#[cfg(debug_assertions)]
+pub fn check_buddy_address() {
+    with_buffer_map::<1>(32, |map| {
+        let region = map.free_memory;
+
+        let mut paired = 0;
+        for idx in 0..region.size() / BASE_SIZE {
+            let address = region.start() + idx * BASE_SIZE;
+            match map.buddy_address(address, 0) {
+                Some(buddy) => {
+                    assert_eq!(buddy.as_usize() ^ address.as_usize(), BASE_SIZE);
+                    assert!(region.contains(buddy));
+                    paired += 1;
+                }
+                None => assert!(
+                    !region.contains(address.buddy(BASE_SIZE)),
+                    "Buddy of {} inside the region dropped",
+                    address
+                ),
+            }
+        }
+        assert!(paired > 0, "No order 0 block has a buddy");
+
+        // a block over half the region wide has its buddy past one of the region's ends
+        let order = (region.size() / BASE_SIZE).ilog2() as u8;
+        let block = region.start().align_up((1 << order) * BASE_SIZE);
+        assert_eq!(
+            map.buddy_address(block, order),
+            None,
+            "Buddy outside the region returned"
+        );
+    });
+}
+
+/// Runs `f` on a map over `N` discontiguous `bank_frames` frame buffers taken from the
+/// frame allocator, see `PhysicalMemoryMap::from_buffers`. The buffers are handed back
+/// afterwards.
+#[cfg(debug_assertions)]
+pub(crate) fn with_buffer_map<const N: usize>(
+    bank_frames: usize,
+    f: impl FnOnce(&PhysicalMemoryMap),
+) {
+    use core::alloc::Layout;
+
+    let layout =
+        Layout::from_size_align(bank_frames * BASE_SIZE, BASE_SIZE).expect("Invalid bank layout");
+    let frame_allocator = crate::memory::frame_allocator();
+
+    let mut spacers = [None; N];
+    let blocks: [NonNull<u8>; N] = core::array::from_fn(|idx| {
+        let block = frame_allocator
+            .alloc(layout)
+            .expect("Failed to allocate a test bank");
+        // freed once every bank is taken, usually leaving a hole between two banks
+        if idx + 1 < N {
+            spacers[idx] = frame_allocator.alloc(layout);
+        }
+        block
+    });
+    for spacer in spacers.into_iter().flatten() {
+        frame_allocator.dealloc(spacer, layout);
+    }
+
+    // SAFETY: the blocks are owned by this function until they are freed below
+    let buffers = blocks
+        .map(|block| unsafe { core::slice::from_raw_parts_mut(block.as_ptr(), layout.size()) });
+    f(&PhysicalMemoryMap::from_buffers(buffers));
+
+    for block in blocks {
+        frame_allocator.dealloc(block, layout);
+    }
+}
@@ -1,18 +1,30 @@
 use crate::cpu::current_hart_id;
-use crate::memory::frame::{BASE_SIZE, BASE_SIZE_LAYOUT, Frame};
-use crate::memory::hart_cache::{Greedy, HartCache, MAX_HARTS};
-use crate::memory::{FrameAllocator, frame_allocator, pmem_map};
-use crate::sync::{OnceLock, Spinlock};
+#[cfg(feature = "no-slub")]
+use crate::memory::FRAME_ALLOCATOR;
+use crate::memory::frame::{BASE_SIZE, Frame, SlabInfo, State};
+use crate::memory::hart_cache::{Greedy, HartCache, HartCaches, MAX_HARTS};
+use crate::memory::health::{HealthIssue, HealthReport};
+use crate::memory::{EMPTY_SLABS_LOCK_LEVEL, PARTIAL_SLABS_LOCK_LEVEL};
+use crate::memory::{FrameAllocator, PhysicalMemoryMap, RegionKind, frame_allocator, pmem_map};
+use crate::sync::{OnceLock, OrderedSpinlock, Spinlock};
 use crate::{
     collections::{DoublyLinkedList, SinglyLinkable},
     memory::PhysicalAddress,
 };
 
 use core::alloc::{GlobalAlloc, Layout};
-use core::cell::UnsafeCell;
+use core::fmt;
+use core::ops::Range;
 use core::ptr;
 use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
+/// Free-list link threaded through the first bytes of every free object.
+///
+/// Each free object holds a `Slot` at its start, so every size class must be at least
+/// `size_of::<Slot>()` bytes and aligned for it, otherwise writing `next` clobbers the
+/// neighbouring object. Growing `Slot` raises the smallest usable class: the compile-time
+/// check below `SIZE_CLASSES` fails until the classes are adjusted.
 pub struct Slot {
     next: Option<NonNull<Slot>>,
 }
@@ -31,61 +43,349 @@ const MIN_HART_CACHE_TARGET: usize = 8;
 const MAX_HART_CACHE_TARGET: usize = 128;
 const EMPTY_SLABS_CAP: usize = 4; // TODO: Make dynamic based on memory pressure
 
+/// How long, in `mtime` ticks, `reclaim_empty_slabs` keeps slabs for a peak that was not
+/// reached again. 1 s on QEMU `virt`'s 10 MHz timer.
+pub const SHRINK_COOLDOWN_TICKS: u64 = 10_000_000;
+
+/// Large classes get multi-frame slabs until each slab holds at least this many slots,
+/// otherwise e.g. a 2048-byte class would churn through a slab every 2 allocations.
+const MIN_SLOTS_PER_SLAB: usize = 4;
+const MAX_SLAB_ORDER: u8 = 3;
+
+/// Stamped right after a free slot's `next` link when `slub-poison` is enabled,
+/// followed by the owning `SizeClassManager` pointer if the slot is big enough.
+const FREE_MAGIC: usize = 0xF4EE_F4EE_DEAD_BEEF;
+const POISON_MAGIC_OFFSET: usize = 1;
+const POISON_OWNER_OFFSET: usize = 2;
+
+/// Point-in-time view of a size class' slab lists, see `SizeClassManager::try_snapshot`.
+#[derive(Debug, Clone, Copy)]
+pub struct SizeClassSnapshot {
+    pub object_size: usize,
+    pub partial_slabs: usize,
+    pub empty_slabs: usize,
+}
+
+impl fmt::Display for SizeClassSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:>5}-byte class: {} partial, {} empty slabs",
+            self.object_size, self.partial_slabs, self.empty_slabs
+        )
+    }
+}
+
+/// Peak live slot count `reclaim_empty_slabs` keeps room for, and when it was last reached.
+struct HighWatermark {
+    live_slots: usize,
+    tick: u64,
+}
+
 pub struct SizeClassManager {
-    hart_caches: [UnsafeCell<HartCache<Slot, Greedy>>; MAX_HARTS], // TODO: make dynamic based on number of harts
+    hart_caches: HartCaches<Slot, Greedy>,
+
+    partial_slabs: OrderedSpinlock<DoublyLinkedList<Frame>, PARTIAL_SLABS_LOCK_LEVEL>,
+    empty_slabs: OrderedSpinlock<DoublyLinkedList<Frame>, EMPTY_SLABS_LOCK_LEVEL>,
 
-    partial_slabs: Spinlock<DoublyLinkedList<Frame>>,
-    empty_slabs: Spinlock<DoublyLinkedList<Frame>>,
+    // slots taken out of slabs, including the ones parked in hart caches
+    live_slots: AtomicUsize,
+    // highest `live_slots` since the last `reclaim_empty_slabs`
+    recent_peak: AtomicUsize,
+    watermark: Spinlock<HighWatermark>,
 
     object_size: usize,
     slots_per_slab: usize,
+    slab_order: u8,
 }
 
 impl SizeClassManager {
-    pub fn new(num_harts: usize, object_size: usize) -> Self {
-        let slots_per_slab = BASE_SIZE / object_size;
+    /// Creates a manager for `object_size` objects carved out of `2^slab_order`-frame slabs.
+    pub fn new(num_harts: usize, object_size: usize, slab_order: u8) -> Self {
+        let slab_size = BASE_SIZE << slab_order;
+
+        assert!(
+            object_size <= slab_size,
+            "Size class {} does not fit into an order {} slab",
+            object_size,
+            slab_order
+        );
+        assert!(
+            fits_slot(object_size),
+            "Size class {} cannot hold a {}-byte free list link",
+            object_size,
+            size_of::<Slot>()
+        );
+
+        // round down so a trailing partial slot (e.g. 4096 % 1500) is never handed out
+        let slots_per_slab = slab_size / object_size;
 
         let hart_cache_target = slots_per_slab.clamp(MIN_HART_CACHE_TARGET, MAX_HART_CACHE_TARGET);
 
-        let hart_caches =
-            core::array::from_fn(|_| UnsafeCell::new(HartCache::new(hart_cache_target, Greedy)));
+        // each hart creates its own cache on first use
+        let hart_caches = HartCaches::new(hart_cache_target);
 
         Self {
             hart_caches,
-            partial_slabs: Spinlock::new(DoublyLinkedList::new()),
-            empty_slabs: Spinlock::new(DoublyLinkedList::new()),
+            partial_slabs: OrderedSpinlock::new(DoublyLinkedList::new()),
+            empty_slabs: OrderedSpinlock::new(DoublyLinkedList::new()),
+            live_slots: AtomicUsize::new(0),
+            recent_peak: AtomicUsize::new(0),
+            watermark: Spinlock::new(HighWatermark {
+                live_slots: 0,
+                tick: 0,
+            }),
             object_size,
             slots_per_slab,
+            slab_order,
         }
     }
 
+    pub fn object_size(&self) -> usize {
+        self.object_size
+    }
+
+    /// Snapshot of the slab lists, or `None` if either lock is held.
+    ///
+    /// Never blocks, so it is safe to call from the panic handler.
+    pub fn try_snapshot(&self) -> Option<SizeClassSnapshot> {
+        let partial_slabs = self.partial_slabs.try_lock()?.len();
+        let empty_slabs = self.empty_slabs.try_lock()?.len();
+
+        Some(SizeClassSnapshot {
+            object_size: self.object_size,
+            partial_slabs,
+            empty_slabs,
+        })
+    }
+
+    /// Records every listed slab whose in-use count doesn't match its list or free chain.
+    ///
+    /// Full slabs are on no list and go unchecked. Never blocks, held locks are reported instead.
+    pub fn check_consistency(&self, report: &mut HealthReport) {
+        let Some(partial_slabs) = self.partial_slabs.try_lock() else {
+            return report.push(HealthIssue::LockHeld("partial slab list"));
+        };
+        let Some(empty_slabs) = self.empty_slabs.try_lock() else {
+            return report.push(HealthIssue::LockHeld("empty slab list"));
+        };
+
+        let listed = partial_slabs
+            .iter()
+            .map(|slab| (slab, 1..self.slots_per_slab))
+            .chain(empty_slabs.iter().map(|slab| (slab, 0..1)));
+
+        for (slab, expected_in_use) in listed {
+            let Some(slab_info) = unsafe { slab.as_ref() }.try_lock_slab_info() else {
+                report.push(HealthIssue::LockHeld("slab info"));
+                continue;
+            };
+
+            if let Err(issue) = self.check_slab(slab, &slab_info, expected_in_use) {
+                report.push(issue);
+            }
+        }
+    }
+
+    /// Checks every listed slab's free chain and in-use count, returning the first
+    /// corruption found.
+    ///
+    /// Blocks on the slab lists. Slab info ranks below them in the lock order, so a slab
+    /// whose info is locked meanwhile is skipped rather than waited on.
+    pub fn validate(&self) -> Result<(), HealthIssue> {
+        let partial_slabs = self.partial_slabs.lock();
+        let empty_slabs = self.empty_slabs.lock();
+
+        let listed = partial_slabs
+            .iter()
+            .map(|slab| (slab, 1..self.slots_per_slab))
+            .chain(empty_slabs.iter().map(|slab| (slab, 0..1)));
+
+        for (slab, expected_in_use) in listed {
+            if let Some(slab_info) = unsafe { slab.as_ref() }.try_lock_slab_info() {
+                self.check_slab(slab, &slab_info, expected_in_use)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Walks `slab`'s free chain: every free slot must sit on the slot grid inside the slab,
+    /// and free plus in-use slots must add up to the whole slab.
+    ///
+    /// The walk stops at the first stray slot, it must not be dereferenced.
+    fn check_slab(
+        &self,
+        slab: NonNull<Frame>,
+        slab_info: &SlabInfo,
+        expected_in_use: Range<usize>,
+    ) -> Result<(), HealthIssue> {
+        let slab_start = pmem_map().frame_ref_to_address(unsafe { slab.as_ref() });
+        let slots_start = slab_start.as_mut_ptr::<u8>() as usize;
+        let slots_end = slots_start + self.slots_per_slab * self.object_size;
+
+        let mut free_slots = 0;
+        let mut next = slab_info.next_slot;
+        while let Some(slot) = next
+            && free_slots <= self.slots_per_slab
+        {
+            let address = slot.as_ptr() as usize;
+            if !(slots_start..slots_end).contains(&address)
+                || !(address - slots_start).is_multiple_of(self.object_size)
+            {
+                return Err(HealthIssue::StraySlot {
+                    object_size: self.object_size,
+                    slab: slab_start,
+                    slot: address,
+                });
+            }
+
+            free_slots += 1;
+            next = unsafe { slot.as_ref() }.next;
+        }
+
+        if !expected_in_use.contains(&slab_info.in_use_count)
+            || slab_info.in_use_count + free_slots != self.slots_per_slab
+        {
+            return Err(HealthIssue::SlabInUseCount {
+                object_size: self.object_size,
+                slab: slab_start,
+                in_use: slab_info.in_use_count,
+                free_slots,
+                slots_per_slab: self.slots_per_slab,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Smallest slab order giving at least `MIN_SLOTS_PER_SLAB` slots for `object_size`.
+    pub const fn default_slab_order(object_size: usize) -> u8 {
+        let mut order = 0;
+        while order < MAX_SLAB_ORDER {
+            if (BASE_SIZE << order) / object_size >= MIN_SLOTS_PER_SLAB {
+                return order;
+            }
+            order += 1;
+        }
+        MAX_SLAB_ORDER
+    }
+
+    #[inline]
+    fn slab_size(&self) -> usize {
+        BASE_SIZE << self.slab_order
+    }
+
+    #[inline]
+    fn slab_layout(&self) -> Layout {
+        Layout::from_size_align(self.slab_size(), BASE_SIZE).expect("Invalid slab layout")
+    }
+
     #[inline]
     #[allow(clippy::mut_from_ref)]
     fn hart_cache(&self, hart_id: usize) -> &mut HartCache<Slot, Greedy> {
-        unsafe { &mut *self.hart_caches[hart_id].get() }
+        self.hart_caches.get(hart_id)
     }
 
     pub fn alloc(&self) -> Option<NonNull<u8>> {
         let hart_id = current_hart_id();
         let cache = self.hart_cache(hart_id);
 
-        if let Some(slot) = cache.pop() {
-            return Some(slot.cast());
+        let slot = match cache.pop() {
+            Some(slot) => slot,
+            None => {
+                self.refill_hart_cache(hart_id).ok()?;
+                cache.pop()?
+            }
+        };
+
+        if cfg!(feature = "slub-poison") {
+            self.check_poison(slot);
         }
 
-        self.refill_hart_cache(hart_id).ok()?;
+        Some(slot.cast())
+    }
+
+    /// Number of `usize` words in a slot, word 0 always holds the `next` link.
+    #[inline]
+    fn poison_words(&self) -> usize {
+        self.object_size / size_of::<usize>()
+    }
+
+    /// Stamps a free slot with `FREE_MAGIC` and its owner, as far as the slot size allows.
+    fn poison_slot(&self, slot: NonNull<Slot>) {
+        let words = slot.cast::<usize>();
+        unsafe {
+            if self.poison_words() > POISON_MAGIC_OFFSET {
+                words.add(POISON_MAGIC_OFFSET).write(FREE_MAGIC);
+            }
+            if self.poison_words() > POISON_OWNER_OFFSET {
+                words
+                    .add(POISON_OWNER_OFFSET)
+                    .write(self as *const Self as usize);
+            }
+        }
+    }
 
-        cache.pop().map(|slot| slot.cast())
+    /// Panics if a slot about to be handed out was written to while it was free.
+    fn check_poison(&self, slot: NonNull<Slot>) {
+        if let Some((offset, found)) = self.damaged_poison(slot) {
+            let word = if offset == POISON_MAGIC_OFFSET {
+                "magic"
+            } else {
+                "owner"
+            };
+            panic!(
+                "Write after free in {}-byte slot at {:p}: {} {:#x}",
+                self.object_size, slot, word, found
+            );
+        }
+    }
+
+    /// First word stamped by `poison_slot` that no longer holds its stamp, with the value
+    /// found there.
+    fn damaged_poison(&self, slot: NonNull<Slot>) -> Option<(usize, usize)> {
+        let words = slot.cast::<usize>();
+        let stamps = [
+            (POISON_MAGIC_OFFSET, FREE_MAGIC),
+            (POISON_OWNER_OFFSET, self as *const Self as usize),
+        ];
+
+        stamps
+            .into_iter()
+            .filter(|&(offset, _)| self.poison_words() > offset)
+            .map(|(offset, stamp)| (offset, stamp, unsafe { words.add(offset).read() }))
+            .find(|&(_, stamp, found)| found != stamp)
+            .map(|(offset, _, found)| (offset, found))
     }
 
     fn create_new_slab(&self) -> Result<NonNull<Frame>, ()> {
-        let mut frame = frame_allocator().alloc_slab().ok_or(())?;
+        let pm_map = pmem_map();
+
+        let mut frame = if self.slab_order == 0 {
+            frame_allocator().alloc_slab().ok_or(())?
+        } else {
+            let block = frame_allocator().alloc(self.slab_layout()).ok_or(())?;
+            pm_map.address_to_frame_ptr(PhysicalAddress::from(block.as_ptr() as usize))
+        };
         let frame_ref = unsafe { frame.as_mut() };
-        let frame_addr = pmem_map().frame_ref_to_address(frame_ref);
+        let frame_addr = pm_map.frame_ref_to_address(frame_ref);
+
+        // tail frames point back to the head so any slot address resolves to the slab
+        for i in 1..(1 << self.slab_order) {
+            let mut tail = pm_map.address_to_frame_ptr(frame_addr + i * BASE_SIZE);
+            unsafe { tail.as_mut().convert_to_slab_tail(frame) };
+        }
 
         let start_ptr = frame_addr.as_mut_ptr::<u8>();
 
         for i in 0..(self.slots_per_slab - 1) {
+            debug_assert!(
+                (i + 1) * self.object_size <= self.slab_size(),
+                "Slot {} straddles the slab boundary",
+                i
+            );
+
             unsafe {
                 let current_slot_ptr = start_ptr.add(i * self.object_size).cast::<Slot>();
                 let next_slot_ptr = start_ptr.add((i + 1) * self.object_size).cast::<Slot>();
@@ -94,6 +394,18 @@ impl SizeClassManager {
             }
         }
 
+        if cfg!(feature = "slub-poison") {
+            for i in 0..self.slots_per_slab {
+                let slot_ptr = unsafe { start_ptr.add(i * self.object_size) };
+                self.poison_slot(unsafe { NonNull::new_unchecked(slot_ptr) }.cast());
+            }
+        }
+
+        debug_assert!(
+            self.slots_per_slab * self.object_size <= self.slab_size(),
+            "Last slot straddles the slab boundary"
+        );
+
         // explicitly set last slot `next` to None in case of stale garbage in provided frame
         unsafe {
             let last_slot_ptr = start_ptr
@@ -125,19 +437,28 @@ impl SizeClassManager {
             let slab_ref = unsafe { &mut slab_to_process.as_mut() };
             let mut slab_info = slab_ref.lock_slab_info();
 
-            while amount_to_refill > 0 {
-                match slab_info.next_slot {
-                    Some(slot_ptr) => {
-                        let slot = unsafe { slot_ptr.as_ref() };
-                        slab_info.next_slot = slot.next;
-
-                        cache.push(slot_ptr);
-                        slab_info.in_use_count += 1;
+            // detach a run of up to `amount_to_refill` slots and hand it over in one go
+            if let Some(head) = slab_info.next_slot {
+                let mut tail = head;
+                let mut taken = 1;
 
-                        amount_to_refill -= 1;
-                    }
-                    None => break,
+                while taken < amount_to_refill
+                    && let Some(next) = unsafe { tail.as_ref() }.next
+                {
+                    tail = next;
+                    taken += 1;
                 }
+
+                let tail_ref = unsafe { tail.as_mut() };
+                slab_info.next_slot = tail_ref.next.take();
+
+                cache.push_chain(head, tail, taken);
+                slab_info.in_use_count += taken;
+
+                let live_slots = self.live_slots.fetch_add(taken, Ordering::Relaxed) + taken;
+                self.recent_peak.fetch_max(live_slots, Ordering::Relaxed);
+
+                amount_to_refill -= taken;
             }
 
             if slab_info.next_slot.is_some() {
@@ -154,50 +475,266 @@ impl SizeClassManager {
 
         let slot = ptr.cast::<Slot>();
 
+        debug_assert!(
+            !self.is_slot_free(slot, hart_id),
+            "Double free of {}-byte slot at {:p}",
+            self.object_size,
+            ptr
+        );
+
+        if cfg!(feature = "slub-poison") {
+            self.poison_slot(slot);
+        }
+
         if !cache.is_full() {
             return cache.push(slot);
         }
 
-        let pm_map = pmem_map();
+        cache
+            .drain()
+            .for_each(|slot_ptr| self.return_to_slab(slot_ptr));
+    }
 
-        cache.drain().for_each(|mut slot_ptr| {
-            let mut frame_ptr =
-                pm_map.address_to_frame_ptr(PhysicalAddress::from(slot_ptr.as_ptr() as usize));
+    /// Returns `true` if `slot` is already free: parked in `hart_id`'s cache, linked into
+    /// its slab's free chain, or in a slab with no slots in use or no longer one of this
+    /// class' slabs at all. Other harts' caches are not checked.
+    ///
+    /// Walks both chains, meant for debug double-free detection only.
+    fn is_slot_free(&self, slot: NonNull<Slot>, hart_id: usize) -> bool {
+        if self.hart_cache(hart_id).contains(slot) {
+            return true;
+        }
 
-            let frame = unsafe { frame_ptr.as_mut() };
-            let mut slab_info = frame.lock_slab_info();
-            let slot = unsafe { slot_ptr.as_mut() };
+        let frame = slab_frame_for(pmem_map(), PhysicalAddress::from(slot.as_ptr() as usize));
+        let frame_ref = unsafe { frame.as_ref() };
+        // the whole slab went back to the frame allocator since
+        if frame_ref.slab_cache() != Some(NonNull::from(self)) {
+            return true;
+        }
 
-            let was_full = slab_info.in_use_count == self.slots_per_slab;
+        let slab_info = frame_ref.lock_slab_info();
+        if slab_info.in_use_count == 0 {
+            return true;
+        }
 
-            slot.next = slab_info.next_slot;
-            slab_info.next_slot = Some(slot_ptr);
-            slab_info.in_use_count -= 1;
+        let mut next = slab_info.next_slot;
+        while let Some(free_slot) = next {
+            if free_slot == slot {
+                return true;
+            }
+            next = unsafe { free_slot.as_ref() }.next;
+        }
 
-            if was_full {
-                // now partial
-                self.partial_slabs.lock().push_front(frame_ptr);
-            } else if slab_info.in_use_count == 0 {
-                // now empty
-                self.partial_slabs.lock().remove(frame_ptr);
+        false
+    }
 
-                let mut empty_slabs = self.empty_slabs.lock();
-                empty_slabs.push_front(frame_ptr);
+    /// Returns every slot cached by every hart to its slab and hands all empty slabs
+    /// back to the frame allocator.
+    ///
+    /// Other harts must not use this size class meanwhile.
+    pub fn drain_all_caches(&self) {
+        for hart_id in 0..MAX_HARTS {
+            self.flush_hart_cache(hart_id);
+        }
 
-                if empty_slabs.len() >= EMPTY_SLABS_CAP
-                    && let Some(oldest_slab) = empty_slabs.pop_back()
-                {
-                    drop(empty_slabs);
-                    frame_allocator().dealloc(oldest_slab.cast(), BASE_SIZE_LAYOUT);
-                }
+        loop {
+            let Some(slab) = self.empty_slabs.lock().pop_front() else {
+                break;
+            };
+            self.release_slab(slab);
+        }
+    }
+
+    /// Returns every slot cached by `hart_id` to its slab, updating the slabs' in-use counts.
+    ///
+    /// For a hart going offline: that hart must not use this size class meanwhile.
+    pub fn flush_hart_cache(&self, hart_id: usize) {
+        let Some(cache) = self.hart_caches.get_initialized(hart_id) else {
+            return;
+        };
+
+        cache
+            .drain_all()
+            .for_each(|slot_ptr| self.return_to_slab(slot_ptr));
+
+        debug_assert!(cache.is_empty(), "Hart {} slot cache not drained", hart_id);
+    }
+
+    /// Links a free slot back into its slab and moves the slab between the partial
+    /// and empty lists as its occupancy changes.
+    fn return_to_slab(&self, mut slot_ptr: NonNull<Slot>) {
+        let mut frame_ptr = slab_frame_for(
+            pmem_map(),
+            PhysicalAddress::from(slot_ptr.as_ptr() as usize),
+        );
+
+        let frame = unsafe { frame_ptr.as_mut() };
+        let mut slab_info = frame.lock_slab_info();
+        let slot = unsafe { slot_ptr.as_mut() };
+
+        let was_full = slab_info.in_use_count == self.slots_per_slab;
+
+        slot.next = slab_info.next_slot;
+        slab_info.next_slot = Some(slot_ptr);
+        slab_info.in_use_count -= 1;
+        self.live_slots.fetch_sub(1, Ordering::Relaxed);
+
+        if was_full {
+            // now partial
+            self.partial_slabs.lock().push_front(frame_ptr);
+        } else if slab_info.in_use_count == 0 {
+            // now empty
+            self.partial_slabs.lock().remove(frame_ptr);
+
+            let mut empty_slabs = self.empty_slabs.lock();
+            empty_slabs.push_front(frame_ptr);
+
+            if empty_slabs.len() >= EMPTY_SLABS_CAP
+                && let Some(oldest_slab) = empty_slabs.pop_back()
+            {
+                drop(empty_slabs);
+                self.release_slab(oldest_slab);
+            }
+        }
+    }
+
+    /// Hands up to `max_slabs` empty slabs back to the frame allocator, keeping enough of
+    /// them to serve the high watermark again. Returns the number of slabs released.
+    ///
+    /// The watermark follows every new peak at once but only drops to the latest one after
+    /// `SHRINK_COOLDOWN_TICKS` without reaching it, so a class that just shrank keeps its
+    /// slabs for the next burst. `now` is the current `mtime`; calls must not overlap.
+    pub fn reclaim_empty_slabs(&self, now: u64, max_slabs: usize) -> usize {
+        let live_slots = self.live_slots.load(Ordering::Relaxed);
+        let recent_peak = self
+            .recent_peak
+            .swap(live_slots, Ordering::Relaxed)
+            .max(live_slots);
+
+        let retained_slabs = {
+            let mut watermark = self.watermark.lock();
+            if recent_peak >= watermark.live_slots
+                || now.saturating_sub(watermark.tick) >= SHRINK_COOLDOWN_TICKS
+            {
+                watermark.live_slots = recent_peak;
+                watermark.tick = now;
+            }
+
+            watermark
+                .live_slots
+                .saturating_sub(live_slots)
+                .div_ceil(self.slots_per_slab)
+                .min(EMPTY_SLABS_CAP)
+        };
+
+        let mut released = 0;
+        while released < max_slabs {
+            let mut empty_slabs = self.empty_slabs.lock();
+            if empty_slabs.len() <= retained_slabs {
+                break;
             }
-        });
+            let Some(slab) = empty_slabs.pop_back() else {
+                break;
+            };
+            drop(empty_slabs);
+
+            self.release_slab(slab);
+            released += 1;
+        }
+
+        released
+    }
+
+    /// Hands an empty slab back to the frame allocator.
+    fn release_slab(&self, mut slab: NonNull<Frame>) {
+        let pm_map = pmem_map();
+        let slab_ref = unsafe { slab.as_mut() };
+        let slab_addr = pm_map.frame_ref_to_address(slab_ref);
+
+        for i in 1..(1 << self.slab_order) {
+            let mut tail = pm_map.address_to_frame_ptr(slab_addr + i * BASE_SIZE);
+            unsafe { tail.as_mut().free_to_buddy() };
+        }
+
+        // back to a plain allocated block, which is what the frame allocator expects to free
+        slab_ref.free_to_buddy();
+        slab_ref.set_state(State::Allocated);
+
+        let block = NonNull::new(slab_addr.as_mut_ptr::<u8>()).expect("Slab at null address");
+        frame_allocator().dealloc(block, self.slab_layout());
     }
 }
 
+/// Resolves any address inside a slab to the slab's head frame.
+fn slab_frame_for(pm_map: &PhysicalMemoryMap, address: PhysicalAddress) -> NonNull<Frame> {
+    let frame_ptr = pm_map.address_to_frame_ptr(address);
+    unsafe { frame_ptr.as_ref() }
+        .slab_head()
+        .unwrap_or(frame_ptr)
+}
+
 const SIZE_CLASSES: [usize; 9] = [8, 16, 32, 64, 128, 256, 512, 1024, 2048];
 const NUM_CACHES: usize = SIZE_CLASSES.len();
 
+/// `true` if objects of `object_size` bytes, laid out back to back, can each hold a `Slot`.
+const fn fits_slot(object_size: usize) -> bool {
+    object_size >= size_of::<Slot>() && object_size.is_multiple_of(align_of::<Slot>())
+}
+
+// every class threads its free list through the objects themselves
+const _: () = {
+    let mut i = 0;
+    while i < NUM_CACHES {
+        assert!(
+            fits_slot(SIZE_CLASSES[i]),
+            "Size class too small for a Slot"
+        );
+        i += 1;
+    }
+};
+
+// the largest class gets 2-frame slabs, 4 slots each, instead of 2 slots per frame
+const _: () = assert!(SizeClassManager::default_slab_order(2048) == 1);
+const _: () = assert!((BASE_SIZE << SizeClassManager::default_slab_order(2048)) / 2048 == 4);
+
+/// Where periodic reclaim resumes, so bounded rounds still get to every size class.
+pub struct ReclaimCursor(AtomicUsize);
+
+impl ReclaimCursor {
+    pub const fn new() -> Self {
+        Self(AtomicUsize::new(0))
+    }
+
+    /// Reclaims empty slabs from up to `max_classes` classes following the last one
+    /// visited, releasing at most `max_slabs` slabs in total. Returns the number released.
+    pub fn step(
+        &self,
+        classes: &[SizeClassManager],
+        now: u64,
+        max_classes: usize,
+        max_slabs: usize,
+    ) -> usize {
+        let mut released = 0;
+
+        for _ in 0..max_classes.min(classes.len()) {
+            if released == max_slabs {
+                break;
+            }
+
+            let index = self.0.fetch_add(1, Ordering::Relaxed) % classes.len();
+            released += classes[index].reclaim_empty_slabs(now, max_slabs - released);
+        }
+
+        released
+    }
+}
+
+impl Default for ReclaimCursor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 // TODO: consider Poisoning/Red-zoning
 pub struct SlubAllocator {
     size_classes: [SizeClassManager; NUM_CACHES],
@@ -207,18 +744,170 @@ impl SlubAllocator {
     pub fn new(num_harts: usize) -> Self {
         Self {
             size_classes: core::array::from_fn(|i| {
-                SizeClassManager::new(num_harts, SIZE_CLASSES[i])
+                let object_size = SIZE_CLASSES[i];
+                SizeClassManager::new(
+                    num_harts,
+                    object_size,
+                    SizeClassManager::default_slab_order(object_size),
+                )
             }),
         }
     }
 
+    /// Smallest object size served by the slub.
+    pub const fn min_alloc_size() -> usize {
+        SIZE_CLASSES[0]
+    }
+
+    /// Largest object size served by the slub, bigger requests need the frame allocator.
+    pub const fn max_slab_alloc_size() -> usize {
+        SIZE_CLASSES[NUM_CACHES - 1]
+    }
+
+    /// Drains every size class, see `SizeClassManager::drain_all_caches`.
+    pub fn drain_all_caches(&self) {
+        self.classes().for_each(SizeClassManager::drain_all_caches);
+    }
+
+    /// Flushes `hart_id`'s cache in every size class, see `SizeClassManager::flush_hart_cache`.
+    pub fn flush_hart_cache(&self, hart_id: usize) {
+        self.classes()
+            .for_each(|class| class.flush_hart_cache(hart_id));
+    }
+
+    /// Reclaims empty slabs in every size class, see `SizeClassManager::reclaim_empty_slabs`.
+    pub fn reclaim_empty_slabs(&self, now: u64) -> usize {
+        self.classes()
+            .map(|class| class.reclaim_empty_slabs(now, usize::MAX))
+            .sum()
+    }
+
+    /// One bounded round of `reclaim_empty_slabs`, see `ReclaimCursor::step`.
+    pub fn reclaim_step(
+        &self,
+        cursor: &ReclaimCursor,
+        now: u64,
+        max_classes: usize,
+        max_slabs: usize,
+    ) -> usize {
+        cursor.step(&self.size_classes, now, max_classes, max_slabs)
+    }
+
+    /// Iterates over all size class managers, smallest object size first.
+    pub fn classes(&self) -> impl Iterator<Item = &SizeClassManager> {
+        self.size_classes.iter()
+    }
+
+    /// Index into `size_classes` of the class serving `layout`, or `None` if the layout
+    /// is too big for any of them and has to go to the frame allocator.
+    ///
+    /// Single source of truth for class selection, so `alloc` and `dealloc` can't disagree.
+    pub const fn size_class_index(layout: Layout) -> Option<usize> {
+        // slots are naturally aligned to their object size, so an over-aligned
+        // layout has to be served from a class at least as large as its alignment
+        let required_size = if layout.size() > layout.align() {
+            layout.size()
+        } else {
+            layout.align()
+        };
+
+        let mut i = 0;
+        while i < NUM_CACHES {
+            if SIZE_CLASSES[i] >= required_size {
+                return Some(i);
+            }
+            i += 1;
+        }
+
+        None
+    }
+
+    // only the slub `GlobalAlloc` impl routes by size class
+    #[cfg(not(feature = "no-slub"))]
     fn find_size_class(&self, layout: Layout) -> Option<&SizeClassManager> {
-        self.size_classes
-            .iter()
-            .find(|class| class.object_size >= layout.size())
+        Self::size_class_index(layout).map(|i| &self.size_classes[i])
+    }
+
+    /// Recovers the `SizeClassManager` that owns `ptr` from its slab frame metadata,
+    /// or `None` if `ptr` lives in a page-backed (non-slab) allocation.
+    ///
+    /// Unlike `find_size_class`, this does not trust the caller-provided layout.
+    #[cfg(not(feature = "no-slub"))]
+    fn owning_size_class(&self, ptr: NonNull<u8>) -> Option<&SizeClassManager> {
+        let address = PhysicalAddress::from(ptr.as_ptr() as usize);
+        let frame_ptr = pmem_map().address_to_frame_ptr(address);
+        let cache = unsafe { frame_ptr.as_ref() }.slab_cache()?;
+
+        // SAFETY: slab frames are only ever converted by a `SizeClassManager` owned by
+        // this allocator, which lives for the rest of the kernel's lifetime
+        Some(unsafe { cache.as_ref() })
+    }
+
+    /// Size class whose slabs hold `ptr`, or `None` if `ptr` is not slub memory.
+    ///
+    /// Unlike `owning_size_class` nothing about `ptr` is trusted: it may point anywhere,
+    /// and the cache recorded in the slab frame must be one of this allocator's classes
+    /// by identity. Meant for heap corruption diagnostics.
+    pub fn class_for_ptr(&self, ptr: NonNull<u8>) -> Option<&SizeClassManager> {
+        let pm_map = pmem_map();
+        let address = PhysicalAddress::from(ptr.as_ptr() as usize);
+        if pm_map.classify(address) != RegionKind::FreeMemory {
+            return None;
+        }
+
+        let cache = unsafe { slab_frame_for(pm_map, address).as_ref() }.slab_cache()?;
+        self.classes().find(|class| ptr::eq(*class, cache.as_ptr()))
+    }
+
+    /// Every size class' listed slabs, see `SizeClassManager::validate`.
+    pub fn try_validate_heap(&self) -> Result<(), HealthIssue> {
+        self.classes().try_for_each(SizeClassManager::validate)
+    }
+
+    /// Panics with the offending slab's address if any listed slab's free chain or
+    /// in-use count is corrupt.
+    ///
+    /// Full slabs are on no list and go unchecked. Blocks on the slab list locks, so
+    /// unlike `memory::health_check` it is not meant for the panic path.
+    pub fn validate_heap(&self) {
+        if let Err(issue) = self.try_validate_heap() {
+            panic!("Heap corruption: {}", issue);
+        }
     }
 }
 
+// representative layouts (size, align) and the class index they must land in,
+// checked at compile time
+const _: () = {
+    const TABLE: [(usize, usize, Option<usize>); 10] = [
+        (0, 1, Some(0)),
+        (1, 1, Some(0)),
+        (8, 8, Some(0)),
+        (9, 1, Some(1)),
+        (24, 8, Some(2)),
+        (8, 64, Some(3)),
+        (100, 4, Some(4)),
+        (2048, 8, Some(8)),
+        (2049, 8, None),
+        (16, 4096, None),
+    ];
+
+    let mut i = 0;
+    while i < TABLE.len() {
+        let (size, align, expected) = TABLE[i];
+        let Ok(layout) = Layout::from_size_align(size, align) else {
+            panic!("Invalid layout in the size class table");
+        };
+
+        match (SlubAllocator::size_class_index(layout), expected) {
+            (Some(index), Some(expected)) => assert!(index == expected),
+            (None, None) => {}
+            _ => panic!("Size class table mismatch"),
+        }
+        i += 1;
+    }
+};
+
 pub struct KernelAllocator(OnceLock<SlubAllocator>);
 
 #[allow(clippy::new_without_default)]
@@ -226,19 +915,76 @@ impl KernelAllocator {
     pub const fn new() -> Self {
         Self(OnceLock::new())
     }
+
+    /// Starts serving allocations from `slub_allocator`, every `alloc` before returns null.
+    pub fn init(&self, slub_allocator: SlubAllocator) {
+        if self.0.set(slub_allocator).is_err() {
+            panic!("SlubAllocator already initialized");
+        }
+    }
+
+    /// The slub behind this allocator, `None` until it is set up.
+    pub fn slub(&self) -> Option<&SlubAllocator> {
+        self.0.get()
+    }
+
+    /// Returns every slot cached by `hart_id` to its slab, no-op before the slub is set up.
+    pub fn flush_hart_cache(&self, hart_id: usize) {
+        if let Some(slub_allocator) = self.0.get() {
+            slub_allocator.flush_hart_cache(hart_id);
+        }
+    }
+
+    /// Returns every slot cached by every hart to its slab, no-op before the slub is set up.
+    pub fn drain_all_caches(&self) {
+        if let Some(slub_allocator) = self.0.get() {
+            slub_allocator.drain_all_caches();
+        }
+    }
+
+    /// Reclaims empty slabs past the high watermarks, `0` before the slub is set up.
+    pub fn reclaim_empty_slabs(&self, now: u64) -> usize {
+        self.0
+            .get()
+            .map_or(0, |slub_allocator| slub_allocator.reclaim_empty_slabs(now))
+    }
 }
 
+#[cfg(not(feature = "no-slub"))]
 unsafe impl GlobalAlloc for KernelAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        if let Some(slub_allocator) = self.0.get() {
-            slub_allocator
-                .find_size_class(layout)
-                .and_then(|class_manager| class_manager.alloc())
-                .map(|non_null_ptr| non_null_ptr.as_ptr())
-                .unwrap_or(ptr::null_mut())
-        } else {
-            ptr::null_mut()
-        }
+        let Some(slub_allocator) = self.0.get() else {
+            return ptr::null_mut();
+        };
+
+        let allocation = match slub_allocator.find_size_class(layout) {
+            Some(class_manager) => class_manager.alloc(),
+            // too big for any size class: hand out whole frames without any slab bookkeeping
+            None => frame_allocator().alloc(layout),
+        };
+
+        allocation
+            .map(|non_null_ptr| non_null_ptr.as_ptr())
+            .unwrap_or(ptr::null_mut())
+    }
+
+    /// Slots are zeroed up to the requested size, frame-backed blocks in full.
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let Some(slub_allocator) = self.0.get() else {
+            return ptr::null_mut();
+        };
+
+        let allocation = match slub_allocator.find_size_class(layout) {
+            Some(class_manager) => class_manager.alloc().inspect(|slot| {
+                // SAFETY: the slot holds at least `layout.size()` bytes
+                unsafe { slot.write_bytes(0, layout.size()) }
+            }),
+            None => frame_allocator().alloc_zeroed(layout),
+        };
+
+        allocation
+            .map(|non_null_ptr| non_null_ptr.as_ptr())
+            .unwrap_or(ptr::null_mut())
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
@@ -248,21 +994,333 @@ unsafe impl GlobalAlloc for KernelAllocator {
 
         let slub_allocator = self.0.get().expect("SlubAllocator not initialized");
 
-        if let Some(class_manager) = slub_allocator.find_size_class(layout) {
-            // checked for null above
-            let non_null_ptr = unsafe { NonNull::new_unchecked(ptr) };
-            class_manager.dealloc(non_null_ptr);
-        } else {
-            // critical error
-            panic!(
-                "dealloc called with unsupported layout: size={}, align={}",
+        // checked for null above
+        let non_null_ptr = unsafe { NonNull::new_unchecked(ptr) };
+
+        // the layout is only a hint, the frame metadata is the source of truth
+        let Some(class_manager) = slub_allocator.owning_size_class(non_null_ptr) else {
+            let frame_allocator = frame_allocator();
+
+            // `alloc` only goes to the frame allocator when no size class fits,
+            // so `dealloc` must agree with it on the same layout
+            debug_assert!(
+                slub_allocator.find_size_class(layout).is_none(),
+                "dealloc routing mismatch: size={}, align={} fits a size class but points to a page-backed block",
                 layout.size(),
                 layout.align()
             );
+
+            // page-backed allocation, the buddy order is already stored in the head frame
+            debug_assert_eq!(
+                unsafe {
+                    pmem_map()
+                        .address_to_frame_ptr(PhysicalAddress::from(ptr as usize))
+                        .as_ref()
+                        .order()
+                },
+                frame_allocator.order_from_size(layout.size()),
+                "dealloc layout size={} does not match the allocated block order",
+                layout.size()
+            );
+
+            return frame_allocator.dealloc(non_null_ptr, layout);
+        };
+
+        debug_assert!(
+            slub_allocator
+                .find_size_class(layout)
+                .is_some_and(|expected| ptr::eq(expected, class_manager)),
+            "dealloc layout mismatch: size={}, align={} does not belong to the {}-byte class",
+            layout.size(),
+            layout.align(),
+            class_manager.object_size
+        );
+
+        class_manager.dealloc(non_null_ptr);
+    }
+
+    /// Keeps the slot when both sizes land in the same class, e.g. a `Vec` growing from
+    /// 40 to 48 bytes stays in its 64-byte slot. Anything else, including growth past the
+    /// largest class onto whole frames, moves the data.
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        // SAFETY: the caller guarantees `new_size` rounded up to `align` doesn't overflow
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+
+        // page-backed allocations (no class) are never resized in place
+        if let Some(class_index) = SlubAllocator::size_class_index(layout)
+            && SlubAllocator::size_class_index(new_layout) == Some(class_index)
+        {
+            return ptr;
+        }
+
+        let new_ptr = unsafe { self.alloc(new_layout) };
+        if !new_ptr.is_null() {
+            unsafe {
+                ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+                self.dealloc(ptr, layout);
+            }
+        }
+
+        new_ptr
+    }
+}
+
+// Debug configuration: bypass the slub entirely and serve every allocation
+// straight from the buddy allocator, rounded up to whole frames.
+// Useful to tell apart slub bugs from buddy bugs.
+#[cfg(feature = "no-slub")]
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if let Some(frame_allocator) = FRAME_ALLOCATOR.get() {
+            frame_allocator
+                .alloc(layout)
+                .map(|non_null_ptr| non_null_ptr.as_ptr())
+                .unwrap_or(ptr::null_mut())
+        } else {
+            ptr::null_mut()
+        }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        if let Some(frame_allocator) = FRAME_ALLOCATOR.get() {
+            frame_allocator
+                .alloc_zeroed(layout)
+                .map(|non_null_ptr| non_null_ptr.as_ptr())
+                .unwrap_or(ptr::null_mut())
+        } else {
+            ptr::null_mut()
         }
     }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let Some(non_null_ptr) = NonNull::new(ptr) else {
+            return;
+        };
+
+        frame_allocator().dealloc(non_null_ptr, layout);
+    }
 }
 
 // TODO: double check
 unsafe impl Send for SlubAllocator {}
 unsafe impl Sync for SlubAllocator {}
+
+/// Allocates `CHURNED_OBJECTS` objects from `class` and frees them all again.
+fn churn(class: &SizeClassManager) {
+    let mut objects = [NonNull::dangling(); CHURNED_OBJECTS];
+    for object in &mut objects {
+        *object = class.alloc().expect("Slab allocation failed");
+    }
+    for object in objects {
+        class.dealloc(object);
+    }
+    class.flush_hart_cache(current_hart_id());
+}
+
+const CHURNED_OBJECTS: usize = 16;
+
+/// Checks that a class whose peak is recent keeps more empty slabs than one that stayed
+/// below its peak for the whole cooldown. Needs the frame allocator only.
+pub fn check_reclaim_hysteresis() {
+    // 4 slots per slab, so the churn peaks at 4 slabs
+    let object_size = 1024;
+    let order = SizeClassManager::default_slab_order(object_size);
+    let recent = SizeClassManager::new(MAX_HARTS, object_size, order);
+    let idle = SizeClassManager::new(MAX_HARTS, object_size, order);
+
+    churn(&recent);
+    churn(&idle);
+
+    recent.reclaim_empty_slabs(0, usize::MAX);
+    idle.reclaim_empty_slabs(0, usize::MAX);
+
+    // neither class gets back to its peak, only the idle one waits out the cooldown
+    recent.reclaim_empty_slabs(1, usize::MAX);
+    idle.reclaim_empty_slabs(SHRINK_COOLDOWN_TICKS, usize::MAX);
+
+    let recent_empty = recent.empty_slabs.lock().len();
+    let idle_empty = idle.empty_slabs.lock().len();
+    assert!(
+        recent_empty > idle_empty,
+        "Recently busy class kept {} empty slabs, idle class {}",
+        recent_empty,
+        idle_empty
+    );
+    assert_eq!(idle_empty, 0, "Idle class kept slabs past the cooldown");
+
+    // the slabs point back at these managers, none may outlive them
+    recent.drain_all_caches();
+    idle.drain_all_caches();
+}
+
+/// Checks a write to a poisoned slot is found, in the magic and in the owner word. Runs
+/// without `slub-poison` too, stamping the slot by hand. Needs the frame allocator only.
+pub fn check_poison() {
+    let object_size = 64;
+    let class = SizeClassManager::new(
+        MAX_HARTS,
+        object_size,
+        SizeClassManager::default_slab_order(object_size),
+    );
+
+    let object = class.alloc().expect("Slab allocation failed");
+    let slot = object.cast::<Slot>();
+    let words = object.cast::<usize>();
+
+    class.poison_slot(slot);
+    assert_eq!(
+        class.damaged_poison(slot),
+        None,
+        "Fresh poison seen as damaged"
+    );
+
+    for offset in [POISON_MAGIC_OFFSET, POISON_OWNER_OFFSET] {
+        unsafe { words.add(offset).write(0xdead) };
+        assert_eq!(
+            class.damaged_poison(slot),
+            Some((offset, 0xdead)),
+            "Write after free to word {} missed",
+            offset
+        );
+        class.poison_slot(slot);
+    }
+
+    class.dealloc(object);
+    // the slabs point back at this manager, none may outlive it
+    class.drain_all_caches();
+}
+
+/// Checks a freed slot is seen as free while cached, back in its slab's free chain and
+/// after the slab went back to the frame allocator, so freeing it again is caught. Needs
+/// the frame allocator only.
+pub fn check_double_free() {
+    let object_size = 64;
+    let class = SizeClassManager::new(
+        MAX_HARTS,
+        object_size,
+        SizeClassManager::default_slab_order(object_size),
+    );
+    let hart_id = current_hart_id();
+
+    let object = class.alloc().expect("Slab allocation failed");
+    let slot = object.cast::<Slot>();
+    assert!(!class.is_slot_free(slot, hart_id), "Live slot seen as free");
+
+    class.dealloc(object);
+    assert!(
+        class.is_slot_free(slot, hart_id),
+        "Cached slot seen as live"
+    );
+
+    class.flush_hart_cache(hart_id);
+    assert!(
+        class.is_slot_free(slot, hart_id),
+        "Slot back in its slab seen as live"
+    );
+
+    class.reclaim_empty_slabs(0, usize::MAX);
+    class.reclaim_empty_slabs(SHRINK_COOLDOWN_TICKS, usize::MAX);
+    assert_eq!(
+        class.empty_slabs.lock().len(),
+        0,
+        "Empty slab not reclaimed"
+    );
+    assert!(
+        class.is_slot_free(slot, hart_id),
+        "Slot of a released slab seen as live"
+    );
+
+    // the slabs point back at this manager, none may outlive it
+    class.drain_all_caches();
+}
+
+/// Checks that bounded reclaim rounds never release more than their budget and still
+/// empty every idle class eventually. Needs the frame allocator only.
+pub fn check_reclaim_step() {
+    const MAX_CLASSES: usize = 2;
+    const MAX_SLABS: usize = 1;
+
+    let object_size = 1024;
+    let order = SizeClassManager::default_slab_order(object_size);
+    let classes: [SizeClassManager; 3] =
+        core::array::from_fn(|_| SizeClassManager::new(MAX_HARTS, object_size, order));
+
+    for class in &classes {
+        churn(class);
+        class.reclaim_empty_slabs(0, 0);
+    }
+    let idle_slabs = || {
+        classes
+            .iter()
+            .map(|class| class.empty_slabs.lock().len())
+            .sum::<usize>()
+    };
+    let mut left = idle_slabs();
+    assert!(
+        left > MAX_SLABS,
+        "Churn left too few empty slabs to reclaim"
+    );
+
+    let cursor = ReclaimCursor::new();
+    let mut rounds = 0;
+    while left > 0 {
+        let released = cursor.step(&classes, SHRINK_COOLDOWN_TICKS, MAX_CLASSES, MAX_SLABS);
+        assert!(released <= MAX_SLABS, "Reclaim round exceeded its budget");
+
+        left = idle_slabs();
+        rounds += 1;
+        assert!(rounds <= 64, "Idle slabs never reclaimed, {} left", left);
+    }
+
+    // the slabs point back at these managers, none may outlive them
+    classes.iter().for_each(SizeClassManager::drain_all_caches);
+}
+
+/// Checks `flush_hart_cache` links every cached slot back into its slab: the cache ends up
+/// empty and the in-use counts drop back to the live objects, also for slabs whose slots
+/// were all parked in the cache. Needs the frame allocator only.
+pub fn check_flush_hart_cache() {
+    let object_size = 1024;
+    let class = SizeClassManager::new(
+        MAX_HARTS,
+        object_size,
+        SizeClassManager::default_slab_order(object_size),
+    );
+    let hart_id = current_hart_id();
+
+    // the refill takes whole slabs into the cache, full as far as their in-use counts go
+    let live = class.alloc().expect("Slab allocation failed");
+    let freed = class.alloc().expect("Slab allocation failed");
+    class.dealloc(freed);
+    assert!(
+        class.partial_slabs.lock().is_empty(),
+        "Refill left a slab partial"
+    );
+
+    class.flush_hart_cache(hart_id);
+    assert!(
+        class.hart_cache(hart_id).is_empty(),
+        "Flushed cache kept slots"
+    );
+    assert_eq!(class.live_slots.load(Ordering::Relaxed), 1);
+    assert_eq!(
+        class.partial_slabs.lock().len(),
+        1,
+        "Live object's slab not partial"
+    );
+    let slab = slab_frame_for(pmem_map(), PhysicalAddress::from(live.as_ptr() as usize));
+    assert_eq!(unsafe { slab.as_ref() }.lock_slab_info().in_use_count, 1);
+    assert!(class.validate().is_ok(), "Flush left a slab inconsistent");
+
+    class.dealloc(live);
+    class.flush_hart_cache(hart_id);
+    assert_eq!(class.live_slots.load(Ordering::Relaxed), 0);
+    assert!(
+        class.partial_slabs.lock().is_empty(),
+        "Emptied slab still partial"
+    );
+    assert!(class.validate().is_ok(), "Flush left a slab inconsistent");
+
+    // the slabs point back at this manager, none may outlive it
+    class.drain_all_caches();
+}
@@ -0,0 +1,99 @@
+//! Allocator event tracing for external profiling tools.
+//!
+//! Compiled in with the `alloc-trace` feature. Events are only built and delivered once
+//! a sink is registered, otherwise each trace point costs a single branch.
+
+use crate::sync::OnceLock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceEvent {
+    /// A block of `2^order` frames was handed out for a `size`-byte request.
+    Alloc {
+        hart_id: usize,
+        size: usize,
+        order: u8,
+    },
+    /// A `size`-byte, order `order` block was freed.
+    Dealloc {
+        hart_id: usize,
+        size: usize,
+        order: u8,
+    },
+    /// `count` frames were moved from the free lists into the hart cache.
+    Refill { hart_id: usize, count: usize },
+    /// `count` frames were moved from the hart cache back to the free lists.
+    Drain { hart_id: usize, count: usize },
+}
+
+static TRACE_SINK: OnceLock<fn(TraceEvent)> = OnceLock::new();
+
+/// Registers the function receiving every allocator event, fails if one is already set.
+pub fn set_sink(sink: fn(TraceEvent)) -> Result<(), &'static str> {
+    TRACE_SINK
+        .set(sink)
+        .map_err(|_| "Allocator trace sink already set")
+}
+
+/// Delivers the event built by `event` to the sink, if the feature is on and a sink is set.
+///
+/// The sink runs on the allocating hart, possibly with allocator locks held,
+/// so it must not allocate.
+#[inline(always)]
+pub fn emit(event: impl FnOnce() -> TraceEvent) {
+    if !cfg!(feature = "alloc-trace") {
+        return;
+    }
+
+    if let Some(sink) = TRACE_SINK.get() {
+        sink(event());
+    }
+}
+
+/// Registers a sink recording the events of one probe allocation and checks an
+/// alloc/dealloc pair is reported in order. Takes the sink slot for good, so needs the
+/// frame allocator and no other sink.
+#[cfg(feature = "alloc-trace")]
+pub fn check_trace_sink() {
+    use crate::memory::frame::BASE_SIZE;
+    use core::alloc::Layout;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    // no other request has this size, so only the probe's events are recorded
+    const PROBE_SIZE: usize = 3 * BASE_SIZE + 1;
+    const ALLOC: usize = 1 << 8;
+    const DEALLOC: usize = 2 << 8;
+
+    static RECORDED: [AtomicUsize; 3] = [const { AtomicUsize::new(0) }; 3];
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    fn record(event: TraceEvent) {
+        let entry = match event {
+            TraceEvent::Alloc { size, order, .. } if size == PROBE_SIZE => ALLOC | order as usize,
+            TraceEvent::Dealloc { size, order, .. } if size == PROBE_SIZE => {
+                DEALLOC | order as usize
+            }
+            _ => return,
+        };
+        if let Some(slot) = RECORDED.get(NEXT.fetch_add(1, Ordering::Relaxed)) {
+            slot.store(entry, Ordering::Relaxed);
+        }
+    }
+
+    set_sink(record).expect("Allocator trace sink already set");
+    assert!(set_sink(record).is_err(), "Second sink replaced the first");
+
+    let layout = Layout::from_size_align(PROBE_SIZE, BASE_SIZE).expect("Invalid probe layout");
+    let allocator = crate::memory::frame_allocator();
+    let block = allocator
+        .alloc(layout)
+        .expect("Failed to allocate the probe block");
+    allocator.dealloc(block, layout);
+
+    let order = allocator.order_from_size(PROBE_SIZE) as usize;
+    assert_eq!(NEXT.load(Ordering::Relaxed), 2, "Wrong number of events");
+    assert_eq!(
+        RECORDED.each_ref().map(|slot| slot.load(Ordering::Relaxed)),
+        [ALLOC | order, DEALLOC | order, 0],
+        "Alloc/dealloc pair not reported in order"
+    );
+}
@@ -0,0 +1,80 @@
+//! Checksummed memory regions, for catching writes to memory that should not change.
+
+use crate::collections::hash_map::FnvHasher;
+use crate::memory::{MemoryRegion, PhysicalAddress};
+use core::hash::Hasher;
+
+/// A region checksummed when the watch is armed, `verify` panics if it changed since.
+///
+/// The region must stay readable for as long as the watch is used. Meant for debugging
+/// corruption: checksumming is a byte-by-byte walk.
+pub struct Watch {
+    region: MemoryRegion,
+    checksum: u64,
+}
+
+impl Watch {
+    pub fn new(region: MemoryRegion) -> Self {
+        Self {
+            region,
+            checksum: checksum(region),
+        }
+    }
+
+    pub fn region(&self) -> MemoryRegion {
+        self.region
+    }
+
+    /// `true` if the region still matches the checksum taken by `new` or `rearm`.
+    pub fn is_intact(&self) -> bool {
+        checksum(self.region) == self.checksum
+    }
+
+    /// Takes a fresh checksum, after the region was changed on purpose.
+    pub fn rearm(&mut self) {
+        self.checksum = checksum(self.region);
+    }
+
+    /// Panics with the region if its contents changed since the watch was armed.
+    pub fn verify(&self) {
+        assert!(
+            self.is_intact(),
+            "Watched region {}..{} was modified",
+            self.region.start(),
+            self.region.end()
+        );
+    }
+}
+
+fn checksum(region: MemoryRegion) -> u64 {
+    let start = region.start().as_ptr::<u8>();
+    let mut hasher = FnvHasher::new();
+
+    // volatile: the whole point is to observe writes the compiler doesn't know about
+    for offset in 0..region.size() {
+        hasher.write_u8(unsafe { start.add(offset).read_volatile() });
+    }
+
+    hasher.finish()
+}
+
+/// Checks that a write into a watched region is caught and that `rearm` accepts it.
+pub fn check_watch() {
+    let mut buffer = [0x5au8; 64];
+    let region = MemoryRegion::new(
+        PhysicalAddress::from(buffer.as_ptr() as usize),
+        buffer.len(),
+    );
+
+    let mut watch = Watch::new(region);
+    assert!(watch.is_intact(), "Untouched region reported as modified");
+
+    unsafe { buffer.as_mut_ptr().add(37).write_volatile(0xa5) };
+    assert!(
+        !watch.is_intact(),
+        "Write into the watched region went unnoticed"
+    );
+
+    watch.rearm();
+    watch.verify();
+}
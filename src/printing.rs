@@ -1,39 +1,61 @@
 use crate::{
-    devices::{_UART_PANIC_ADDRESS, UART_INSTANCE, uart},
+    devices::{_UART_PANIC_DEVICE, EARLY_UART_ADDRESS, UART_INSTANCE, uart_irqsave},
     drivers::uart::Uart,
 };
 use core::fmt::{self, Write};
 
+/// Returns `true` once a console is set up, i.e. `print!` won't panic.
+pub fn console_available() -> bool {
+    UART_INSTANCE.get().is_some()
+}
+
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
-    let mut guard = uart();
-
-    guard
-        .write_fmt(args)
-        .map_err(|e| {
-            drop(guard);
-            panic!("UART write error: {}", e);
-        })
-        .ok();
+    // Interrupts stay off while the UART is held: the lock is reentrant, so a handler
+    // printing on this hart would not deadlock but interleave with the interrupted line.
+    let guard = uart_irqsave();
+    let mut uart: &Uart = &guard;
+
+    if let Err(e) = uart.write_fmt(args) {
+        drop(guard);
+        panic!("UART write error: {}", e);
+    }
 }
 
+/// Unlike `_print`, never waits for the UART lock: the panicking hart may have been
+/// interrupted while another hart, or a frame below it, holds the lock forever. Losing
+/// interleaving guarantees is fine here, losing the panic message is not.
 #[doc(hidden)]
 pub fn _panic_print(args: fmt::Arguments) {
     // Try to use the fully initialized, primary UART driver.
     // This is the best-case scenario. It will succeed if the driver
-    // is initialized and not currently locked.
-    if let Some(mut guard) = UART_INSTANCE.get().and_then(|lock| lock.try_lock()) {
-        guard.write_fmt(args).ok();
+    // is initialized and not currently locked by another hart.
+    if let Some(guard) = UART_INSTANCE.get().and_then(|lock| lock.try_lock()) {
+        let mut uart: &Uart = &guard;
+        uart.write_fmt(args).ok();
         return;
     }
 
-    // Fallback: The primary driver is unavailable. Try the panic address.
-    // We can only `get()` the address. If it hasn't been set yet,
-    // it's too late to initialize it now, so we can't print.
-    if let Some(panic_addr) = _UART_PANIC_ADDRESS.get() {
-        let mut stolen_uart = Uart::new(*panic_addr);
-        stolen_uart.write_fmt(args).ok();
-    }
+    // Fallback: The primary driver is unavailable, write to the UART directly.
+    _early_print(args);
+}
+
+/// Prints without going through `UART_INSTANCE`, for messages that must get out
+/// before the console is probed or when it might be locked forever.
+///
+/// Uses a copy of the probed UART if there is one, otherwise the FDT's `/chosen` stdout
+/// device. With neither known the message is dropped, nothing is written to a guessed
+/// address.
+#[doc(hidden)]
+pub fn _early_print(args: fmt::Arguments) {
+    let Some(stolen_uart) = _UART_PANIC_DEVICE
+        .get()
+        .copied()
+        .or_else(|| EARLY_UART_ADDRESS.get().map(|&address| Uart::new(address)))
+    else {
+        return;
+    };
+    (&stolen_uart).write_fmt(args).ok();
 }
 
 #[macro_export]
@@ -0,0 +1,82 @@
+//! Secondary hart bring-up.
+//!
+//! # Handoff ordering
+//!
+//! 1. Every hart but the boot hart parks in `hart_jail` (`boot.S`) with only the
+//!    machine software interrupt enabled, polling its slot in `HART_STACKS` on each wakeup.
+//! 2. The boot hart runs `memory::init`, so `FRAME_ALLOCATOR` is set before anyone is released.
+//! 3. `boot_secondary_harts` allocates a stack for each parked hart, publishes its top
+//!    in `HART_STACKS` (release) and only then raises the hart's software interrupt.
+//! 4. The woken hart sees a non-zero slot, runs the common M-mode setup on that stack
+//!    and `mret`s into `kmain_secondary`.
+//! 5. `kmain_secondary` clears its pending software interrupt, waits on `FRAME_ALLOCATOR`
+//!    and installs its own trap stack before doing anything that can trap.
+
+use crate::cpu;
+use crate::devices::clint;
+use crate::memory::frame::BASE_SIZE;
+use crate::memory::frame_allocator;
+use crate::memory::hart_cache::MAX_HARTS;
+use core::alloc::Layout;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use fdt::Fdt;
+
+/// Same as the boot hart's stack (`STACK_SIZE` in the linker script).
+pub const HART_STACK_SIZE: usize = 64 * 1024;
+pub const TRAP_STACK_SIZE: usize = 16 * 1024;
+
+/// Stack top per hart, `0` keeps the hart parked. Read by `hart_jail` in `boot.S`.
+#[unsafe(no_mangle)]
+pub static HART_STACKS: [AtomicUsize; MAX_HARTS] = [const { AtomicUsize::new(0) }; MAX_HARTS];
+
+/// Number of `HART_STACKS` slots, harts with a higher id stay parked forever.
+#[unsafe(no_mangle)]
+pub static HART_STACKS_LEN: usize = MAX_HARTS;
+
+/// Releases every hart listed in the device tree except `boot_hart_id`.
+///
+/// Must be called after `memory::init`.
+pub fn boot_secondary_harts(fdt: &Fdt, boot_hart_id: usize) {
+    for hart_id in fdt.cpus().map(|cpu| cpu.ids().first()) {
+        if hart_id == boot_hart_id {
+            continue;
+        }
+
+        if hart_id >= MAX_HARTS {
+            log_warn!(
+                "hart {} exceeds MAX_HARTS ({}), left parked",
+                hart_id,
+                MAX_HARTS
+            );
+            continue;
+        }
+
+        let Some(stack_top) = alloc_stack(HART_STACK_SIZE) else {
+            log_error!("Failed to allocate a boot stack for hart {}", hart_id);
+            continue;
+        };
+
+        let stack_bottom = stack_top - HART_STACK_SIZE;
+        unsafe { cpu::paint_stack(stack_bottom, stack_top) };
+        cpu::register_stack(hart_id, stack_bottom, stack_top);
+
+        // the stack must be visible before the hart can observe the wakeup
+        HART_STACKS[hart_id].store(stack_top, Ordering::Release);
+        clint().trigger_software_interrupt(hart_id);
+
+        log_info!("Released hart {}", hart_id);
+    }
+}
+
+/// Allocates a trap stack for the current hart and points `sscratch` at it.
+pub fn init_trap_stack() {
+    let stack_top = alloc_stack(TRAP_STACK_SIZE).expect("Failed to allocate a trap stack");
+    cpu::set_trap_stack(stack_top);
+}
+
+/// Returns the top of a freshly allocated stack of `size` bytes.
+fn alloc_stack(size: usize) -> Option<usize> {
+    let layout = Layout::from_size_align(size, BASE_SIZE).ok()?;
+    let bottom = frame_allocator().alloc(layout)?;
+    Some(bottom.as_ptr() as usize + size)
+}
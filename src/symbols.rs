@@ -0,0 +1,214 @@
+//! Kernel symbol table, used to annotate backtraces.
+//!
+//! With the `ksyms` feature the image carries a zeroed `.ksyms` section of
+//! `KSYMS_CAPACITY` bytes. `scripts/ksyms.py` fills it in after linking with the function
+//! symbols of the final binary. Without the feature, or until the script ran, nothing
+//! resolves and backtraces show raw addresses only.
+//!
+//! Table layout, little endian: a `KSYM` magic and a `u32` entry count, the entries sorted
+//! by address (`u64` start, `u64` size, `u32` name offset, `u32` name length), then the
+//! names back to back.
+
+use crate::cpu::{self, current_hart_id};
+use core::arch::asm;
+use core::fmt;
+
+const MAGIC: [u8; 4] = *b"KSYM";
+const HEADER_SIZE: usize = 8;
+const ENTRY_SIZE: usize = 24;
+
+/// Bytes reserved for the table, `scripts/ksyms.py` refuses bigger ones.
+pub const KSYMS_CAPACITY: usize = 256 * 1024;
+
+/// Maximum frames `print_backtrace` walks before giving up on a corrupt chain.
+const MAX_BACKTRACE_DEPTH: usize = 32;
+
+#[cfg(feature = "ksyms")]
+#[used]
+#[unsafe(link_section = ".ksyms")]
+static KSYMS: [u8; KSYMS_CAPACITY] = [0; KSYMS_CAPACITY];
+
+/// A parsed view over a table in the layout described in the module docs.
+#[derive(Debug, Clone, Copy)]
+pub struct SymbolTable<'a> {
+    entries: &'a [u8],
+    strings: &'a [u8],
+}
+
+impl<'a> SymbolTable<'a> {
+    /// `None` if `bytes` does not start with a well-formed table.
+    pub fn parse(bytes: &'a [u8]) -> Option<Self> {
+        if bytes.get(..4)? != MAGIC {
+            return None;
+        }
+
+        let count = read_u32(bytes, 4)? as usize;
+        let entries_end = HEADER_SIZE.checked_add(count.checked_mul(ENTRY_SIZE)?)?;
+
+        Some(Self {
+            entries: bytes.get(HEADER_SIZE..entries_end)?,
+            strings: &bytes[entries_end..],
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len() / ENTRY_SIZE
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Name of the symbol spanning `address` and the offset of `address` into it.
+    pub fn resolve(&self, address: usize) -> Option<(&'a str, usize)> {
+        let address = address as u64;
+
+        // first entry starting past `address`, the candidate is the one before it
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if read_u64(self.entries, mid * ENTRY_SIZE)? <= address {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+
+        let entry = low.checked_sub(1)? * ENTRY_SIZE;
+        let start = read_u64(self.entries, entry)?;
+        let size = read_u64(self.entries, entry + 8)?;
+        if address - start >= size {
+            return None;
+        }
+
+        let name_offset = read_u32(self.entries, entry + 16)? as usize;
+        let name_len = read_u32(self.entries, entry + 20)? as usize;
+        let name = self
+            .strings
+            .get(name_offset..name_offset.checked_add(name_len)?)?;
+
+        Some((core::str::from_utf8(name).ok()?, (address - start) as usize))
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        bytes.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+/// The table embedded in the kernel image, if built with `ksyms` and filled in.
+pub fn kernel_table() -> Option<SymbolTable<'static>> {
+    #[cfg(feature = "ksyms")]
+    {
+        // patched after linking, the compiler must not fold the zeros it was built with
+        let bytes: &'static [u8] = core::hint::black_box(&KSYMS);
+        SymbolTable::parse(bytes)
+    }
+
+    #[cfg(not(feature = "ksyms"))]
+    None
+}
+
+/// Resolves `address` against the kernel's own symbols.
+pub fn resolve(address: usize) -> Option<(&'static str, usize)> {
+    kernel_table()?.resolve(address)
+}
+
+/// Walks the frame pointer chain of the current hart's stack, printing each return address
+/// with its symbol when known.
+///
+/// Relies on `-Cforce-frame-pointers=yes`: `s0` points just above the saved `ra` and the
+/// caller's `s0`. Stops at the edge of the registered stack, so a trap stack shows nothing.
+#[inline(never)]
+pub fn print_backtrace(mut print: impl FnMut(fmt::Arguments)) {
+    let Some((bottom, top)) = cpu::stack_bounds(current_hart_id()) else {
+        print(format_args!("Backtrace: stack bounds unknown\n"));
+        return;
+    };
+
+    let mut fp: usize;
+    unsafe {
+        asm!("mv {}, s0", out(reg) fp);
+    }
+
+    print(format_args!("Backtrace:\n"));
+    for depth in 0..MAX_BACKTRACE_DEPTH {
+        if fp < bottom + 2 * size_of::<usize>() || fp > top || !fp.is_multiple_of(8) {
+            break;
+        }
+
+        let ra = unsafe { ((fp - size_of::<usize>()) as *const usize).read() };
+        let caller_fp = unsafe { ((fp - 2 * size_of::<usize>()) as *const usize).read() };
+        if ra == 0 {
+            break;
+        }
+
+        match resolve(ra) {
+            Some((name, offset)) => print(format_args!(
+                "  #{:<2} {:#018x} {}+{:#x}\n",
+                depth, ra, name, offset
+            )),
+            None => print(format_args!("  #{:<2} {:#018x}\n", depth, ra)),
+        }
+
+        // callers live higher up the stack, anything else is a broken chain
+        if caller_fp <= fp {
+            break;
+        }
+        fp = caller_fp;
+    }
+}
+
+/// Checks lookups against a hand-built table, and against the kernel's own when present.
+pub fn check_symbol_lookup() {
+    const NAMES: &[u8] = b"foobar";
+    // (start, size, name offset, name length)
+    const ENTRIES: [(u64, u64, u32, u32); 2] = [(0x1000, 0x40, 0, 3), (0x1040, 0x20, 3, 3)];
+
+    let mut table = [0u8; HEADER_SIZE + ENTRIES.len() * ENTRY_SIZE + NAMES.len()];
+    table[..4].copy_from_slice(&MAGIC);
+    table[4..8].copy_from_slice(&(ENTRIES.len() as u32).to_le_bytes());
+    for (i, (start, size, name_offset, name_len)) in ENTRIES.into_iter().enumerate() {
+        let entry = &mut table[HEADER_SIZE + i * ENTRY_SIZE..][..ENTRY_SIZE];
+        entry[..8].copy_from_slice(&start.to_le_bytes());
+        entry[8..16].copy_from_slice(&size.to_le_bytes());
+        entry[16..20].copy_from_slice(&name_offset.to_le_bytes());
+        entry[20..24].copy_from_slice(&name_len.to_le_bytes());
+    }
+    table[HEADER_SIZE + ENTRIES.len() * ENTRY_SIZE..].copy_from_slice(NAMES);
+
+    let symbols = SymbolTable::parse(&table).expect("Failed to parse the test table");
+    assert_eq!(symbols.len(), 2);
+    assert_eq!(symbols.resolve(0x1000), Some(("foo", 0)));
+    assert_eq!(symbols.resolve(0x1010), Some(("foo", 0x10)));
+    assert_eq!(symbols.resolve(0x1050), Some(("bar", 0x10)));
+    assert_eq!(
+        symbols.resolve(0xfff),
+        None,
+        "Address below every symbol resolved"
+    );
+    assert_eq!(
+        symbols.resolve(0x1060),
+        None,
+        "Address past the last symbol resolved"
+    );
+    assert!(SymbolTable::parse(&table[..HEADER_SIZE + 4]).is_none());
+
+    if let Some(kernel) = kernel_table() {
+        let (name, _) = kernel
+            .resolve(check_symbol_lookup as fn() as usize)
+            .expect("Kernel table does not cover its own functions");
+        assert!(
+            name.contains("check_symbol_lookup"),
+            "Resolved to {} instead",
+            name
+        );
+    }
+}
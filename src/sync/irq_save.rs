@@ -0,0 +1,62 @@
+use crate::cpu::{IrqGuard, interrupts_enabled};
+use crate::sync::Spinlock;
+use core::ops::{Deref, DerefMut};
+
+/// A lock guard that also keeps interrupts disabled, returned by the `lock_irqsave` methods.
+///
+/// The lock is released before interrupts are restored, so a pending interrupt taken
+/// right after never finds the lock held by the code it interrupted.
+pub struct IrqSaveGuard<G> {
+    // fields drop in declaration order
+    guard: G,
+    _irq: IrqGuard,
+}
+
+impl<G> IrqSaveGuard<G> {
+    /// `guard` must have been acquired after `irq` disabled interrupts.
+    pub(crate) fn new(guard: G, irq: IrqGuard) -> Self {
+        Self { guard, _irq: irq }
+    }
+}
+
+impl<G: Deref> Deref for IrqSaveGuard<G> {
+    type Target = G::Target;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<G: DerefMut> DerefMut for IrqSaveGuard<G> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+/// Checks interrupts stay off while an irqsave guard is held and come back as they were.
+pub fn check_lock_irqsave() {
+    let lock = Spinlock::new(());
+    let enabled_before = interrupts_enabled();
+
+    {
+        let _outer = lock.lock_irqsave();
+        assert!(
+            !interrupts_enabled(),
+            "Interrupts enabled under an irqsave lock"
+        );
+
+        // a nested guard must not turn interrupts back on when it goes away
+        drop(IrqGuard::new());
+        assert!(
+            !interrupts_enabled(),
+            "Nested IrqGuard re-enabled interrupts"
+        );
+    }
+
+    assert_eq!(
+        interrupts_enabled(),
+        enabled_before,
+        "Interrupt state not restored after an irqsave lock"
+    );
+    assert!(lock.try_lock().is_some(), "irqsave lock not released");
+}
@@ -1,5 +1,11 @@
+pub mod irq_save;
 pub mod once_lock;
+pub mod ordered_spinlock;
+pub mod reentrant_spinlock;
 pub mod spinlock;
 
+pub use irq_save::IrqSaveGuard;
 pub use once_lock::OnceLock;
+pub use ordered_spinlock::{OrderedSpinlock, OrderedSpinlockGuard};
+pub use reentrant_spinlock::{ReentrantSpinlock, ReentrantSpinlockGuard};
 pub use spinlock::{Spinlock, SpinlockGuard};
@@ -1,9 +1,12 @@
-use Ordering::{Acquire, Relaxed};
+use Ordering::{Acquire, Relaxed, Release};
 use core::cell::UnsafeCell;
 use core::sync::atomic::{AtomicBool, Ordering};
 
 pub struct OnceLock<T> {
+    // claimed by the hart that writes the value
     initialized: AtomicBool,
+    // set once the value is actually written and visible to other harts
+    ready: AtomicBool,
     inner: UnsafeCell<Option<T>>,
 }
 
@@ -11,12 +14,24 @@ impl<T> OnceLock<T> {
     pub const fn new() -> Self {
         OnceLock {
             initialized: AtomicBool::new(false),
+            ready: AtomicBool::new(false),
             inner: UnsafeCell::new(None),
         }
     }
 
     pub fn is_initialized(&self) -> bool {
-        self.initialized.load(Acquire)
+        self.ready.load(Acquire)
+    }
+
+    /// Spins until another hart sets the value, then returns it.
+    ///
+    /// Used by secondary harts to wait for state the boot hart initializes.
+    pub fn wait(&self) -> &T {
+        while !self.is_initialized() {
+            core::hint::spin_loop();
+        }
+        // SAFETY: `ready` is only set after `inner` is written.
+        unsafe { (*self.inner.get()).as_ref().unwrap_unchecked() }
     }
 
     pub fn get(&self) -> Option<&T> {
@@ -31,6 +46,24 @@ impl<T> OnceLock<T> {
         unsafe { (*self.inner.get()).as_mut() }
     }
 
+    /// Takes the value out, leaving the lock uninitialized so it can be set again.
+    ///
+    /// Meant for test harnesses building a fresh instance per test, not for production
+    /// globals: `&mut self` can't be had on a `static` anyway, and release builds leave it
+    /// out.
+    #[cfg(debug_assertions)]
+    pub fn take(&mut self) -> Option<T> {
+        *self.initialized.get_mut() = false;
+        *self.ready.get_mut() = false;
+        self.inner.get_mut().take()
+    }
+
+    /// Drops the value, if any, and leaves the lock uninitialized. See `take`.
+    #[cfg(debug_assertions)]
+    pub fn reset(&mut self) {
+        self.take();
+    }
+
     pub fn get_or_init<F>(&self, init: F) -> &T
     where
         F: FnOnce() -> T,
@@ -49,15 +82,13 @@ impl<T> OnceLock<T> {
             // write the `Some(val)` into the `UnsafeCell`
             unsafe {
                 *self.inner.get() = Some(val);
-                (*self.inner.get()).as_ref().unwrap_unchecked()
             }
+            self.ready.store(true, Release);
+            // SAFETY: the value was just written
+            unsafe { (*self.inner.get()).as_ref().unwrap_unchecked() }
         } else {
             // losing hart spins until the value is initialized by the winner
-            while !self.is_initialized() {
-                core::hint::spin_loop();
-            }
-            // SAFETY: `initialized` is now true, so `inner` is guaranteed to be `Some(T)`.
-            unsafe { (*self.inner.get()).as_ref().unwrap_unchecked() }
+            self.wait()
         }
     }
 
@@ -73,6 +104,7 @@ impl<T> OnceLock<T> {
             unsafe {
                 *self.inner.get() = Some(value);
             }
+            self.ready.store(true, Release);
             Ok(())
         } else {
             Err(value)
@@ -88,3 +120,22 @@ impl Default for OnceLock<()> {
 
 unsafe impl<T: Send> Send for OnceLock<T> {}
 unsafe impl<T: Send + Sync> Sync for OnceLock<T> {}
+
+/// Checks a lock can be set, taken, set again and reset.
+#[cfg(debug_assertions)]
+pub fn check_take_reset() {
+    let mut lock = OnceLock::new();
+    assert_eq!(lock.take(), None, "Empty lock yielded a value");
+
+    lock.set(1).expect("Failed to set an empty lock");
+    assert_eq!(lock.set(2), Err(2), "Set lock was overwritten");
+    assert_eq!(lock.take(), Some(1));
+    assert!(!lock.is_initialized(), "Taken lock still initialized");
+
+    lock.set(3).expect("Failed to set a taken lock");
+    assert_eq!(lock.get(), Some(&3));
+
+    lock.reset();
+    assert_eq!(lock.get(), None, "Reset lock still holds a value");
+    assert_eq!(lock.get_or_init(|| 4), &4);
+}
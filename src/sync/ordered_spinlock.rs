@@ -0,0 +1,159 @@
+use crate::cpu::current_hart_id;
+use crate::memory::hart_cache::MAX_HARTS;
+use crate::sync::{Spinlock, SpinlockGuard};
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU8, Ordering};
+
+/// Number of levels in the hierarchy, `LEVEL` must be below it.
+pub const MAX_LOCK_LEVELS: usize = 8;
+
+/// Locks of each level currently held by each hart, `try_lock` can take several locks of
+/// the same level. Debug builds only.
+// only ever touched by the owning hart, relaxed is enough
+static HELD_COUNTS: [[AtomicU8; MAX_LOCK_LEVELS]; MAX_HARTS] =
+    [const { [const { AtomicU8::new(0) }; MAX_LOCK_LEVELS] }; MAX_HARTS];
+
+/// A spinlock with a place in a lock hierarchy.
+///
+/// A hart may only block on a lock whose `LEVEL` is strictly above every level it already
+/// holds, debug builds panic on an inversion before it gets the chance to deadlock.
+/// `try_lock` never blocks, so it may ignore the hierarchy.
+pub struct OrderedSpinlock<T, const LEVEL: u32> {
+    inner: Spinlock<T>,
+}
+
+impl<T, const LEVEL: u32> OrderedSpinlock<T, LEVEL> {
+    pub const fn new(data: T) -> Self {
+        const {
+            assert!(
+                (LEVEL as usize) < MAX_LOCK_LEVELS,
+                "Lock level out of range"
+            )
+        };
+
+        Self {
+            inner: Spinlock::new(data),
+        }
+    }
+
+    pub fn lock(&self) -> OrderedSpinlockGuard<'_, T, LEVEL> {
+        if cfg!(debug_assertions) {
+            let held = held_levels();
+            assert!(
+                !violates_order(held, LEVEL),
+                "Lock order inversion: taking level {} while holding levels {:#b}",
+                LEVEL,
+                held
+            );
+        }
+
+        let guard = self.inner.lock();
+        mark_held(LEVEL);
+        OrderedSpinlockGuard { guard }
+    }
+
+    pub fn try_lock(&self) -> Option<OrderedSpinlockGuard<'_, T, LEVEL>> {
+        let guard = self.inner.try_lock()?;
+        mark_held(LEVEL);
+        Some(OrderedSpinlockGuard { guard })
+    }
+
+    /// See `Spinlock::get_mut`.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+
+    /// See `Spinlock::as_ptr`.
+    pub fn as_ptr(&self) -> *mut T {
+        self.inner.as_ptr()
+    }
+}
+
+pub struct OrderedSpinlockGuard<'a, T, const LEVEL: u32> {
+    guard: SpinlockGuard<'a, T>,
+}
+
+impl<T, const LEVEL: u32> Drop for OrderedSpinlockGuard<'_, T, LEVEL> {
+    fn drop(&mut self) {
+        if cfg!(debug_assertions) {
+            let held =
+                HELD_COUNTS[current_hart_id()][LEVEL as usize].fetch_sub(1, Ordering::Relaxed);
+            debug_assert_ne!(held, 0, "Released level {} was not held", LEVEL);
+        }
+        // the inner guard unlocks right after
+    }
+}
+
+impl<T, const LEVEL: u32> Deref for OrderedSpinlockGuard<'_, T, LEVEL> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<T, const LEVEL: u32> DerefMut for OrderedSpinlockGuard<'_, T, LEVEL> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+/// `true` if blocking on a `level` lock while holding `held` levels breaks the hierarchy.
+const fn violates_order(held: u32, level: u32) -> bool {
+    // any held level at or above the requested one
+    held >> level != 0
+}
+
+/// Levels the current hart holds at least one lock of, one bit per level.
+fn held_levels() -> u32 {
+    HELD_COUNTS[current_hart_id()]
+        .iter()
+        .enumerate()
+        .filter(|(_, count)| count.load(Ordering::Relaxed) != 0)
+        .fold(0, |held, (level, _)| held | 1 << level)
+}
+
+fn mark_held(level: u32) {
+    if cfg!(debug_assertions) {
+        HELD_COUNTS[current_hart_id()][level as usize].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Checks the current hart's bookkeeping flags an inversion, keeps a level held while another
+/// lock of it is, and clears on release.
+pub fn check_lock_order() {
+    let outer = OrderedSpinlock::<(), 2>::new(());
+    let inner = OrderedSpinlock::<(), 1>::new(());
+
+    let held_before = held_levels();
+    {
+        let _outer = outer.lock();
+        assert!(
+            violates_order(held_levels(), 1),
+            "Taking a lower level lock was not flagged"
+        );
+        assert!(
+            violates_order(held_levels(), 2),
+            "Re-taking the same level was not flagged"
+        );
+        assert!(
+            !violates_order(held_levels(), 3),
+            "Taking a higher level lock was flagged"
+        );
+
+        // try_lock is exempt, it can't deadlock
+        drop(inner.try_lock().expect("Uncontended try_lock failed"));
+
+        let sibling = OrderedSpinlock::<(), 2>::new(());
+        drop(sibling.try_lock().expect("Uncontended try_lock failed"));
+        assert!(
+            violates_order(held_levels(), 2),
+            "Releasing a sibling lock cleared a level still held"
+        );
+    }
+    assert_eq!(
+        held_levels(),
+        held_before,
+        "Released levels still marked held"
+    );
+}
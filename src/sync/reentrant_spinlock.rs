@@ -0,0 +1,109 @@
+use crate::cpu::{IrqGuard, current_hart_id};
+use crate::sync::IrqSaveGuard;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, Drop};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+const NO_OWNER: usize = usize::MAX;
+
+/// A spinlock the owning hart can acquire again without deadlocking itself.
+///
+/// Meant for the print/panic path: a hart that panics while holding the UART
+/// lock must still be able to print the panic message.
+///
+/// Nested guards on the same hart alias the data, so guards only hand out `&T`:
+/// anything the holder changes must sit behind interior mutability, like MMIO registers.
+pub struct ReentrantSpinlock<T> {
+    owner: AtomicUsize,
+    // only ever touched by the owning hart
+    depth: UnsafeCell<usize>,
+    inner: UnsafeCell<T>,
+}
+
+impl<T> ReentrantSpinlock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            owner: AtomicUsize::new(NO_OWNER),
+            depth: UnsafeCell::new(0),
+            inner: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn lock(&self) -> ReentrantSpinlockGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return guard;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Disables interrupts on this hart, then locks.
+    ///
+    /// A handler on the owning hart would otherwise re-enter the lock and alias the data
+    /// the interrupted code is in the middle of using.
+    pub fn lock_irqsave(&self) -> IrqSaveGuard<ReentrantSpinlockGuard<'_, T>> {
+        let irq = IrqGuard::new();
+        IrqSaveGuard::new(self.lock(), irq)
+    }
+
+    /// Acquires the lock if it is free or already held by the current hart.
+    pub fn try_lock(&self) -> Option<ReentrantSpinlockGuard<'_, T>> {
+        let hart_id = current_hart_id();
+
+        if self.owner.load(Ordering::Relaxed) != hart_id
+            && self
+                .owner
+                .compare_exchange(NO_OWNER, hart_id, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+        {
+            return None;
+        }
+
+        // SAFETY: this hart owns the lock, nobody else touches `depth`
+        unsafe { *self.depth.get() += 1 };
+
+        Some(ReentrantSpinlockGuard { lock: self })
+    }
+
+    /// Number of guards currently held by the owning hart, `0` if unlocked.
+    pub fn depth(&self) -> usize {
+        if self.owner.load(Ordering::Relaxed) == current_hart_id() {
+            unsafe { *self.depth.get() }
+        } else {
+            0
+        }
+    }
+
+    fn unlock(&self) {
+        // SAFETY: only the owning hart holds guards
+        let depth = unsafe { &mut *self.depth.get() };
+        *depth -= 1;
+
+        if *depth == 0 {
+            self.owner.store(NO_OWNER, Ordering::Release);
+        }
+    }
+}
+
+pub struct ReentrantSpinlockGuard<'a, T> {
+    lock: &'a ReentrantSpinlock<T>,
+}
+
+impl<T> Drop for ReentrantSpinlockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.unlock();
+    }
+}
+
+impl<T> Deref for ReentrantSpinlockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.inner.get() }
+    }
+}
+
+// unsafe guarantees
+unsafe impl<T: Send> Send for ReentrantSpinlock<T> {}
+unsafe impl<T: Send> Sync for ReentrantSpinlock<T> {}
@@ -1,3 +1,5 @@
+use crate::cpu::IrqGuard;
+use crate::sync::IrqSaveGuard;
 use core::cell::UnsafeCell;
 use core::convert::From;
 use core::ops::{Deref, DerefMut, Drop};
@@ -27,6 +29,15 @@ impl<T> Spinlock<T> {
         SpinlockGuard { lock: self }
     }
 
+    /// Disables interrupts on this hart, then locks.
+    ///
+    /// Use it for locks an interrupt handler may also take, otherwise the handler can spin
+    /// on a lock the code it interrupted holds.
+    pub fn lock_irqsave(&self) -> IrqSaveGuard<SpinlockGuard<'_, T>> {
+        let irq = IrqGuard::new();
+        IrqSaveGuard::new(self.lock(), irq)
+    }
+
     pub fn try_lock(&self) -> Option<SpinlockGuard<'_, T>> {
         if self
             .locked
@@ -39,6 +50,21 @@ impl<T> Spinlock<T> {
         }
     }
 
+    /// Returns a mutable reference to the protected data without taking the lock.
+    ///
+    /// `&mut self` already proves no one else can access the lock, so the `locked`
+    /// flag is left untouched. Meant for setup code that builds the data before publishing it.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+
+    /// Returns a raw pointer to the protected data without taking the lock.
+    ///
+    /// Dereferencing it is only sound for data that is not concurrently mutated.
+    pub fn as_ptr(&self) -> *mut T {
+        self.inner.get()
+    }
+
     fn unlock(&self) {
         self.locked.store(false, Ordering::Release);
     }
@@ -77,3 +103,14 @@ impl<T> DerefMut for SpinlockGuard<'_, T> {
 // unsafe guarantees
 unsafe impl<T: Send> Send for Spinlock<T> {}
 unsafe impl<T: Send> Sync for Spinlock<T> {}
+
+/// Checks `get_mut` hands out the data without setting the `locked` flag.
+pub fn check_get_mut() {
+    let mut lock = Spinlock::new(1);
+    *lock.get_mut() += 1;
+    assert!(
+        !lock.locked.load(Ordering::Relaxed),
+        "get_mut left the lock taken"
+    );
+    assert_eq!(*lock.try_lock().expect("Lock taken after get_mut"), 2);
+}
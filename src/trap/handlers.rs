@@ -1,20 +1,162 @@
-use crate::trap::{Trap, TrapFrame};
+use crate::cpu::{self, SIE_STIE};
+use crate::crash_log::{self, TruncatingWriter};
+use crate::devices::{clint, timer};
+use crate::drivers::clint::us_to_ticks;
+use crate::trap::{Exception, Interrupt, Trap, TrapFrame};
+use core::arch::asm;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 
-#[unsafe(no_mangle)]
-pub extern "C" fn trap_handler(frame: &mut TrapFrame) -> ! {
-    println!("{}", frame);
+/// Number of breakpoints taken and stepped over, across all harts.
+pub static BREAKPOINTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Period of `default_timer_handler`, 100 Hz.
+pub const JIFFY_US: u64 = 10_000;
 
+static JIFFIES: AtomicU64 = AtomicU64::new(0);
+
+/// Jiffies counted by `default_timer_handler` since the CLINT came up.
+pub fn jiffies() -> u64 {
+    JIFFIES.load(Ordering::Relaxed)
+}
+
+/// Periodic timer callback counting jiffies, registered by the CLINT driver every `JIFFY_US`.
+///
+/// RISC-V has no separate timer acknowledge: the interrupt stays pending until `mtimecmp`
+/// is moved past `mtime`. `timer::tick` does that after the callbacks run, re-arming for
+/// the next deadline, which is always in the future since missed periods are skipped.
+pub fn default_timer_handler() {
+    JIFFIES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Handles a trap described by `frame` and returns to `alltraps`, which restores
+/// the (possibly modified) frame and `sret`s to `frame.sepc`.
+#[unsafe(no_mangle)]
+pub extern "C" fn trap_handler(frame: &mut TrapFrame) {
     match Trap::try_from(frame.scause) {
         Ok(trap) => match trap {
-            Trap::Interrupt(interrupt) => {
-                panic!("Interrupt: {:?}", interrupt);
+            Trap::Interrupt(Interrupt::SupervisorTimer) => {
+                let now = clint().mtime();
+                timer::tick(now);
             }
+            Trap::Exception(Exception::Breakpoint) => {
+                BREAKPOINTS.fetch_add(1, Ordering::Relaxed);
+                frame.sepc += instruction_len(frame.sepc);
+            }
+            Trap::Interrupt(interrupt) => fatal(frame, format_args!("Interrupt: {:?}", interrupt)),
             Trap::Exception(exception) => {
-                panic!("Exception: {:?}", exception);
+                let report = ExceptionReport {
+                    exception: &exception,
+                    frame,
+                };
+                fatal(frame, format_args!("{}", report))
             }
         },
-        Err(e) => {
-            panic!("{}", e);
+        Err(e) => fatal(frame, format_args!("{}", e)),
+    }
+}
+
+/// Panic message for an exception, with the faulting address for memory faults.
+struct ExceptionReport<'a> {
+    exception: &'a Exception,
+    frame: &'a TrapFrame,
+}
+
+impl fmt::Display for ExceptionReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Exception: {}", self.exception.describe())?;
+        if self.exception.is_memory_fault() {
+            write!(f, " accessing {:#x}", self.frame.stval)?;
         }
+        write!(f, " at pc {:#x}", self.frame.sepc)
     }
 }
+
+/// Traps the kernel can't recover from.
+fn fatal(frame: &TrapFrame, reason: core::fmt::Arguments) -> ! {
+    crash_log::set_trap_frame(frame);
+    println!("{}", frame);
+    panic!("{}", reason);
+}
+
+/// Length of the instruction at `address`: 2 bytes for compressed encodings, 4 otherwise.
+fn instruction_len(address: usize) -> usize {
+    // SAFETY: `address` is the `sepc` of a breakpoint, so it points to a fetched instruction.
+    // Only the first halfword is read, instructions are at least 2-byte aligned.
+    let low = unsafe { (address as *const u16).read_volatile() };
+    if low & 0b11 == 0b11 { 4 } else { 2 }
+}
+
+/// Checks the message of a synthetic load page fault names the exception, `stval` and
+/// `sepc`, and that a non-memory exception leaves `stval` out.
+pub fn check_exception_report() {
+    // SAFETY: `TrapFrame` is plain `usize`s, all zeros is valid
+    let mut frame: TrapFrame = unsafe { core::mem::zeroed() };
+    frame.stval = 0xdead_b000;
+    frame.sepc = 0x8020_1234;
+
+    let cases = [
+        (
+            Exception::LoadPageFault,
+            "Exception: load page fault accessing 0xdeadb000 at pc 0x80201234",
+        ),
+        (
+            Exception::IllegalInstruction,
+            "Exception: illegal instruction at pc 0x80201234",
+        ),
+    ];
+    for (exception, expected) in cases {
+        let mut buf = [0u8; 96];
+        let mut writer = TruncatingWriter::new(&mut buf);
+        let report = ExceptionReport {
+            exception: &exception,
+            frame: &frame,
+        };
+        write!(writer, "{}", report).ok();
+        assert_eq!(writer.as_str(), expected);
+    }
+}
+
+/// Takes a breakpoint on the current hart and checks execution resumes right after it,
+/// i.e. the frame was saved, `sepc` advanced and everything restored on `sret`.
+pub fn check_breakpoint_return() {
+    let taken_before = BREAKPOINTS.load(Ordering::Relaxed);
+    let mut resumed: usize = 0;
+
+    unsafe {
+        asm!("ebreak", "addi {0}, {0}, 1", inout(reg) resumed);
+    }
+
+    assert_eq!(resumed, 1, "Did not resume right after the breakpoint");
+    assert_eq!(
+        BREAKPOINTS.load(Ordering::Relaxed),
+        taken_before + 1,
+        "Breakpoint was not handled"
+    );
+}
+
+/// Takes timer interrupts on the current hart for a few jiffies of wall-clock time and
+/// checks `jiffies` advanced, i.e. `machine_trap` forwards the CLINT timer to S-mode.
+///
+/// Spins until `mtime` passes the deadline, never on `jiffies` itself. Needs the CLINT.
+pub fn check_jiffies() {
+    const SPAN_JIFFIES: u64 = 5;
+
+    let before = jiffies();
+    let deadline = clint().mtime() + us_to_ticks(SPAN_JIFFIES * JIFFY_US);
+
+    cpu::set_sie_bit(SIE_STIE);
+    cpu::enable_interrupts();
+    // the handler takes the CLINT lock too, never hold it with interrupts on
+    while cpu::with_interrupts_disabled(|| clint().mtime()) < deadline {
+        core::hint::spin_loop();
+    }
+    cpu::disable_interrupts();
+    cpu::clear_sie_bit(SIE_STIE);
+
+    assert!(
+        jiffies() > before,
+        "No timer interrupt over {} jiffies",
+        SPAN_JIFFIES
+    );
+}
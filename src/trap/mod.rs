@@ -1,5 +1,8 @@
 mod handlers;
 mod traps;
 
-pub use handlers::trap_handler;
-pub use traps::{Exception, Interrupt, Trap, TrapFrame};
+pub use handlers::{
+    JIFFY_US, check_breakpoint_return, check_exception_report, check_jiffies,
+    default_timer_handler, jiffies, trap_handler,
+};
+pub use traps::{Exception, Interrupt, Trap, TrapFrame, check_cause_decoding};
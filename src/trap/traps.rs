@@ -10,7 +10,8 @@ pub enum Interrupt {
     SupervisorSoft = 1,
     SupervisorTimer = 5,
     SupervisorExternal = 9,
-    // TODO: add more interrupts as needed
+    // not decoded: the M-mode interrupts (3, 7, 11) are never delegated to S-mode, and the
+    // counter overflow (13) and platform (16 and up) ones are never enabled
 }
 
 #[derive(Debug)]
@@ -20,14 +21,54 @@ pub enum Exception {
     InstructionFault = 1,
     IllegalInstruction = 2,
     Breakpoint = 3,
+    LoadMisaligned = 4,
     LoadFault = 5,
+    StoreMisaligned = 6,
     StoreFault = 7,
     UserEcall = 8,
     SupervisorEcall = 9,
     InstructionPageFault = 12,
     LoadPageFault = 13,
     StorePageFault = 15,
-    // TODO: add more exceptions as needed
+    // not decoded: ecalls from VS- and M-mode (10, 11) and guest page faults (20-23) need
+    // the hypervisor extension or can't reach S-mode, 14 and 16-17 are reserved,
+    // 18-19 belong to extensions the kernel doesn't enable, 24-31 and 48-63 are custom
+}
+
+impl Exception {
+    /// Human-readable description of the exception.
+    pub const fn describe(&self) -> &'static str {
+        match self {
+            Exception::InstructionMisaligned => "instruction address misaligned",
+            Exception::InstructionFault => "instruction access fault",
+            Exception::IllegalInstruction => "illegal instruction",
+            Exception::Breakpoint => "breakpoint",
+            Exception::LoadMisaligned => "load address misaligned",
+            Exception::LoadFault => "load access fault",
+            Exception::StoreMisaligned => "store/AMO address misaligned",
+            Exception::StoreFault => "store/AMO access fault",
+            Exception::UserEcall => "environment call from U-mode",
+            Exception::SupervisorEcall => "environment call from S-mode",
+            Exception::InstructionPageFault => "instruction page fault",
+            Exception::LoadPageFault => "load page fault",
+            Exception::StorePageFault => "store/AMO page fault",
+        }
+    }
+
+    /// `true` for faulting memory accesses, where `stval` holds the faulting address.
+    pub const fn is_memory_fault(&self) -> bool {
+        matches!(
+            self,
+            Exception::InstructionFault
+                | Exception::LoadMisaligned
+                | Exception::LoadFault
+                | Exception::StoreMisaligned
+                | Exception::StoreFault
+                | Exception::InstructionPageFault
+                | Exception::LoadPageFault
+                | Exception::StorePageFault
+        )
+    }
 }
 
 impl TryFrom<usize> for Trap {
@@ -51,7 +92,9 @@ impl TryFrom<usize> for Trap {
                 1 => Exception::InstructionFault,
                 2 => Exception::IllegalInstruction,
                 3 => Exception::Breakpoint,
+                4 => Exception::LoadMisaligned,
                 5 => Exception::LoadFault,
+                6 => Exception::StoreMisaligned,
                 7 => Exception::StoreFault,
                 8 => Exception::UserEcall,
                 9 => Exception::SupervisorEcall,
@@ -65,16 +108,59 @@ impl TryFrom<usize> for Trap {
     }
 }
 
+/// Checks the misaligned access codes decode to their exceptions, and that reserved and
+/// undelegated codes are still rejected.
+pub fn check_cause_decoding() {
+    const INTERRUPT: usize = 1 << (usize::BITS - 1);
+
+    assert!(matches!(
+        Trap::try_from(4),
+        Ok(Trap::Exception(Exception::LoadMisaligned))
+    ));
+    assert!(matches!(
+        Trap::try_from(6),
+        Ok(Trap::Exception(Exception::StoreMisaligned))
+    ));
+    assert!(
+        Exception::LoadMisaligned.is_memory_fault() && Exception::StoreMisaligned.is_memory_fault(),
+        "Misaligned accesses must report stval"
+    );
+
+    for code in [10, 11, 14, 16, 24] {
+        assert!(
+            Trap::try_from(code).is_err(),
+            "Unsupported exception code {} decoded",
+            code
+        );
+    }
+    assert!(
+        Trap::try_from(INTERRUPT | 7).is_err(),
+        "M-mode timer interrupt decoded"
+    );
+}
+
+/// Saved by `save_context` in `trap.S`, field offsets must match its `TRAP_FRAME_*` slots.
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct TrapFrame {
-    pub gprs: [usize; 32], // 256
-    pub sstatus: usize,    // 264
-    pub sepc: usize,       // 272
-    pub stval: usize,      // 280
-    pub scause: usize,     // 288
+    pub gprs: [usize; 32], // offset 0
+    pub sstatus: usize,    // offset 256
+    pub sepc: usize,       // offset 264
+    pub stval: usize,      // offset 272
+    pub scause: usize,     // offset 280
+    /// `sscratch` to restore when returning from this trap, managed by `alltraps`
+    pub sscratch: usize, // offset 288
+    _reserved: usize,      // offset 296, keeps the frame 16-byte aligned
 }
 
+const _: () = assert!(core::mem::offset_of!(TrapFrame, sstatus) == 32 * 8);
+const _: () = assert!(core::mem::offset_of!(TrapFrame, sepc) == 33 * 8);
+const _: () = assert!(core::mem::offset_of!(TrapFrame, stval) == 34 * 8);
+const _: () = assert!(core::mem::offset_of!(TrapFrame, scause) == 35 * 8);
+const _: () = assert!(core::mem::offset_of!(TrapFrame, sscratch) == 36 * 8);
+// `TRAP_FRAME_SIZE`, what `save_context` reserves on the trap stack
+const _: () = assert!(size_of::<TrapFrame>() == 38 * 8);
+
 impl core::fmt::Display for TrapFrame {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         writeln!(f, "--- TrapFrame ---")?;